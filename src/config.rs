@@ -1,5 +1,35 @@
 use serde::Deserialize;
 
+use crate::storage::S3Config;
+use crate::store::UPLOAD_LIFETIME_SECS;
+use crate::yivi::IrmaConfig;
+
+/// Where upload payloads are kept; see `crate::storage`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// UUID-named files under `data_dir`.
+    #[default]
+    Local,
+    /// An S3-compatible bucket, configured by the `[s3]` table.
+    S3,
+}
+
+/// What finalize does when the sealed header has no recipient for an
+/// address the upload notifies, i.e. someone who would get a link to a
+/// file they cannot decrypt.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecipientCheck {
+    /// Don't compare.
+    Off,
+    /// Finalize and notify anyway, but report the addresses to the client.
+    #[default]
+    Flag,
+    /// Refuse to finalize and drop the upload.
+    Reject,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RawCryptifyConfig {
     server_url: String,
@@ -15,13 +45,22 @@ pub struct RawCryptifyConfig {
     metrics_scan_interval_secs: Option<u64>,
     chunk_size: Option<u64>,
     session_ttl_secs: Option<u64>,
+    retention_scan_interval_secs: Option<u64>,
+    max_upload_lifetime_secs: Option<u64>,
+    api_key_max_upload_lifetime_secs: Option<u64>,
     staging_mode: Option<bool>,
     metrics_token: Option<String>,
     usage_db: Option<String>,
     email_attribute: Option<String>,
+    storage_backend: Option<StorageBackend>,
+    s3: Option<S3Config>,
+    recipient_check: Option<RecipientCheck>,
+    irma: Option<IrmaConfig>,
+    yivi_downloads: Option<bool>,
+    download_link_secret: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(from = "RawCryptifyConfig")]
 pub struct CryptifyConfig {
     server_url: String,
@@ -37,22 +76,81 @@ pub struct CryptifyConfig {
     metrics_scan_interval_secs: u64,
     chunk_size: u64,
     session_ttl_secs: u64,
+    /// How often the retention reaper scans for finalized uploads whose
+    /// expiry has passed and deletes their payload from `data_dir`.
+    retention_scan_interval_secs: u64,
+    /// Longest lifetime a sender may choose for an upload (`expiresIn` at
+    /// init). Defaults to the 14-day standard lifetime.
+    max_upload_lifetime_secs: u64,
+    /// The same bound for uploads by a validated API-key tenant. Defaults to
+    /// `max_upload_lifetime_secs`.
+    api_key_max_upload_lifetime_secs: u64,
     staging_mode: bool,
     metrics_token: Option<String>,
     /// Filesystem path to the SQLite database backing the rolling-quota
-    /// usage state. When set, per-sender usage survives process restarts
-    /// (the in-memory map in `Store` is only a cache). `None` keeps usage
-    /// entirely in memory, as it was before persistence was added.
+    /// usage state, the in-flight upload sessions and the retention records
+    /// of finalized uploads. When set, all three survive process restarts
+    /// (the in-memory maps in `Store` are only a cache). `None` keeps them
+    /// entirely in memory; uploads in progress are then lost on restart and
+    /// the retention reaper falls back to file modification times.
     usage_db: Option<String>,
     /// Attribute type carrying the sender's email in the signing identity
     /// (postguard#236). Finalize requires this attribute to be present.
     /// Test environments override it with a test-scheme type (e.g.
     /// `irma-demo.sidn-pbdf.email.email`); production keeps the default.
     email_attribute: String,
+    storage_backend: StorageBackend,
+    /// Bucket and credentials for `storage_backend = "s3"`. Checked to be
+    /// present at startup whenever that backend is selected.
+    s3: Option<S3Config>,
+    /// How finalize treats notified addresses the payload was not sealed
+    /// for. Defaults to `flag`.
+    recipient_check: RecipientCheck,
+    /// IRMA server that runs the Yivi sessions, from the `[irma]` table.
+    irma: Option<IrmaConfig>,
+    /// When set, a recipient must disclose their email with Yivi before
+    /// downloading, and only an address the upload was sent to may
+    /// download. Requires `[irma]`.
+    yivi_downloads: bool,
+    /// Key that signs the per-recipient download links; see `crate::links`.
+    /// When set, a download needs the token from a link mailed to a
+    /// recipient (or, with `yivi_downloads`, a disclosure). At least
+    /// [`MIN_LINK_SECRET_LEN`] bytes.
+    download_link_secret: Option<String>,
 }
 
+/// Shortest `download_link_secret` accepted, in bytes.
+pub const MIN_LINK_SECRET_LEN: usize = 32;
+
 impl From<RawCryptifyConfig> for CryptifyConfig {
     fn from(config: RawCryptifyConfig) -> Self {
+        let storage_backend = config.storage_backend.unwrap_or_default();
+        let max_upload_lifetime_secs = config
+            .max_upload_lifetime_secs
+            .unwrap_or(UPLOAD_LIFETIME_SECS as u64);
+        if storage_backend == StorageBackend::S3 && config.s3.is_none() {
+            log::error!("storage_backend = \"s3\" requires an [s3] table");
+            panic!("storage_backend = \"s3\" requires an [s3] table")
+        }
+        let yivi_downloads = config.yivi_downloads.unwrap_or(false);
+        if yivi_downloads && config.irma.is_none() {
+            log::error!("yivi_downloads requires an [irma] table");
+            panic!("yivi_downloads requires an [irma] table")
+        }
+        if config
+            .download_link_secret
+            .as_ref()
+            .is_some_and(|secret| secret.len() < MIN_LINK_SECRET_LEN)
+        {
+            log::error!(
+                "download_link_secret must be at least {} bytes",
+                MIN_LINK_SECRET_LEN
+            );
+            panic!(
+                "download_link_secret must be at least {} bytes",
+                MIN_LINK_SECRET_LEN
+            )
+        }
         CryptifyConfig {
             server_url: config.server_url,
             data_dir: config.data_dir,
@@ -70,12 +168,23 @@ impl From<RawCryptifyConfig> for CryptifyConfig {
             metrics_scan_interval_secs: config.metrics_scan_interval_secs.unwrap_or(60),
             chunk_size: config.chunk_size.unwrap_or(5_000_000),
             session_ttl_secs: config.session_ttl_secs.unwrap_or(3600),
+            retention_scan_interval_secs: config.retention_scan_interval_secs.unwrap_or(600),
+            max_upload_lifetime_secs,
+            api_key_max_upload_lifetime_secs: config
+                .api_key_max_upload_lifetime_secs
+                .unwrap_or(max_upload_lifetime_secs),
             staging_mode: config.staging_mode.unwrap_or(false),
             metrics_token: config.metrics_token,
             usage_db: config.usage_db,
             email_attribute: config
                 .email_attribute
                 .unwrap_or_else(|| "pbdf.sidn-pbdf.email.email".to_owned()),
+            storage_backend,
+            s3: config.s3,
+            recipient_check: config.recipient_check.unwrap_or_default(),
+            irma: config.irma,
+            yivi_downloads,
+            download_link_secret: config.download_link_secret,
         }
     }
 }
@@ -133,6 +242,20 @@ impl CryptifyConfig {
         self.session_ttl_secs
     }
 
+    pub fn retention_scan_interval_secs(&self) -> u64 {
+        self.retention_scan_interval_secs
+    }
+
+    /// The longest upload lifetime a sender may choose; `api_key` selects
+    /// the bound for validated API-key tenants.
+    pub fn max_upload_lifetime_secs(&self, api_key: bool) -> u64 {
+        if api_key {
+            self.api_key_max_upload_lifetime_secs
+        } else {
+            self.max_upload_lifetime_secs
+        }
+    }
+
     pub fn staging_mode(&self) -> bool {
         self.staging_mode
     }
@@ -156,6 +279,30 @@ impl CryptifyConfig {
         &self.email_attribute
     }
 
+    pub fn storage_backend(&self) -> StorageBackend {
+        self.storage_backend
+    }
+
+    pub fn s3(&self) -> Option<&S3Config> {
+        self.s3.as_ref()
+    }
+
+    pub fn recipient_check(&self) -> RecipientCheck {
+        self.recipient_check
+    }
+
+    pub fn irma(&self) -> Option<&IrmaConfig> {
+        self.irma.as_ref()
+    }
+
+    pub fn yivi_downloads(&self) -> bool {
+        self.yivi_downloads
+    }
+
+    pub fn download_link_secret(&self) -> Option<&[u8]> {
+        self.download_link_secret.as_deref().map(str::as_bytes)
+    }
+
     #[cfg(test)]
     pub(crate) fn for_test(server_url: &str, staging_mode: bool) -> Self {
         CryptifyConfig {
@@ -172,12 +319,27 @@ impl CryptifyConfig {
             metrics_scan_interval_secs: 60,
             chunk_size: 5_000_000,
             session_ttl_secs: 3600,
+            retention_scan_interval_secs: 600,
+            max_upload_lifetime_secs: UPLOAD_LIFETIME_SECS as u64,
+            api_key_max_upload_lifetime_secs: UPLOAD_LIFETIME_SECS as u64,
             staging_mode,
             metrics_token: None,
             usage_db: None,
             email_attribute: "pbdf.sidn-pbdf.email.email".to_owned(),
+            storage_backend: StorageBackend::Local,
+            s3: None,
+            recipient_check: RecipientCheck::Flag,
+            irma: None,
+            yivi_downloads: false,
+            download_link_secret: None,
         }
     }
+
+    #[cfg(test)]
+    pub(crate) fn with_download_link_secret(mut self, secret: &str) -> Self {
+        self.download_link_secret = Some(secret.to_owned());
+        self
+    }
 }
 
 #[cfg(test)]
@@ -228,4 +390,92 @@ mod tests {
         let config: CryptifyConfig = Figment::from(Serialized::defaults(raw)).extract().unwrap();
         assert_eq!(config.email_attribute(), "irma-demo.sidn-pbdf.email.email");
     }
+
+    #[test]
+    fn upload_lifetime_bounds_default_to_the_standard_lifetime() {
+        let config: CryptifyConfig = Figment::from(Serialized::defaults(base_config()))
+            .extract()
+            .unwrap();
+        assert_eq!(config.max_upload_lifetime_secs(false), 1_209_600);
+        assert_eq!(config.max_upload_lifetime_secs(true), 1_209_600);
+
+        let mut raw = base_config();
+        raw["max_upload_lifetime_secs"] = serde_json::json!(86_400);
+        let config: CryptifyConfig = Figment::from(Serialized::defaults(raw.clone()))
+            .extract()
+            .unwrap();
+        assert_eq!(
+            config.max_upload_lifetime_secs(true),
+            86_400,
+            "follows the anonymous bound"
+        );
+        raw["api_key_max_upload_lifetime_secs"] = serde_json::json!(7_776_000);
+        let config: CryptifyConfig = Figment::from(Serialized::defaults(raw)).extract().unwrap();
+        assert_eq!(config.max_upload_lifetime_secs(false), 86_400);
+        assert_eq!(config.max_upload_lifetime_secs(true), 7_776_000);
+    }
+
+    #[test]
+    fn recipient_check_defaults_to_flag() {
+        let config: CryptifyConfig = Figment::from(Serialized::defaults(base_config()))
+            .extract()
+            .unwrap();
+        assert_eq!(config.recipient_check(), RecipientCheck::Flag);
+
+        let mut raw = base_config();
+        raw["recipient_check"] = serde_json::json!("reject");
+        let config: CryptifyConfig = Figment::from(Serialized::defaults(raw)).extract().unwrap();
+        assert_eq!(config.recipient_check(), RecipientCheck::Reject);
+    }
+
+    #[test]
+    #[should_panic(expected = "requires an [irma] table")]
+    fn yivi_downloads_without_irma_table_is_rejected() {
+        let mut raw = base_config();
+        raw["yivi_downloads"] = serde_json::json!(true);
+        let _: CryptifyConfig = Figment::from(Serialized::defaults(raw)).extract().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "download_link_secret must be at least 32 bytes")]
+    fn short_download_link_secret_is_rejected() {
+        let mut raw = base_config();
+        raw["download_link_secret"] = serde_json::json!("hunter2");
+        let _: CryptifyConfig = Figment::from(Serialized::defaults(raw)).extract().unwrap();
+    }
+
+    #[test]
+    fn storage_backend_defaults_to_local() {
+        let config: CryptifyConfig = Figment::from(Serialized::defaults(base_config()))
+            .extract()
+            .unwrap();
+        assert_eq!(config.storage_backend(), StorageBackend::Local);
+        assert!(config.s3().is_none());
+    }
+
+    #[test]
+    fn s3_backend_reads_its_table() {
+        let mut raw = base_config();
+        raw["storage_backend"] = serde_json::json!("s3");
+        raw["s3"] = serde_json::json!({
+            "endpoint": "http://minio:9000",
+            "bucket": "cryptify",
+            "access_key": "key",
+            "secret_key": "secret",
+        });
+        let config: CryptifyConfig = Figment::from(Serialized::defaults(raw)).extract().unwrap();
+        assert_eq!(config.storage_backend(), StorageBackend::S3);
+        let s3 = config.s3().unwrap();
+        assert_eq!(s3.bucket, "cryptify");
+        assert_eq!(s3.region, "us-east-1");
+        assert_eq!(s3.prefix, "");
+    }
+
+    #[test]
+    #[should_panic(expected = "requires an [s3] table")]
+    fn s3_backend_without_table_is_rejected() {
+        let mut raw = base_config();
+        raw["storage_backend"] = serde_json::json!("s3");
+        let _: CryptifyConfig = Figment::from(Serialized::defaults(raw)).extract().unwrap();
+    }
 }
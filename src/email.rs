@@ -1,5 +1,5 @@
 use crate::config::CryptifyConfig;
-use crate::store::FileState;
+use crate::store::{FileState, Mailing, Receipt};
 
 use askama::Template;
 
@@ -10,7 +10,7 @@ use lettre::{
         header::{ContentType, Header, HeaderName, HeaderValue},
         Attachment, Mailbox, MultiPart, SinglePart,
     },
-    transport::smtp::authentication::Credentials,
+    transport::smtp::{authentication::Credentials, SmtpTransportBuilder},
     Message, SmtpTransport, Transport,
 };
 
@@ -117,7 +117,7 @@ const CHECK_PNG: &[u8] = include_bytes!("../templates/email/check.png");
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Language {
     #[serde(rename = "EN")]
     En,
@@ -135,6 +135,12 @@ struct MailStrings<'a> {
     subject_confirm: &'a str,
     confirm: &'a str,
     files_from: &'a str,
+    revoked_str: &'a str,
+    revoked_body: &'a str,
+    extended_str: &'a str,
+    extended_body: &'a str,
+    downloaded_str: &'a str,
+    downloaded_body: &'a str,
 }
 
 const NL_STRINGS: MailStrings = MailStrings {
@@ -147,6 +153,12 @@ const NL_STRINGS: MailStrings = MailStrings {
     subject_confirm: "Je bestanden zijn verstuurd via PostGuard",
     confirm: "Je kunt nog steeds bij je bestanden",
     files_from: "De bestanden komen van",
+    revoked_str: "heeft de gestuurde bestanden ingetrokken",
+    revoked_body: "De bestanden zijn niet meer te downloaden.",
+    extended_str: "heeft de downloadperiode van je bestanden verlengd",
+    extended_body: "Je kunt de bestanden nu downloaden tot",
+    downloaded_str: "heeft je bestanden gedownload",
+    downloaded_body: "Gedownload op",
 };
 
 const EN_STRINGS: MailStrings = MailStrings {
@@ -159,6 +171,12 @@ const EN_STRINGS: MailStrings = MailStrings {
     subject_confirm: "Your files have been sent via PostGuard",
     confirm: "You can still access your files",
     files_from: "The files come from",
+    revoked_str: "withdrew the files sent to you",
+    revoked_body: "The files can no longer be downloaded.",
+    extended_str: "extended the download period of your files",
+    extended_body: "You can now download the files until",
+    downloaded_str: "downloaded your files",
+    downloaded_body: "Downloaded on",
 };
 
 #[derive(Template)]
@@ -204,6 +222,35 @@ struct EmailTextTemplate<'a> {
     sender_attributes: &'a [(String, String)],
 }
 
+/// A short notice about an earlier upload. Has a download button only when
+/// `url` is not empty: signed links stop working at the old expiry, so an
+/// extension hands out new ones.
+#[derive(Template)]
+#[template(path = "email/notice.html")]
+struct NoticeTemplate<'a> {
+    header: &'a str,
+    subheader: &'a str,
+    body: &'a str,
+    download_str: &'a str,
+    url: &'a str,
+    files_from: &'a str,
+    sender_email: &'a str,
+    sender_attributes: &'a [(String, String)],
+}
+
+#[derive(Template)]
+#[template(path = "email/notice.txt", escape = "none")]
+struct NoticeTextTemplate<'a> {
+    header: &'a str,
+    subheader: &'a str,
+    body: &'a str,
+    download_str: &'a str,
+    url: &'a str,
+    files_from: &'a str,
+    sender_email: &'a str,
+    sender_attributes: &'a [(String, String)],
+}
+
 /// Assemble the MIME body: a `multipart/alternative` whose HTML branch is
 /// itself a `multipart/related` carrying the HTML part plus the PostGuard
 /// logo as an inline image referenced via `cid:pg-logo`. This shape avoids
@@ -231,7 +278,11 @@ fn build_body(html: String, text: String) -> Result<MultiPart, Box<dyn std::erro
 /// twice. An empty disclosed value is treated as not disclosed. When no
 /// name is available the display falls back to "PostGuard".
 fn sender_display(state: &FileState) -> (String, Vec<(String, String)>) {
-    let mut attrs = state.sender_attributes.clone();
+    sender_display_of(&state.sender_attributes)
+}
+
+fn sender_display_of(sender_attributes: &[(String, String)]) -> (String, Vec<(String, String)>) {
+    let mut attrs = sender_attributes.to_vec();
 
     // 1. Prefer gemeente.personalData.fullname (Dutch municipality credential).
     let name = attrs
@@ -293,7 +344,8 @@ pub struct RenderedEmail {
 
 /// Build the `/download?uuid=…&recipient=…` link cryptify embeds in the
 /// notification body. Extracted from `send_email` so the preview endpoint
-/// constructs URLs the same way and they cannot drift.
+/// constructs URLs the same way and they cannot drift. With a
+/// `download_link_secret` the link also carries a `token` for `recipient`.
 fn build_download_url(
     config: &CryptifyConfig,
     uuid: &str,
@@ -304,9 +356,31 @@ fn build_download_url(
     url.query_pairs_mut()
         .append_pair("uuid", uuid)
         .append_pair("recipient", recipient);
+    if let Some(secret) = config.download_link_secret() {
+        url.query_pairs_mut()
+            .append_pair("token", &crate::links::sign(secret, uuid, recipient));
+    }
     Ok(url.to_string())
 }
 
+/// The signed download link of each recipient of an upload that does not
+/// notify them, for the uploader to deliver instead. Empty without a
+/// `download_link_secret`, as an unsigned link is the same for anyone.
+pub fn unmailed_download_links(
+    config: &CryptifyConfig,
+    state: &FileState,
+    uuid: &str,
+) -> Result<Vec<String>, url::ParseError> {
+    if state.notify_recipients || config.download_link_secret().is_none() {
+        return Ok(Vec::new());
+    }
+    state
+        .recipients
+        .iter()
+        .map(|recipient| build_download_url(config, uuid, recipient.email.as_ref()))
+        .collect()
+}
+
 /// Render the per-recipient notification email (subject + HTML + text)
 /// for a single recipient on an upload. Pure: no SMTP, no IO beyond URL
 /// parsing.
@@ -351,6 +425,130 @@ pub fn render_confirmation_email(
     }))
 }
 
+/// What a notice about an earlier upload tells its recipients.
+#[derive(Clone, Debug)]
+pub enum Notice {
+    /// The sender revoked the upload.
+    Revoked,
+    /// The sender moved the expiry of the upload to `expires`.
+    Extended { expires: i64 },
+}
+
+/// Render `notice` for one recipient of upload `uuid`.
+pub fn render_notice_email(
+    notice: &Notice,
+    mailing: &Mailing,
+    config: &CryptifyConfig,
+    recipient_email: &str,
+    uuid: &str,
+) -> RenderedEmail {
+    let strings = match mailing.mail_lang {
+        Language::En => EN_STRINGS,
+        Language::Nl => NL_STRINGS,
+    };
+    let (display, attrs) = sender_display_of(&mailing.sender_attributes);
+    let (subheader, body) = match notice {
+        Notice::Revoked => (strings.revoked_str, strings.revoked_body.to_owned()),
+        Notice::Extended { expires } => (
+            strings.extended_str,
+            format!(
+                "{} {}.",
+                strings.extended_body,
+                format_date(*expires, &mailing.mail_lang).trim()
+            ),
+        ),
+    };
+    let url = match notice {
+        Notice::Extended { .. } if config.download_link_secret().is_some() => {
+            build_download_url(config, uuid, recipient_email).unwrap_or_default()
+        }
+        _ => String::new(),
+    };
+
+    let html = NoticeTemplate {
+        header: &display,
+        subheader,
+        body: &body,
+        download_str: strings.download_str,
+        url: &url,
+        files_from: strings.files_from,
+        sender_email: &display,
+        sender_attributes: &attrs,
+    };
+    let text = NoticeTextTemplate {
+        header: &display,
+        subheader,
+        body: &body,
+        download_str: strings.download_str,
+        url: &url,
+        files_from: strings.files_from,
+        sender_email: &display,
+        sender_attributes: &attrs,
+    };
+    let subject = SubjectTemplate {
+        subject_str: subheader,
+        sender: &display,
+    };
+    RenderedEmail {
+        recipient: recipient_email.to_owned(),
+        subject: subject.to_string(),
+        from: config.email_from().to_string(),
+        reply_to: mailing.sender.clone(),
+        html: html.to_string(),
+        text: text.to_string(),
+    }
+}
+
+/// Render the receipt telling the sender that a recipient downloaded their
+/// files. `None` when no sender address is known.
+pub fn render_receipt_email(receipt: &Receipt, config: &CryptifyConfig) -> Option<RenderedEmail> {
+    let sender_email = receipt.mailing.sender.clone()?;
+    let lang = &receipt.mailing.mail_lang;
+    let strings = match lang {
+        Language::En => EN_STRINGS,
+        Language::Nl => NL_STRINGS,
+    };
+    let body = format!(
+        "{} {}.",
+        strings.downloaded_body,
+        format_date(receipt.at, lang).trim()
+    );
+
+    // The sender needs no reminder of who they are: no footer.
+    let html = NoticeTemplate {
+        header: &receipt.recipient,
+        subheader: strings.downloaded_str,
+        body: &body,
+        download_str: strings.download_str,
+        url: "",
+        files_from: strings.files_from,
+        sender_email: "",
+        sender_attributes: &[],
+    };
+    let text = NoticeTextTemplate {
+        header: &receipt.recipient,
+        subheader: strings.downloaded_str,
+        body: &body,
+        download_str: strings.download_str,
+        url: "",
+        files_from: strings.files_from,
+        sender_email: "",
+        sender_attributes: &[],
+    };
+    let subject = SubjectTemplate {
+        subject_str: strings.downloaded_str,
+        sender: &receipt.recipient,
+    };
+    Some(RenderedEmail {
+        recipient: sender_email,
+        subject: subject.to_string(),
+        from: config.email_from().to_string(),
+        reply_to: None,
+        html: html.to_string(),
+        text: text.to_string(),
+    })
+}
+
 fn email_templates(state: &FileState, url: &str) -> (String, String, String) {
     let strings = match state.mail_lang {
         Language::En => EN_STRINGS,
@@ -457,27 +655,7 @@ pub async fn send_email(
         return Ok(staging_log_email(config, state, uuid));
     }
 
-    // setup SMTP connection
-    log::info!(
-        "Setting up SMTP: host={}, port={}, tls={}, credentials={}",
-        config.smtp_url(),
-        config.smtp_port(),
-        config.smtp_tls(),
-        config.smtp_username().is_some()
-    );
-    let mut mailer_builder = if config.smtp_tls() {
-        SmtpTransport::starttls_relay(config.smtp_url())?.port(config.smtp_port())
-    } else {
-        SmtpTransport::builder_dangerous(config.smtp_url()).port(config.smtp_port())
-    };
-
-    mailer_builder = mailer_builder.timeout(Some(std::time::Duration::from_secs(10)));
-
-    // add credentials, if present
-    if let (Some(username), Some(password)) = (config.smtp_username(), config.smtp_password()) {
-        let credentials = Credentials::new(username.to_owned(), password.to_owned());
-        mailer_builder = mailer_builder.credentials(credentials);
-    }
+    let mailer_builder = smtp_transport(config)?;
 
     if state.notify_recipients {
         for recipient in state.recipients.iter() {
@@ -557,6 +735,129 @@ pub async fn send_email(
     Ok("Email successfully sent".to_owned())
 }
 
+/// Build the SMTP transport from the configured relay and credentials.
+fn smtp_transport(
+    config: &CryptifyConfig,
+) -> Result<SmtpTransportBuilder, Box<dyn std::error::Error>> {
+    log::info!(
+        "Setting up SMTP: host={}, port={}, tls={}, credentials={}",
+        config.smtp_url(),
+        config.smtp_port(),
+        config.smtp_tls(),
+        config.smtp_username().is_some()
+    );
+    let mut mailer_builder = if config.smtp_tls() {
+        SmtpTransport::starttls_relay(config.smtp_url())?.port(config.smtp_port())
+    } else {
+        SmtpTransport::builder_dangerous(config.smtp_url()).port(config.smtp_port())
+    };
+
+    mailer_builder = mailer_builder.timeout(Some(std::time::Duration::from_secs(10)));
+
+    // add credentials, if present
+    if let (Some(username), Some(password)) = (config.smtp_username(), config.smtp_password()) {
+        let credentials = Credentials::new(username.to_owned(), password.to_owned());
+        mailer_builder = mailer_builder.credentials(credentials);
+    }
+    Ok(mailer_builder)
+}
+
+/// Send `notice` to the recipients of upload `uuid`. In staging mode it is
+/// only logged.
+pub async fn send_notice_email(
+    config: &CryptifyConfig,
+    notice: &Notice,
+    mailing: &Mailing,
+    uuid: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let recipients: Vec<String> = mailing
+        .recipients
+        .iter()
+        .map(|m| m.email.to_string())
+        .collect();
+    if config.staging_mode() {
+        let summary = format!(
+            "[STAGING] Email NOT sent (staging_mode=true). Would have told recipients={:?} \
+             about upload {}: {:?}",
+            recipients, uuid, notice,
+        );
+        log::info!("{}", summary);
+        return Ok(summary);
+    }
+
+    let mailer = smtp_transport(config)?.build();
+    for recipient in mailing.recipients.iter() {
+        let rendered = render_notice_email(notice, mailing, config, recipient.email.as_ref(), uuid);
+        let mut builder = Message::builder()
+            .header(XPostGuard(X_POSTGUARD_VERSION.to_owned()))
+            .header(AutoSubmitted)
+            .from(config.email_from())
+            .to(recipient.clone())
+            .subject(&rendered.subject);
+        if let Some(Ok(mailbox)) = rendered.reply_to.as_deref().map(str::parse::<Mailbox>) {
+            builder = builder.reply_to(mailbox);
+        }
+        let email = builder.multipart(build_body(rendered.html, rendered.text)?)?;
+
+        log::info!("Sending {:?} notice to {}", notice, recipient.email);
+        mailer.send(&email).map_err(|e| {
+            log::error!("Failed to send notice to {}: {}", recipient.email, e);
+            e
+        })?;
+    }
+
+    Ok(format!("Notice sent to {:?}", recipients))
+}
+
+/// Tell the sender of an upload that a recipient downloaded it, from the
+/// receipt mailer.
+pub async fn send_receipt_email(
+    config: &CryptifyConfig,
+    receipt: &Receipt,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let Some(rendered) = render_receipt_email(receipt, config) else {
+        return Ok(format!("No sender recorded for upload {}", receipt.id));
+    };
+    if config.staging_mode() {
+        let summary = format!(
+            "[STAGING] Email NOT sent (staging_mode=true). Would have told sender={} \
+             that {} downloaded upload {}",
+            rendered.recipient, receipt.recipient, receipt.id,
+        );
+        log::info!("{}", summary);
+        return Ok(summary);
+    }
+
+    let email = Message::builder()
+        .header(XPostGuard(X_POSTGUARD_VERSION.to_owned()))
+        .header(AutoSubmitted)
+        .from(config.email_from())
+        .to(rendered.recipient.parse()?)
+        .subject(&rendered.subject)
+        .multipart(build_body(rendered.html, rendered.text)?)?;
+
+    log::info!("Sending download receipt for {}", receipt.id);
+    smtp_transport(config)?.build().send(&email).map_err(|e| {
+        log::error!("Failed to send receipt for {}: {}", receipt.id, e);
+        e
+    })?;
+
+    Ok(format!("Receipt sent to {}", rendered.recipient))
+}
+
+/// Send the receipts the store passes on, one at a time, until the store
+/// is dropped. A failed delivery is logged and not retried.
+pub async fn receipt_mailer(
+    config: CryptifyConfig,
+    mut receipts: rocket::tokio::sync::mpsc::UnboundedReceiver<Receipt>,
+) {
+    while let Some(receipt) = receipts.recv().await {
+        if let Err(e) = send_receipt_email(&config, &receipt).await {
+            log::error!("could not send download receipt for {}: {}", receipt.id, e);
+        }
+    }
+}
+
 /// Staging-mode replacement for actual SMTP delivery. Logs a clearly
 /// marked record of the email that *would* have been sent (recipients,
 /// sender, attributes, expiry, download URL) so operators of a staging
@@ -887,6 +1188,15 @@ mod tests {
             api_key_validation_failed: false,
             last_chunk: None,
             recovery_token: String::new(),
+            finalized: false,
+            reservation: None,
+            header_verified: false,
+            sealed_prefix: Vec::new(),
+            chunked: None,
+            upload_length: None,
+            declared_size: None,
+            max_downloads: None,
+            unsealable_recipients: Vec::new(),
         }
     }
 
@@ -979,6 +1289,120 @@ mod tests {
         assert!(rendered.is_none());
     }
 
+    #[test]
+    fn render_notice_email_names_the_sender_without_a_link() {
+        let config = CryptifyConfig::for_test("https://staging.example.com/", true);
+        let mut state = staging_filestate();
+        state.mail_lang = Language::Nl;
+        let mailing = Mailing::of(&state);
+        let rendered = render_notice_email(
+            &Notice::Revoked,
+            &mailing,
+            &config,
+            "alice@example.com",
+            "uuid-abc",
+        );
+        assert_eq!(rendered.recipient, "alice@example.com");
+        assert_eq!(rendered.reply_to, state.sender);
+        assert!(rendered.subject.contains(NL_STRINGS.revoked_str));
+        assert!(rendered.text.contains(NL_STRINGS.revoked_body));
+        assert!(!rendered.html.contains("/download"));
+
+        let notice = Notice::Extended {
+            expires: 1_700_000_000,
+        };
+        let rendered =
+            render_notice_email(&notice, &mailing, &config, "alice@example.com", "uuid-abc");
+        assert!(rendered.subject.contains(NL_STRINGS.extended_str));
+        assert!(rendered.text.contains("tot 14 november 2023."));
+        assert!(!rendered.html.contains("/download"));
+    }
+
+    #[test]
+    fn signed_links_carry_a_token_for_the_recipient() {
+        const SECRET: &str = "a secret of at least thirty-two bytes";
+        let config = CryptifyConfig::for_test("https://staging.example.com/", true)
+            .with_download_link_secret(SECRET);
+        let state = staging_filestate();
+        let rendered = render_recipient_email(&state, &config, "alice@example.com", "uuid-abc")
+            .expect("render");
+        let token = crate::links::sign(SECRET.as_bytes(), "uuid-abc", "alice@example.com");
+        assert!(
+            rendered.text.contains(&format!(
+                "download?uuid=uuid-abc&recipient=alice%40example.com&token={}",
+                token
+            )),
+            "text missing signed URL: {}",
+            rendered.text
+        );
+
+        // An extension repeats the link, which keeps working.
+        let notice = Notice::Extended {
+            expires: 1_800_000_000,
+        };
+        let mailing = Mailing::of(&state);
+        let rendered =
+            render_notice_email(&notice, &mailing, &config, "alice@example.com", "uuid-abc");
+        assert!(rendered.text.contains(&token), "got: {}", rendered.text);
+        assert!(rendered.html.contains(EN_STRINGS.download_str));
+    }
+
+    #[test]
+    fn unmailed_recipients_get_their_links_from_the_uploader() {
+        const SECRET: &str = "a secret of at least thirty-two bytes";
+        let config = CryptifyConfig::for_test("https://staging.example.com/", true);
+        let mut state = staging_filestate();
+        state.notify_recipients = false;
+        assert!(unmailed_download_links(&config, &state, "uuid-abc")
+            .unwrap()
+            .is_empty());
+
+        let config = config.with_download_link_secret(SECRET);
+        let links = unmailed_download_links(&config, &state, "uuid-abc").unwrap();
+        assert_eq!(links.len(), 2);
+        let token = crate::links::sign(SECRET.as_bytes(), "uuid-abc", "bob@example.com");
+        assert_eq!(
+            links[1],
+            format!(
+                "https://staging.example.com/download?uuid=uuid-abc&recipient=bob%40example.com&token={}",
+                token
+            )
+        );
+
+        state.notify_recipients = true;
+        assert!(unmailed_download_links(&config, &state, "uuid-abc")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn render_receipt_email_tells_the_sender_who_downloaded() {
+        let config = CryptifyConfig::for_test("https://staging.example.com/", true);
+        let mut state = staging_filestate();
+        let mut receipt = Receipt {
+            id: "uuid-abc".to_owned(),
+            recipient: "alice@example.com".to_owned(),
+            mailing: Mailing::of(&state),
+            at: 1_700_000_000,
+        };
+        let rendered = render_receipt_email(&receipt, &config).expect("render");
+        assert_eq!(rendered.recipient, "sender@example.com");
+        assert_eq!(rendered.reply_to, None);
+        assert_eq!(rendered.subject, "alice@example.com downloaded your files");
+        assert!(rendered.text.contains("Downloaded on 14 November 2023."));
+        assert!(!rendered.html.contains("/download"));
+
+        state.mail_lang = Language::Nl;
+        receipt.mailing = Mailing::of(&state);
+        let rendered = render_receipt_email(&receipt, &config).expect("render");
+        assert!(rendered.subject.contains(NL_STRINGS.downloaded_str));
+        assert!(rendered.text.contains("Gedownload op 14 november 2023."));
+
+        state.sender = None;
+        receipt.mailing = Mailing::of(&state);
+        assert!(render_receipt_email(&receipt, &config).is_none());
+    }
+
     #[test]
     fn format_file_size_clamps_above_tb() {
         // u64 max is ~16 EB, far beyond TB — previously UNITS[i] would panic.
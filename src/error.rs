@@ -29,9 +29,24 @@ pub enum Error {
     /// that requires one. Distinct from the upload flow, which degrades a
     /// missing/invalid key to the default tier rather than rejecting.
     Unauthorized(Option<String>),
+    /// 403 — the caller proved who they are, but that identity may not
+    /// have what they asked for, e.g. a disclosed address that is not a
+    /// recipient of the upload.
+    Forbidden(Option<String>),
     /// 404 — the resource (e.g. the email template for a validated API
     /// key) does not exist. Carries an optional human-readable message.
     NotFound(Option<String>),
+    /// 409 — the request is valid but the upload no longer accepts it,
+    /// e.g. a chunk PUT after the upload was finalized.
+    Conflict(Option<String>),
+    /// 410 — the upload existed but was revoked or used up, like a
+    /// download of it would answer.
+    Gone(Option<String>),
+    /// 412 — the request speaks a protocol version the server does not,
+    /// e.g. a tus `Tus-Resumable` other than 1.0.0.
+    PreconditionFailed(Option<String>),
+    /// 415 — the request body has the wrong `Content-Type`.
+    UnsupportedMediaType(Option<String>),
     UnprocessableEntity(Option<String>),
     InternalServerError(Option<String>),
     PayloadTooLarge(PayloadTooLargeBody),
@@ -62,11 +77,36 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
                 e.unwrap_or_else(|| "".to_owned()),
             )
             .respond_to(request),
+            Error::Forbidden(e) => response::status::Custom::<String>(
+                rocket::http::Status::Forbidden,
+                e.unwrap_or_else(|| "".to_owned()),
+            )
+            .respond_to(request),
             Error::NotFound(e) => response::status::Custom::<String>(
                 rocket::http::Status::NotFound,
                 e.unwrap_or_else(|| "".to_owned()),
             )
             .respond_to(request),
+            Error::Conflict(e) => response::status::Custom::<String>(
+                rocket::http::Status::Conflict,
+                e.unwrap_or_else(|| "".to_owned()),
+            )
+            .respond_to(request),
+            Error::Gone(e) => response::status::Custom::<String>(
+                rocket::http::Status::Gone,
+                e.unwrap_or_else(|| "".to_owned()),
+            )
+            .respond_to(request),
+            Error::PreconditionFailed(e) => response::status::Custom::<String>(
+                rocket::http::Status::PreconditionFailed,
+                e.unwrap_or_else(|| "".to_owned()),
+            )
+            .respond_to(request),
+            Error::UnsupportedMediaType(e) => response::status::Custom::<String>(
+                rocket::http::Status::UnsupportedMediaType,
+                e.unwrap_or_else(|| "".to_owned()),
+            )
+            .respond_to(request),
             // response::status::Custom apparently doesn't support Option<R>
             Error::UnprocessableEntity(e) => response::status::Custom::<String>(
                 rocket::http::Status::UnprocessableEntity,
@@ -0,0 +1,77 @@
+//! Signed per-recipient download links. With `download_link_secret` set,
+//! every link cryptify mails carries a token that binds the upload and the
+//! recipient it was sent to with an HMAC-SHA256 over the server's secret,
+//! so a link only works for the address it names. The token does not sign
+//! an expiry: a link works as long as its upload's record says the upload
+//! does, so extending the upload keeps links already mailed working, and
+//! a payload without a record is not opened by any link.
+
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+
+fn mac(secret: &[u8], uuid: &str, recipient: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    // Neither a UUID nor an address contains a newline, so the fields
+    // cannot run into each other.
+    mac.update(uuid.as_bytes());
+    mac.update(b"\n");
+    mac.update(recipient.to_lowercase().as_bytes());
+    mac
+}
+
+/// The token for `recipient`'s link to `uuid`, as hex. Recipients compare
+/// case-insensitively.
+pub fn sign(secret: &[u8], uuid: &str, recipient: &str) -> String {
+    let tag = mac(secret, uuid, recipient).finalize().into_bytes();
+    crate::bytes_to_hex(&tag)
+}
+
+/// Whether a token from a link was signed for the upload and recipient it
+/// is presented for.
+pub fn verify(secret: &[u8], uuid: &str, recipient: &str, token: &str) -> bool {
+    hex_to_bytes(token).is_some_and(|tag| mac(secret, uuid, recipient).verify_slice(&tag).is_ok())
+}
+
+fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"a secret of at least thirty-two bytes";
+
+    #[test]
+    fn a_signed_token_opens_only_its_own_link() {
+        let token = sign(SECRET, "uuid-1", "Alice@example.com");
+        assert!(verify(SECRET, "uuid-1", "alice@example.com", &token));
+        for (uuid, recipient) in [
+            ("uuid-2", "alice@example.com"),
+            ("uuid-1", "bob@example.com"),
+        ] {
+            assert!(!verify(SECRET, uuid, recipient, &token));
+        }
+        assert!(!verify(
+            b"another secret",
+            "uuid-1",
+            "alice@example.com",
+            &token
+        ));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let token = sign(SECRET, "uuid-1", "alice@example.com");
+        let truncated = &token[..token.len() - 2];
+        for garbage in ["", "zz", "0", truncated] {
+            assert!(!verify(SECRET, "uuid-1", "alice@example.com", garbage));
+        }
+    }
+}
@@ -1,41 +1,51 @@
 mod config;
 mod email;
 mod error;
+mod links;
 mod metrics;
+mod storage;
 mod store;
+mod yivi;
 
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::config::CryptifyConfig;
-use crate::email::{render_confirmation_email, render_recipient_email, send_email, RenderedEmail};
+use crate::config::{CryptifyConfig, RecipientCheck, StorageBackend};
+use crate::email::{
+    receipt_mailer, render_confirmation_email, render_recipient_email, send_email,
+    send_notice_email, unmailed_download_links, Notice, RenderedEmail,
+};
 use crate::error::{Error, PayloadTooLargeBody};
 use crate::metrics::{
     detect_channel, parse_client_version, storage_sampler, Metrics, CHANNEL_UNKNOWN,
     CLIENT_VERSION_HEADER,
 };
+use crate::storage::{LocalStorage, PayloadReader, S3Storage, Storage};
 use crate::store::{
-    API_KEY_PER_UPLOAD_LIMIT, API_KEY_ROLLING_LIMIT, PER_UPLOAD_LIMIT, ROLLING_LIMIT,
-    ROLLING_WINDOW_SECS,
+    FinalizedUpload, API_KEY_PER_UPLOAD_LIMIT, API_KEY_ROLLING_LIMIT, PER_UPLOAD_LIMIT,
+    ROLLING_LIMIT, ROLLING_WINDOW_SECS, UPLOAD_LIFETIME_SECS,
 };
+use crate::yivi::{Outcome, Purpose, Yivi};
 
-use std::path::Path;
 use std::str::FromStr;
 
 use pg_core::api::Parameters;
 use pg_core::artifacts::VerifyingKey;
 use pg_core::client::rust::stream::UnsealerStreamConfig;
-use pg_core::client::Unsealer;
+use pg_core::client::{Mode, Unsealer};
+use pg_core::ibs::gg::SIG_BYTES;
+use pg_core::identity::{HiddenPolicy, Policy};
+use pg_core::{
+    HEADER_SIZE_SIZE, MAX_HEADER_SIZE, POL_SIZE_SIZE, PREAMBLE_SIZE, PRELUDE, PRELUDE_SIZE,
+    SIG_SIZE_SIZE, TAG_SIZE,
+};
 
-use tokio_util::compat::TokioAsyncReadCompatExt;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
 
 use sha2::Digest;
 use std::fmt::Write;
 
-use rocket::tokio::{
-    fs::{File, OpenOptions},
-    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
-};
 use rocket::{
     data::ToByteUnit, fairing::AdHoc, figment::Figment, get, http::Header, launch, post, put,
     request::FromRequest, response::Responder, routes, serde::json::Json, Build, Data, Rocket,
@@ -46,7 +56,10 @@ use rocket::http::Method;
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions};
 
 use serde::{Deserialize, Serialize};
-use store::{FileState, LastChunkRecord, Store};
+use store::{
+    ChunkedUpload, DownloadCounter, FileState, LastChunkRecord, Mailing, Reservation, Store,
+    UsageSnapshot,
+};
 
 #[derive(Serialize, Deserialize)]
 struct InitBody {
@@ -65,6 +78,44 @@ struct InitBody {
     /// stored — only the SMTP delivery is skipped.
     #[serde(rename = "notifyRecipients", default = "default_true")]
     notify_recipients: bool,
+    /// Total size in bytes the client is about to upload. Optional. When
+    /// given, no chunk may go past it, and the upload reserves that much of
+    /// the sender's rolling quota, so an over-quota sender is turned away
+    /// before pushing the payload. API-key uploads reserve at init;
+    /// anonymous senders are only known from the sealed header, so theirs
+    /// reserve as soon as the chunk that completes the header is in.
+    #[serde(rename = "declaredSize", default)]
+    declared_size: Option<u64>,
+    /// How the chunks will be sent. Optional; defaults to sequential. The
+    /// response says which mode the server granted: parallel mode needs a
+    /// storage backend that can write at any offset, and falls back to
+    /// sequential otherwise.
+    #[serde(rename = "uploadMode", default)]
+    upload_mode: UploadMode,
+    /// How many completed downloads to allow. Optional; unlimited when
+    /// absent. Once reached the file is deleted. A recipient who passes
+    /// their address along with the download counts only once, so setting
+    /// it to the number of recipients deletes the file once each of them
+    /// has it.
+    #[serde(rename = "maxDownloads", default)]
+    max_downloads: Option<u32>,
+    /// How long the upload stays downloadable after init, in seconds.
+    /// Optional; defaults to 14 days. Bounded by `max_upload_lifetime_secs`
+    /// in the config, which may be higher for API-key tenants.
+    #[serde(rename = "expiresIn", default)]
+    expires_in: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum UploadMode {
+    /// Chunks are PUT to `/fileupload/<uuid>` one after the other, chained
+    /// by the rolling `cryptifytoken`.
+    #[default]
+    Sequential,
+    /// Chunks are PUT to `/fileupload/<uuid>/chunks/<index>`, concurrently
+    /// and in any order, each with its own `ChunkHash`.
+    Parallel,
 }
 
 fn default_true() -> bool {
@@ -80,6 +131,10 @@ struct InitResponse {
     /// the UUID — typically in IndexedDB — and sends it back in an
     /// `X-Recovery-Token` header on resume. Hex-encoded 32-byte random.
     recovery_token: String,
+    upload_mode: UploadMode,
+    /// Length of every chunk but the last, in parallel mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk_size: Option<u64>,
 }
 
 struct CryptifyToken(String);
@@ -420,27 +475,131 @@ impl<'r> FromRequest<'r> for ValidatedApiKey {
 #[post("/fileupload/init", data = "<request>")]
 async fn upload_init(
     config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
     store: &State<Store>,
     api_key: ApiKey,
     request: Json<InitBody>,
     client_headers: ClientHeaders,
 ) -> Result<InitResponder, Error> {
+    let session = create_session(
+        config,
+        storage.inner().as_ref(),
+        store,
+        api_key,
+        &request,
+        client_headers,
+        None,
+    )
+    .await?;
+
+    Ok(InitResponder {
+        inner: Json(InitResponse {
+            uuid: session.uuid,
+            recovery_token: session.recovery_token,
+            upload_mode: if session.chunk_size.is_some() {
+                UploadMode::Parallel
+            } else {
+                UploadMode::Sequential
+            },
+            chunk_size: session.chunk_size,
+        }),
+        cryptify_token: CryptifyToken(session.cryptify_token),
+    })
+}
+
+/// The credentials of a freshly created upload session.
+struct NewSession {
+    uuid: String,
+    cryptify_token: String,
+    recovery_token: String,
+    /// Set when the session was granted parallel mode.
+    chunk_size: Option<u64>,
+}
+
+/// Shortest lifetime a sender may choose: enough to finish the upload.
+const MIN_UPLOAD_LIFETIME_SECS: u64 = 60 * 60;
+
+/// The lifetime of a new upload: the sender's `expires_in` if it is within
+/// the bounds for their tier, else the default capped to those bounds.
+fn upload_lifetime(
+    config: &CryptifyConfig,
+    expires_in: Option<u64>,
+    api_key: bool,
+) -> Result<i64, Error> {
+    let max = config.max_upload_lifetime_secs(api_key);
+    match expires_in {
+        None => Ok((UPLOAD_LIFETIME_SECS as u64).min(max) as i64),
+        Some(secs) if (MIN_UPLOAD_LIFETIME_SECS..=max).contains(&secs) => Ok(secs as i64),
+        Some(_) => Err(Error::BadRequest(Some(format!(
+            "expiresIn must be between {} and {} seconds",
+            MIN_UPLOAD_LIFETIME_SECS, max
+        )))),
+    }
+}
+
+/// Open an upload session for `request`: reserve its declared size if the
+/// sender is known yet, create the payload and register the session in the
+/// store. Shared by
+/// `upload_init` and the tus creation endpoint, which passes its
+/// `Upload-Length` as `upload_length`.
+async fn create_session(
+    config: &CryptifyConfig,
+    storage: &dyn Storage,
+    store: &Store,
+    api_key: ApiKey,
+    request: &InitBody,
+    client_headers: ClientHeaders,
+    upload_length: Option<u64>,
+) -> Result<NewSession, Error> {
     let current_time = chrono::offset::Utc::now().timestamp();
 
     let recipient: lettre::message::Mailboxes = request
         .recipient
         .parse()
         .map_err(|e| Error::BadRequest(Some(format!("Could not parse e-mail address: {}", e))))?;
+    if request.max_downloads == Some(0) {
+        return Err(Error::BadRequest(Some(
+            "maxDownloads must be at least 1".to_owned(),
+        )));
+    }
+    let lifetime = upload_lifetime(config, request.expires_in, api_key.tenant.is_some())?;
 
     let uuid = uuid::Uuid::new_v4().hyphenated().to_string();
 
-    if let Err(e) = File::create(Path::new(config.data_dir()).join(&uuid)).await {
+    let reservation = match (request.declared_size, api_key.tenant.as_deref()) {
+        (Some(bytes), Some(tenant)) => {
+            let reservation = Reservation {
+                key: format!("api-key:{}", tenant),
+                bytes,
+            };
+            store
+                .reserve(
+                    &uuid,
+                    reservation.clone(),
+                    API_KEY_ROLLING_LIMIT,
+                    current_time,
+                )
+                .map_err(|usage| rolling_limit_exceeded(usage, API_KEY_ROLLING_LIMIT))?;
+            Some(reservation)
+        }
+        _ => None,
+    };
+
+    if let Err(e) = storage.create(&uuid).await {
         log::error!("{}", e);
+        store.release(&uuid);
         return Err(Error::InternalServerError(None));
     }
 
     let init_cryptify_token = bytes_to_hex(&rand::random::<[u8; 32]>());
     let recovery_token = bytes_to_hex(&rand::random::<[u8; 32]>());
+    let chunked = match request.upload_mode {
+        UploadMode::Parallel if storage.supports_parallel_writes() => {
+            Some(ChunkedUpload::new(config.chunk_size()))
+        }
+        _ => None,
+    };
+    let chunk_size = chunked.as_ref().map(|c| c.chunk_size);
 
     log::info!(
         "upload_init uuid={} channel={} client_version={:?}",
@@ -454,7 +613,7 @@ async fn upload_init(
         FileState {
             cryptify_token: init_cryptify_token.clone(),
             uploaded: 0,
-            expires: current_time + 1_209_600,
+            expires: current_time + lifetime,
             recipients: recipient,
             mail_content: request.mail_content.clone(),
             mail_lang: request.mail_lang.clone(),
@@ -469,15 +628,23 @@ async fn upload_init(
             api_key_validation_failed: api_key.validation_failed,
             last_chunk: None,
             recovery_token: recovery_token.clone(),
+            finalized: false,
+            reservation,
+            header_verified: false,
+            sealed_prefix: Vec::new(),
+            chunked,
+            upload_length,
+            declared_size: request.declared_size,
+            max_downloads: request.max_downloads,
+            unsealable_recipients: Vec::new(),
         },
     );
 
-    Ok(InitResponder {
-        inner: Json(InitResponse {
-            uuid,
-            recovery_token,
-        }),
-        cryptify_token: CryptifyToken(init_cryptify_token),
+    Ok(NewSession {
+        uuid,
+        cryptify_token: init_cryptify_token,
+        recovery_token,
+        chunk_size,
     })
 }
 
@@ -600,6 +767,9 @@ fn bytes_to_hex(bytes: &[u8]) -> String {
     s
 }
 
+/// The rolling token of a chunk held in memory. The upload path computes
+/// the same thing incrementally in [`ChunkBody`].
+#[cfg(test)]
 fn compute_hash(cryptify_token: &[u8], data: &[u8]) -> String {
     let mut hash = sha2::Sha256::new();
     hash.update(cryptify_token);
@@ -607,11 +777,136 @@ fn compute_hash(cryptify_token: &[u8], data: &[u8]) -> String {
     bytes_to_hex(&hash.finalize())
 }
 
+/// A chunk body read straight off the request, feeding [`compute_hash`]'s
+/// construction as the bytes pass so the chunk never has to sit in memory
+/// whole. A body that ends before its `Content-Range` length (a broken
+/// connection) or runs past it is reported as a read error rather than
+/// EOF, which makes [`Storage::write_at`] roll the chunk back.
+struct ChunkBody<R> {
+    inner: R,
+    remaining: u64,
+    hash: sha2::Sha256,
+    failed: bool,
+    prefix: Vec<u8>,
+    prefix_limit: usize,
+    expected_hash: Option<String>,
+    hash_mismatch: bool,
+}
+
+impl<R> ChunkBody<R> {
+    /// `inner` must be allowed to yield at least one byte more than `len`,
+    /// so an overlong body can be told apart from an exact one.
+    fn new(inner: R, len: u64, cryptify_token: &str) -> Self {
+        let mut hash = sha2::Sha256::new();
+        hash.update(cryptify_token.as_bytes());
+        ChunkBody {
+            inner,
+            remaining: len,
+            hash,
+            failed: false,
+            prefix: Vec::new(),
+            prefix_limit: 0,
+            expected_hash: None,
+            hash_mismatch: false,
+        }
+    }
+
+    /// Fail at the end of the body unless [`ChunkBody::finish`] would
+    /// return `hash`.
+    fn expect_hash(&mut self, hash: &str) {
+        self.expected_hash = Some(hash.to_ascii_lowercase());
+    }
+
+    /// Whether the body was complete but did not match
+    /// [`ChunkBody::expect_hash`].
+    fn hash_mismatch(&self) -> bool {
+        self.hash_mismatch
+    }
+
+    /// Also append the bytes read to `prefix`, until it is `limit` long.
+    fn keep_prefix(&mut self, prefix: Vec<u8>, limit: usize) {
+        self.prefix = prefix;
+        self.prefix_limit = limit;
+    }
+
+    /// The buffer handed to [`ChunkBody::keep_prefix`], with what was read.
+    fn take_prefix(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.prefix)
+    }
+
+    /// Whether reading the body itself failed, as opposed to storing it.
+    fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// The rolling token for the chunk: `compute_hash(token, body)`.
+    fn finish(self) -> String {
+        bytes_to_hex(&self.hash.finalize())
+    }
+}
+
+impl<R: rocket::tokio::io::AsyncRead + Unpin> rocket::tokio::io::AsyncRead for ChunkBody<R> {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut rocket::tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use std::task::Poll;
+
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = match std::pin::Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(result) => result,
+        };
+        let read = &buf.filled()[before..];
+        let result = result.and_then(|()| {
+            if read.is_empty() && this.remaining > 0 {
+                Err(std::io::ErrorKind::UnexpectedEof.into())
+            } else if read.len() as u64 > this.remaining {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "chunk body is longer than its Content-Range",
+                ))
+            } else if read.is_empty()
+                && this.expected_hash.as_ref().is_some_and(|expected| {
+                    *expected != bytes_to_hex(&this.hash.clone().finalize())
+                })
+            {
+                this.hash_mismatch = true;
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "chunk body does not match its ChunkHash",
+                ))
+            } else {
+                Ok(())
+            }
+        });
+        match result {
+            Ok(()) => {
+                this.remaining -= read.len() as u64;
+                this.hash.update(read);
+                let room = this.prefix_limit.saturating_sub(this.prefix.len());
+                this.prefix.extend_from_slice(&read[..room.min(read.len())]);
+            }
+            Err(_) => {
+                // Hand nothing on from a read that failed.
+                buf.set_filled(before);
+                this.failed = true;
+            }
+        }
+        Poll::Ready(result)
+    }
+}
+
 /// Wire-level error message for a `CryptifyToken` mismatch. Reused by both
 /// `check_cryptify_token` (the finalize path) and the chunk classifier so the
 /// message can't drift silently between call sites.
 const TOKEN_MISMATCH_MSG: &str = "Cryptify Token header does not match";
 
+/// Wire-level error message for a chunk PUT against a finalized upload.
+const UPLOAD_FINALIZED_MSG: &str = "Upload has already been finalized";
+
 /// Caller-facing body for 5xx (`InternalServerError` / `ServiceUnavailable`)
 /// responses. Detailed diagnostics are written server-side via `log::error!`
 /// rather than returned to HTTP clients, so operators keep observability
@@ -643,7 +938,9 @@ fn check_cryptify_token(header: &str, expected: &str) -> Result<(), Error> {
 #[put("/fileupload/<uuid>", data = "<data>")]
 async fn upload_chunk(
     config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
     store: &State<Store>,
+    vk: &State<Parameters<VerifyingKey>>,
     uuid: &str,
     headers: UploadHeaders,
     data: Data<'_>,
@@ -658,6 +955,19 @@ async fn upload_chunk(
     };
     let mut state = state.lock().await;
 
+    if state.finalized {
+        return Err(Error::Conflict(Some(UPLOAD_FINALIZED_MSG.to_owned())));
+    }
+    if state.chunked.is_some() {
+        return Err(Error::BadRequest(Some(format!(
+            "Upload is in parallel mode; PUT chunks to /fileupload/{}/chunks/<index>",
+            uuid
+        ))));
+    }
+    if state.upload_length.is_some() {
+        return Err(Error::BadRequest(Some(TUS_SESSION_MSG.to_owned())));
+    }
+
     let start = headers
         .content_range
         .start
@@ -681,8 +991,8 @@ async fn upload_chunk(
     }
 
     // Cheap pre-check before reading the body, so a leaked UUID can't be
-    // used to force the server to buffer up to `chunk_size` bytes per
-    // request just to be rejected. Mirrors the structural part of
+    // used to force the server to read up to `chunk_size` bytes per
+    // request just to reject them. Mirrors the structural part of
     // `classify_chunk_request` — we only commit to reading the body when
     // the request looks like either a normal next chunk or a candidate
     // replay of the last committed chunk.
@@ -701,19 +1011,71 @@ async fn upload_chunk(
         return Err(Error::BadRequest(Some(TOKEN_MISMATCH_MSG.to_owned())));
     }
 
-    let body = data
-        .open((end - start).bytes())
-        .into_bytes()
-        .await
-        .map_err(|_| Error::BadRequest(Some("Could not read data from request".to_owned())))?;
-    if !body.is_complete() || body.len() as u64 != end - start {
-        return Err(Error::BadRequest(Some("Data not complete".to_owned())));
+    // The expected next chunk streams straight into storage. A candidate
+    // replay is only hashed: its bytes were stored the first time round.
+    let mut body = ChunkBody::new(
+        data.open((end - start + 1).bytes()),
+        end - start,
+        &headers.cryptify_token,
+    );
+    if is_normal_next {
+        check_chunk_limits(&state, end)?;
+        // Until the sealed header has been seen whole, collect the leading
+        // bytes so it can be checked. A session reloaded after a restart
+        // lost its prefix and leaves the check to finalize.
+        let collect_prefix = !state.header_verified && state.sealed_prefix.len() as u64 == start;
+        if collect_prefix {
+            body.keep_prefix(
+                std::mem::take(&mut state.sealed_prefix),
+                MAX_SEALED_HEADER_LEN,
+            );
+        }
+        let written = storage.write_at(uuid, start, &mut body).await;
+        if collect_prefix {
+            let mut prefix = body.take_prefix();
+            if written.is_err() {
+                // The write was rolled back to `start`; so is the prefix.
+                prefix.truncate(start as usize);
+            }
+            state.sealed_prefix = prefix;
+        }
+        written.map_err(|e| match e.kind() {
+            _ if body.failed() => Error::BadRequest(Some("Data not complete".to_owned())),
+            std::io::ErrorKind::NotFound => Error::upload_session_not_found(uuid, "file_missing"),
+            _ => {
+                log::error!("could not write chunk to upload file: {}", e);
+                Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+            }
+        })?;
+        if collect_prefix {
+            // Stop a bad upload right away, so the endpoint can't be used
+            // to park arbitrary files until finalize.
+            match check_sealed_prefix(&state.sealed_prefix, &vk.public_key)
+                .await
+                .and_then(|header| reserve_for_sender(config, store, uuid, &mut state, header))
+            {
+                Ok(false) => {}
+                Ok(true) => {
+                    state.header_verified = true;
+                    state.sealed_prefix = Vec::new();
+                }
+                Err(e) => {
+                    drop(state);
+                    discard_upload(storage.inner().as_ref(), store, uuid).await;
+                    return Err(e);
+                }
+            }
+        }
+    } else {
+        rocket::tokio::io::copy(&mut body, &mut rocket::tokio::io::sink())
+            .await
+            .map_err(|_| Error::BadRequest(Some("Data not complete".to_owned())))?;
     }
-    let body = body.into_inner();
+    let body_hash = body.finish();
 
     // Three branches: normal next chunk, idempotent retry of the last
     // committed chunk, or rejection.
-    match classify_chunk_request(&state, &headers.cryptify_token, start, &body) {
+    match classify_chunk_request(&state, &headers.cryptify_token, start, &body_hash) {
         ChunkClassification::NormalNext => {}
         ChunkClassification::ReplayLastChunk(token) => {
             drop(state);
@@ -726,7 +1088,206 @@ async fn upload_chunk(
         ChunkClassification::Reject(err) => return Err(err),
     }
 
-    let per_upload_limit = if state.api_key_tenant.is_some() {
+    let prev_token = headers.cryptify_token;
+    let shasum = body_hash;
+    state.cryptify_token = shasum.clone();
+    state.uploaded += end - start;
+    state.last_chunk = Some(LastChunkRecord {
+        prev_token,
+        prev_uploaded: start,
+        response_token: shasum.clone(),
+    });
+    store.save(uuid, &state);
+
+    drop(state);
+    store.touch(uuid);
+
+    Ok(UploadResponder {
+        body: (),
+        cryptify_token: CryptifyToken(shasum),
+    })
+}
+
+/// The `ChunkHash` header of a parallel chunk PUT: the hex SHA-256 of the
+/// chunk body.
+struct ChunkHashHeader(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ChunkHashHeader {
+    type Error = String;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        match request.headers().get_one("ChunkHash") {
+            Some(hash) => rocket::request::Outcome::Success(ChunkHashHeader(hash.to_owned())),
+            None => rocket::request::Outcome::Error((
+                rocket::http::Status::BadRequest,
+                "Missing ChunkHash header".into(),
+            )),
+        }
+    }
+}
+
+/// One chunk of a parallel upload. Chunk `index` covers the bytes from
+/// `index * chunk_size` (the size handed out at init), and its
+/// `Content-Range` must say so. Chunks may be sent concurrently and in any
+/// order, and sending one again replaces it. The `CryptifyToken` is the one
+/// from init throughout: instead of the rolling hash, each chunk carries
+/// the SHA-256 of its body in `ChunkHash`, and a body that does not match
+/// is not recorded.
+///
+/// The session lock is not held while the body streams into storage, which
+/// is what lets chunks of one upload be written side by side.
+#[put("/fileupload/<uuid>/chunks/<index>", data = "<data>")]
+#[allow(clippy::too_many_arguments)]
+async fn upload_chunk_at(
+    config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    vk: &State<Parameters<VerifyingKey>>,
+    uuid: &str,
+    index: u64,
+    headers: UploadHeaders,
+    chunk_hash: ChunkHashHeader,
+    data: Data<'_>,
+) -> Result<UploadResponder, Error> {
+    if uuid::Uuid::parse_str(uuid).is_err() {
+        return Err(Error::upload_session_not_found(uuid, "invalid_uuid"));
+    }
+
+    let session = match store.get(uuid) {
+        Some(v) => v,
+        None => return Err(Error::upload_session_not_found(uuid, "expired_or_unknown")),
+    };
+    let mut state = session.lock().await;
+
+    if state.finalized {
+        return Err(Error::Conflict(Some(UPLOAD_FINALIZED_MSG.to_owned())));
+    }
+    check_cryptify_token(&headers.cryptify_token, &state.cryptify_token)?;
+    let Some(chunks) = state.chunked.as_ref() else {
+        return Err(Error::BadRequest(Some(
+            "Upload was not started in parallel mode".to_owned(),
+        )));
+    };
+
+    let (Some(start), Some(end)) = (headers.content_range.start, headers.content_range.end) else {
+        return Err(Error::BadRequest(Some(
+            "Could not read Content-Range".to_owned(),
+        )));
+    };
+    if start >= end || index.checked_mul(chunks.chunk_size) != Some(start) {
+        return Err(Error::BadRequest(Some(
+            "Content-Range does not match the chunk index".to_owned(),
+        )));
+    }
+    chunks
+        .check(index, end - start)
+        .map_err(|msg| Error::BadRequest(Some(msg.to_owned())))?;
+    if chunks.in_flight.contains(&index) {
+        return Err(Error::Conflict(Some(
+            "Chunk is already being uploaded".to_owned(),
+        )));
+    }
+    check_chunk_limits(&state, end)?;
+
+    // Until it is written again in full, the chunk counts as missing.
+    let Some(chunks) = state.chunked.as_mut() else {
+        unreachable!("checked above");
+    };
+    chunks.remove(index);
+    chunks.in_flight.insert(index);
+    state.uploaded = chunks.received_bytes();
+    store.save(uuid, &state);
+    drop(state);
+
+    let mut body = ChunkBody::new(data.open((end - start + 1).bytes()), end - start, "");
+    body.expect_hash(&chunk_hash.0);
+    if index == 0 {
+        body.keep_prefix(Vec::new(), MAX_SEALED_HEADER_LEN);
+    }
+    let written = storage.write_chunk(uuid, start, &mut body).await;
+
+    let mut state = session.lock().await;
+    if let Some(chunks) = state.chunked.as_mut() {
+        chunks.in_flight.remove(&index);
+    }
+    // The session may have ended while the body was streaming.
+    if !store.get(uuid).is_some_and(|s| Arc::ptr_eq(&s, &session)) {
+        return Err(Error::upload_session_not_found(uuid, "expired_or_unknown"));
+    }
+    written.map_err(|e| match e.kind() {
+        _ if body.hash_mismatch() => {
+            Error::BadRequest(Some("Chunk does not match its ChunkHash".to_owned()))
+        }
+        _ if body.failed() => Error::BadRequest(Some("Data not complete".to_owned())),
+        std::io::ErrorKind::NotFound => Error::upload_session_not_found(uuid, "file_missing"),
+        _ => {
+            log::error!("could not write chunk to upload file: {}", e);
+            Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+        }
+    })?;
+
+    if index == 0 && !state.header_verified {
+        // Same early check as the sequential path; a header that does not
+        // fit in the first chunk is left to finalize.
+        match check_sealed_prefix(&body.take_prefix(), &vk.public_key)
+            .await
+            .and_then(|header| reserve_for_sender(config, store, uuid, &mut state, header))
+        {
+            Ok(verified) => state.header_verified = verified,
+            Err(e) => {
+                drop(state);
+                discard_upload(storage.inner().as_ref(), store, uuid).await;
+                return Err(e);
+            }
+        }
+    }
+
+    if let Some(chunks) = state.chunked.as_mut() {
+        chunks.insert(index, end - start);
+        state.uploaded = chunks.received_bytes();
+    }
+    store.save(uuid, &state);
+    let cryptify_token = state.cryptify_token.clone();
+    drop(state);
+    store.touch(uuid);
+
+    Ok(UploadResponder {
+        body: (),
+        cryptify_token: CryptifyToken(cryptify_token),
+    })
+}
+
+/// Size limits a chunk ending at byte `end` must respect before any of it
+/// is stored.
+fn check_chunk_limits(state: &FileState, end: u64) -> Result<(), Error> {
+    check_per_upload_limit(
+        state.api_key_tenant.is_some(),
+        state.api_key_validation_failed,
+        state.uploaded,
+        end,
+    )?;
+
+    if state.declared_size.is_some_and(|declared| end > declared) {
+        return Err(Error::BadRequest(Some(
+            "Upload exceeds its declared size".to_owned(),
+        )));
+    }
+
+    Ok(())
+}
+
+/// The per-upload size cap of the sender's tier, for an upload of which
+/// `uploaded` bytes are stored and that would grow to `end`.
+fn check_per_upload_limit(
+    api_key_tenant: bool,
+    validation_failed: bool,
+    uploaded: u64,
+    end: u64,
+) -> Result<(), Error> {
+    let per_upload_limit = if api_key_tenant {
         API_KEY_PER_UPLOAD_LIMIT
     } else {
         PER_UPLOAD_LIMIT
@@ -737,7 +1298,7 @@ async fn upload_chunk(
         // cap that's silent; here, where we'd reject, surface 503 so the
         // client knows the higher tier *might* have applied if pg-pkg had
         // been reachable. Within-default uploads keep flowing as today.
-        if state.api_key_validation_failed {
+        if validation_failed {
             log::error!(
                 "pg-pkg was unreachable while validating the API key; cannot apply the higher upload tier"
             );
@@ -751,50 +1312,13 @@ async fn upload_chunk(
                 per_upload_limit
             ),
             limit: "per_upload",
-            used_bytes: state.uploaded,
+            used_bytes: uploaded,
             limit_bytes: per_upload_limit,
             resets_at: None,
         }));
     }
 
-    let mut file = match OpenOptions::new()
-        .write(true)
-        .open(Path::new(config.data_dir()).join(uuid))
-        .await
-    {
-        Ok(v) => v,
-        Err(_) => return Err(Error::upload_session_not_found(uuid, "file_missing")),
-    };
-
-    file.seek(std::io::SeekFrom::Start(start))
-        .await
-        .map_err(|e| {
-            log::error!("could not seek in upload file: {}", e);
-            Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
-        })?;
-
-    file.write_all(&body).await.map_err(|e| {
-        log::error!("could not write chunk to upload file: {}", e);
-        Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
-    })?;
-
-    let prev_token = headers.cryptify_token;
-    let shasum = compute_hash(prev_token.as_bytes(), &body);
-    state.cryptify_token = shasum.clone();
-    state.uploaded += end - start;
-    state.last_chunk = Some(LastChunkRecord {
-        prev_token,
-        prev_uploaded: start,
-        response_token: shasum.clone(),
-    });
-
-    drop(state);
-    store.touch(uuid);
-
-    Ok(UploadResponder {
-        body: (),
-        cryptify_token: CryptifyToken(shasum),
-    })
+    Ok(())
 }
 
 /// Outcome of inspecting a chunk PUT against the current `FileState`.
@@ -813,11 +1337,13 @@ enum ChunkClassification {
     Reject(Error),
 }
 
+/// `body_hash` is the rolling hash of the request body under the request's
+/// own token, `compute_hash(request_token, body)`.
 fn classify_chunk_request(
     state: &FileState,
     request_token: &str,
     start: u64,
-    body: &[u8],
+    body_hash: &str,
 ) -> ChunkClassification {
     if state.uploaded == start && cryptify_tokens_match(request_token, &state.cryptify_token) {
         return ChunkClassification::NormalNext;
@@ -825,13 +1351,14 @@ fn classify_chunk_request(
 
     if let Some(last) = state.last_chunk.as_ref() {
         if cryptify_tokens_match(request_token, &last.prev_token) && start == last.prev_uploaded {
-            // Recompute the rolling hash over the incoming body. Identity
+            // Compare the rolling hash over the incoming body. Identity
             // is implicit in the rolling-token construction itself: if the
             // hash matches `response_token`, the body is byte-identical to
             // the original chunk (modulo a SHA-256 collision, which would
             // also break the rolling chain). Length divergence surfaces
-            // here too.
-            if compute_hash(last.prev_token.as_bytes(), body) == last.response_token {
+            // here too. The request token equals `prev_token` here, so
+            // `body_hash` was computed under the same prefix.
+            if body_hash == last.response_token {
                 return ChunkClassification::ReplayLastChunk(last.response_token.clone());
             }
             return ChunkClassification::Reject(Error::BadRequest(Some(
@@ -895,43 +1422,408 @@ impl<'r> FromRequest<'r> for FinalizeHeaders {
     }
 }
 
-#[post("/fileupload/finalize/<uuid>")]
-async fn upload_finalize(
-    config: &State<CryptifyConfig>,
-    store: &State<Store>,
-    vk: &State<Parameters<VerifyingKey>>,
-    metrics: &State<Arc<Metrics>>,
-    headers: FinalizeHeaders,
-    uuid: &str,
-) -> Result<(), Error> {
-    let state = match store.get(uuid) {
-        Some(v) => v,
-        None => return Err(Error::upload_session_not_found(uuid, "expired_or_unknown")),
-    };
-    let mut state = state.lock().await;
-
-    check_cryptify_token(&headers.cryptify_token, &state.cryptify_token)?;
-
-    if headers.content_range.size != Some(state.uploaded) {
-        return Err(Error::UnprocessableEntity(None));
+/// End a rejected upload session and delete whatever of its payload was
+/// stored, rather than leaving it to the purge task.
+async fn discard_upload(storage: &dyn Storage, store: &Store, uuid: &str) {
+    store.remove(uuid);
+    if let Err(e) = storage.delete(uuid).await {
+        log::error!("could not delete rejected upload {}: {}", uuid, e);
     }
+}
 
-    let mut file = File::open(Path::new(config.data_dir()).join(uuid))
-        .await
-        .map_err(|e| {
-            log::error!("could not open upload file for finalize: {}", e);
-            Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
-        })?
-        .compat();
+/// The 413 for a sender whose rolling quota cannot take the upload.
+fn rolling_limit_exceeded(usage: UsageSnapshot, limit: u64) -> Error {
+    let resets_at = usage
+        .oldest_expires_at
+        .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+        .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+    Error::PayloadTooLarge(PayloadTooLargeBody {
+        error: format!(
+            "Sender has exceeded the {}-day rolling limit of {} bytes",
+            ROLLING_WINDOW_SECS / 86_400,
+            limit
+        ),
+        limit: "rolling_window",
+        used_bytes: usage.used_bytes,
+        limit_bytes: limit,
+        resets_at,
+    })
+}
 
-    let attributes = Unsealer::<_, UnsealerStreamConfig>::new(&mut file, &vk.public_key)
+const NOT_POSTGUARD_MSG: &str = "Upload is not a PostGuard sealed stream";
+const BAD_SIGNATURE_MSG: &str = "Sender signature on the sealed header does not verify";
+const BAD_HEADER_MSG: &str = "Sealed header is truncated or malformed";
+const BAD_SEGMENTS_MSG: &str = "Sealed payload is truncated or has trailing bytes";
+const NOT_SEALED_FOR_MSG: &str = "Upload is not sealed for these recipients";
+
+/// How far into an upload the sealed header may reach: the preamble, a
+/// header of at most `MAX_HEADER_SIZE`, and the header signature, which
+/// pg_core does not bound by itself and which gets the same allowance.
+const MAX_SEALED_HEADER_LEN: usize =
+    PREAMBLE_SIZE + MAX_HEADER_SIZE + SIG_SIZE_SIZE + MAX_HEADER_SIZE;
+
+/// Parse the sealed header with `Unsealer::new`, which also verifies its
+/// signature, turning a rejection into a 422 with the reason.
+async fn unseal_header<'a, R>(
+    reader: &'a mut Compat<R>,
+    vk: &VerifyingKey,
+) -> Result<Unsealer<&'a mut Compat<R>, UnsealerStreamConfig>, Error>
+where
+    R: rocket::tokio::io::AsyncRead + Unpin,
+{
+    Unsealer::<_, UnsealerStreamConfig>::new(reader, vk)
         .await
         .map_err(|e| {
-            log::error!("could not read postguard file during finalize: {}", e);
+            log::info!("rejecting sealed stream: {}", e);
+            let msg = match e {
+                pg_core::error::Error::NotPostGuard
+                | pg_core::error::Error::IncorrectVersion { .. } => NOT_POSTGUARD_MSG,
+                pg_core::error::Error::IncorrectSignature => BAD_SIGNATURE_MSG,
+                _ => BAD_HEADER_MSG,
+            };
+            Error::UnprocessableEntity(Some(msg.to_owned()))
+        })
+}
+
+/// Check `prefix`, the first bytes of an upload, as far as they go. Returns
+/// the header once they contain all of it and it verified, `Ok(None)`
+/// while more bytes are needed, and a 422 as soon as they can no longer be
+/// the start of a PostGuard stream.
+async fn check_sealed_prefix(
+    prefix: &[u8],
+    vk: &VerifyingKey,
+) -> Result<Option<SealedHeader>, Error> {
+    let reject = |msg: &str| Err(Error::UnprocessableEntity(Some(msg.to_owned())));
+
+    // Only the lengths are read here, to know when the header is complete;
+    // `Unsealer::new` does the actual checking.
+    let prelude = prefix.len().min(PRELUDE_SIZE);
+    if prefix[..prelude] != PRELUDE[..prelude] {
+        return reject(NOT_POSTGUARD_MSG);
+    }
+    let read_len = |at: usize| {
+        prefix
+            .get(at..at + 4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+    };
+    let Some(header_len) = read_len(PREAMBLE_SIZE - HEADER_SIZE_SIZE) else {
+        return Ok(None);
+    };
+    if header_len > MAX_HEADER_SIZE {
+        return reject(BAD_HEADER_MSG);
+    }
+    let sig_at = PREAMBLE_SIZE + header_len;
+    let Some(sig_len) = read_len(sig_at) else {
+        return Ok(None);
+    };
+    let header_end = sig_at + SIG_SIZE_SIZE + sig_len;
+    if header_end > MAX_SEALED_HEADER_LEN {
+        return reject(BAD_HEADER_MSG);
+    }
+    if prefix.len() < header_end {
+        return Ok(None);
+    }
+    unseal_header(&mut (&prefix[..header_end]).compat(), vk)
+        .await
+        .map(|unsealer| Some(SealedHeader::from(unsealer)))
+}
+
+/// Follow up on a [`check_sealed_prefix`] of upload `uuid`: once `header`
+/// is in, an anonymous upload reserves its declared size against the
+/// sender the header names, so an over-quota sender is stopped before the
+/// rest of the payload. API-key uploads reserved theirs at init. Returns
+/// whether the header verified.
+fn reserve_for_sender(
+    config: &CryptifyConfig,
+    store: &Store,
+    uuid: &str,
+    state: &mut FileState,
+    header: Option<SealedHeader>,
+) -> Result<bool, Error> {
+    let Some(header) = header else {
+        return Ok(false);
+    };
+    let Some(bytes) = state.declared_size.filter(|_| state.reservation.is_none()) else {
+        return Ok(true);
+    };
+    // Keyed like the reservation finalize makes, which replaces this one.
+    let email_attribute = config.email_attribute();
+    let Some(sender) = header
+        .sender
+        .con
+        .into_iter()
+        .find(|x| x.atype == email_attribute)
+        .and_then(|x| x.value)
+    else {
+        // Finalize rejects an upload without a sender address.
+        return Ok(true);
+    };
+    let reservation = Reservation { key: sender, bytes };
+    let now = chrono::offset::Utc::now().timestamp();
+    store
+        .reserve(uuid, reservation.clone(), ROLLING_LIMIT, now)
+        .map_err(|usage| rolling_limit_exceeded(usage, ROLLING_LIMIT))?;
+    state.reservation = Some(reservation);
+    Ok(true)
+}
+
+/// What finalize keeps from a verified sealed header.
+struct SealedHeader {
+    /// The sender's public policy, from the header signature.
+    sender: Policy,
+    /// Each recipient's hidden policy, keyed by recipient identifier.
+    recipients: BTreeMap<String, HiddenPolicy>,
+}
+
+impl<R> From<Unsealer<R, UnsealerStreamConfig>> for SealedHeader {
+    fn from(unsealer: Unsealer<R, UnsealerStreamConfig>) -> Self {
+        SealedHeader {
+            sender: unsealer.pub_id,
+            recipients: unsealer
+                .header
+                .recipients
+                .into_iter()
+                .map(|(rid, recipient)| (rid, recipient.policy))
+                .collect(),
+        }
+    }
+}
+
+/// Read the whole sealed stream and check everything that can be checked
+/// without a recipient key: the header, its IBS signature under `vk`, and
+/// that the encrypted segments that follow add up to a complete stream
+/// (any number of full segments, then one shorter final segment that is big
+/// enough for its tag and signature).
+///
+/// The signatures over the message itself are sealed inside the segments,
+/// so only recipients can verify those: a tail that is cut short or altered
+/// within the final segment only shows up when it is decrypted.
+async fn verify_sealed_stream(
+    mut reader: storage::PayloadReader,
+    vk: &VerifyingKey,
+) -> Result<SealedHeader, Error> {
+    let mut compat = (&mut reader).compat();
+    let unsealer = unseal_header(&mut compat, vk).await?;
+    // `Unsealer::new` only accepts streaming mode, with a bounded size.
+    let Mode::Streaming { segment_size, .. } = unsealer.header.mode else {
+        return Err(Error::UnprocessableEntity(Some(BAD_HEADER_MSG.to_owned())));
+    };
+    let header = SealedHeader::from(unsealer);
+
+    // The unsealer stops right after the header signature, so the rest of
+    // the reader is exactly the encrypted payload.
+    let payload_len = rocket::tokio::io::copy(&mut reader, &mut rocket::tokio::io::sink())
+        .await
+        .map_err(|e| {
+            log::error!("could not read sealed payload during finalize: {}", e);
             Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
-        })?
-        .pub_id
-        .con;
+        })?;
+
+    let overhead = (SIG_BYTES + TAG_SIZE) as u64;
+    let full_segment = u64::from(segment_size) + overhead;
+    let last_segment = payload_len % full_segment;
+    // The first segment also carries the length-prefixed hidden policy.
+    let min_last = if payload_len < full_segment {
+        overhead + POL_SIZE_SIZE as u64
+    } else {
+        overhead
+    };
+    if segment_size == 0 || last_segment < min_last {
+        return Err(Error::UnprocessableEntity(Some(
+            BAD_SEGMENTS_MSG.to_owned(),
+        )));
+    }
+
+    Ok(header)
+}
+
+/// The notified addresses that `header` has no recipient for. PostGuard
+/// clients key each recipient by the address it is sent to; the email
+/// attribute value in the policy itself is hidden, so the key is what can
+/// be compared.
+fn unsealable_recipients(
+    notified: &lettre::message::Mailboxes,
+    sealed: &BTreeMap<String, HiddenPolicy>,
+) -> Vec<String> {
+    let sealed: std::collections::HashSet<String> =
+        sealed.keys().map(|rid| rid.trim().to_lowercase()).collect();
+    notified
+        .iter()
+        .map(|mailbox| mailbox.email.to_string())
+        .filter(|address| !sealed.contains(&address.to_lowercase()))
+        .collect()
+}
+
+/// Response header listing, comma-separated, the notified addresses the
+/// payload was not sealed for; see [`unsealable_recipients`].
+const UNSEALABLE_RECIPIENTS_HEADER: &str = "X-Cryptify-Unsealable-Recipients";
+
+/// Response header listing, comma-separated, the signed download links of
+/// recipients the upload does not notify; see [`unmailed_download_links`].
+const DOWNLOAD_LINKS_HEADER: &str = "X-Cryptify-Download-Links";
+
+/// What a finalized upload reports back to the uploader.
+struct Finalized {
+    /// See [`unsealable_recipients`].
+    unsealable: Vec<String>,
+    /// See [`unmailed_download_links`].
+    download_links: Vec<String>,
+}
+
+/// An empty 200 that carries [`UNSEALABLE_RECIPIENTS_HEADER`] and
+/// [`DOWNLOAD_LINKS_HEADER`] when there is something to report.
+struct FinalizeResponse(Finalized);
+
+impl<'r> Responder<'r, 'static> for FinalizeResponse {
+    fn respond_to(self, _: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = rocket::Response::build();
+        if !self.0.unsealable.is_empty() {
+            response.raw_header(UNSEALABLE_RECIPIENTS_HEADER, self.0.unsealable.join(", "));
+        }
+        if !self.0.download_links.is_empty() {
+            response.raw_header(DOWNLOAD_LINKS_HEADER, self.0.download_links.join(", "));
+        }
+        Ok(response.finalize())
+    }
+}
+
+#[post("/fileupload/finalize/<uuid>")]
+async fn upload_finalize(
+    config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    vk: &State<Parameters<VerifyingKey>>,
+    metrics: &State<Arc<Metrics>>,
+    headers: FinalizeHeaders,
+    uuid: &str,
+) -> Result<FinalizeResponse, Error> {
+    let state = match store.get(uuid) {
+        Some(v) => v,
+        None => return Err(Error::upload_session_not_found(uuid, "expired_or_unknown")),
+    };
+    let state = state.lock().await;
+
+    check_cryptify_token(&headers.cryptify_token, &state.cryptify_token)?;
+
+    if state.upload_length.is_some() {
+        return Err(Error::BadRequest(Some(TUS_SESSION_MSG.to_owned())));
+    }
+
+    // A parallel chunk whose write failed is simply not stored; say which
+    // one to send again rather than that the sizes disagree.
+    if let (Some(chunks), Some(total)) = (state.chunked.as_ref(), headers.content_range.size) {
+        if let Some(missing) = chunks.first_missing(total) {
+            return Err(missing_chunk(missing));
+        }
+    }
+
+    if headers.content_range.size != Some(state.uploaded) {
+        return Err(Error::UnprocessableEntity(None));
+    }
+
+    finalize_upload(
+        config,
+        storage.inner().as_ref(),
+        store,
+        vk,
+        metrics,
+        uuid,
+        state,
+    )
+    .await
+    .map(FinalizeResponse)
+}
+
+/// The 422 for a parallel upload that lacks chunk `index`.
+fn missing_chunk(index: u64) -> Error {
+    Error::UnprocessableEntity(Some(format!("Upload is missing chunk {}", index)))
+}
+
+/// Verify the stored payload of the session held in `state`, book its
+/// usage and send the notification emails. Shared by `upload_finalize`,
+/// `upload_single` and the tus `PATCH` that stores the last byte. Returns
+/// the notified addresses the payload was not sealed for, if the
+/// deployment flags them, and the links of recipients it does not mail.
+async fn finalize_upload(
+    config: &CryptifyConfig,
+    storage: &dyn Storage,
+    store: &Store,
+    vk: &Parameters<VerifyingKey>,
+    metrics: &Metrics,
+    uuid: &str,
+    mut state: rocket::tokio::sync::MutexGuard<'_, FileState>,
+) -> Result<Finalized, Error> {
+    let download_links = unmailed_download_links(config, &state, uuid).map_err(|e| {
+        log::error!("could not build download links for {}: {}", uuid, e);
+        Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+    })?;
+
+    // A retry whose first response was lost: the upload is done, the mails
+    // are out and the usage is booked, so just report success again.
+    if state.finalized {
+        let unsealable = state.unsealable_recipients.clone();
+        drop(state);
+        store.touch(uuid);
+        return Ok(Finalized {
+            unsealable,
+            download_links,
+        });
+    }
+
+    if let Some(missing) = state
+        .chunked
+        .as_ref()
+        .and_then(|chunks| chunks.first_missing(state.uploaded))
+    {
+        return Err(missing_chunk(missing));
+    }
+
+    storage.complete(uuid).await.map_err(|e| {
+        log::error!("could not complete upload file for finalize: {}", e);
+        Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+    })?;
+
+    let file = storage.read(uuid, None).await.map_err(|e| {
+        log::error!("could not open upload file for finalize: {}", e);
+        Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+    })?;
+
+    // A broken stream cannot be repaired by more chunks (the bytes are
+    // already moved into place), so the upload is dropped right away
+    // rather than left for the purge task.
+    let header = match verify_sealed_stream(file, &vk.public_key).await {
+        Ok(header) => header,
+        Err(e @ Error::UnprocessableEntity(_)) => {
+            drop(state);
+            discard_upload(storage, store, uuid).await;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+
+    let check = config.recipient_check();
+    let unsealable = if check == RecipientCheck::Off {
+        Vec::new()
+    } else {
+        unsealable_recipients(&state.recipients, &header.recipients)
+    };
+    if !unsealable.is_empty() {
+        log::info!(
+            "upload {} notifies recipients it was not sealed for: {:?}",
+            uuid,
+            unsealable
+        );
+        if check == RecipientCheck::Reject {
+            drop(state);
+            discard_upload(storage, store, uuid).await;
+            return Err(Error::UnprocessableEntity(Some(format!(
+                "{}: {}",
+                NOT_SEALED_FOR_MSG,
+                unsealable.join(", ")
+            ))));
+        }
+    }
+    let attributes = header.sender.con;
 
     // The attribute type carrying the sender's email is configurable
     // (postguard#236): test environments use a test-scheme type since pbdf
@@ -972,41 +1864,41 @@ async fn upload_finalize(
         .as_deref()
         .map(|t| format!("api-key:{}", t))
         .or_else(|| sender.clone());
-    if let Some(key) = accounting_key.as_deref() {
-        let usage = store.get_usage(key, now_secs);
-        log::info!(
-            "Rolling limit check for {} (api_key_tenant={:?}): used={} + current={} vs limit={}",
+    // Swap whatever was reserved at init for the actual size under the
+    // now-proven accounting key. The reservation is booked as usage once
+    // the mails are out, and released if the session dies before that.
+    if let Some(key) = accounting_key {
+        let reservation = Reservation {
             key,
+            bytes: state.uploaded,
+        };
+        let result = store.reserve(uuid, reservation.clone(), rolling_limit, now_secs);
+        log::info!(
+            "Rolling limit check for {} (api_key_tenant={:?}): current={} vs limit={}, accepted={}",
+            reservation.key,
             state.api_key_tenant,
-            usage.used_bytes,
             state.uploaded,
-            rolling_limit
+            rolling_limit,
+            result.is_ok()
         );
-        if usage.used_bytes.saturating_add(state.uploaded) > rolling_limit {
+        if let Err(usage) = result {
             drop(state);
-            store.remove(uuid);
-            let _ = rocket::tokio::fs::remove_file(Path::new(config.data_dir()).join(uuid)).await;
-            let resets_at = usage
-                .oldest_expires_at
-                .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
-                .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
-            return Err(Error::PayloadTooLarge(PayloadTooLargeBody {
-                error: format!(
-                    "Sender has exceeded the {}-day rolling limit of {} bytes",
-                    ROLLING_WINDOW_SECS / 86_400,
-                    rolling_limit
-                ),
-                limit: "rolling_window",
-                used_bytes: usage.used_bytes,
-                limit_bytes: rolling_limit,
-                resets_at,
-            }));
+            discard_upload(storage, store, uuid).await;
+            return Err(rolling_limit_exceeded(usage, rolling_limit));
         }
+        state.reservation = Some(reservation);
     }
 
     state.sender = sender.clone();
     state.sender_attributes = sender_attributes;
 
+    // Only now is the payload downloadable. The marker goes in before the
+    // email so the link it carries never 404s.
+    storage.mark_finalized(uuid).await.map_err(|e| {
+        log::error!("could not mark upload {} as finalized: {}", uuid, e);
+        Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+    })?;
+
     send_email(config, &state, uuid).await.map_err(|e| {
         log::error!("could not send notification email: {}", e);
         Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
@@ -1024,11 +1916,198 @@ async fn upload_finalize(
         state.uploaded
     );
 
-    if let Some(key) = accounting_key {
-        store.record_upload(key, state.uploaded, now_secs);
-    }
+    store.commit_reservation(uuid, now_secs);
+    state.reservation = None;
+    state.finalized = true;
+    state.unsealable_recipients = unsealable.clone();
+    store.save(uuid, &state);
+    store.record_finalized(
+        uuid,
+        FinalizedUpload {
+            recovery_token: Some(state.recovery_token.clone()),
+            mailing: Some(Mailing::of(&state)),
+            max_downloads: state.max_downloads,
+            finalized_at: Some(now_secs),
+            api_key_tenant: state.api_key_tenant.clone(),
+            size: Some(state.uploaded),
+            recipient_policies: Some(header.recipients),
+            receipts: state.confirm,
+            ..FinalizedUpload::new(state.expires)
+        },
+    );
 
-    Ok(())
+    Ok(Finalized {
+        unsealable,
+        download_links,
+    })
+}
+
+/// Largest `metadata` part a single-request upload may have.
+const SINGLE_UPLOAD_METADATA_LIMIT: u64 = 64 * 1024;
+
+#[derive(Serialize)]
+struct SingleUploadResponse {
+    uuid: String,
+    /// See [`unsealable_recipients`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unsealable_recipients: Vec<String>,
+    /// See [`unmailed_download_links`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    download_links: Vec<String>,
+}
+
+/// The error a multipart body stream failed with, if it was the body
+/// rather than storage that broke the write.
+fn multipart_error(e: &std::io::Error) -> Option<&multer::Error> {
+    e.get_ref()
+        .and_then(|inner| inner.downcast_ref::<multer::Error>())
+}
+
+/// Init, upload and finalize a small upload in one request. The body is
+/// `multipart/form-data` with a `metadata` part holding the JSON of
+/// `/fileupload/init`, followed by a `file` part of at most `chunk_size`
+/// bytes with the sealed payload. The file streams straight into storage;
+/// anything that fails along the way takes the whole upload with it, so a
+/// client simply retries the request.
+#[allow(clippy::too_many_arguments)]
+#[post("/fileupload/single", data = "<data>")]
+async fn upload_single(
+    config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    vk: &State<Parameters<VerifyingKey>>,
+    metrics: &State<Arc<Metrics>>,
+    api_key: ApiKey,
+    client_headers: ClientHeaders,
+    content_type: Option<&rocket::http::ContentType>,
+    data: Data<'_>,
+) -> Result<Json<SingleUploadResponse>, Error> {
+    use rocket::futures::StreamExt;
+
+    let boundary = content_type
+        .filter(|ct| ct.is_form_data())
+        .and_then(|ct| ct.param("boundary"))
+        .ok_or_else(|| {
+            Error::UnsupportedMediaType(Some("Content-Type must be multipart/form-data".to_owned()))
+        })?;
+    let too_large = || {
+        Error::BadRequest(Some(format!(
+            "Upload too large for a single request; the maximum is {} bytes",
+            config.chunk_size()
+        )))
+    };
+    let limits = multer::SizeLimit::new()
+        .for_field("metadata", SINGLE_UPLOAD_METADATA_LIMIT)
+        .for_field("file", config.chunk_size());
+    let mut multipart = multer::Multipart::with_reader_with_constraints(
+        data.open((config.chunk_size() + 2 * SINGLE_UPLOAD_METADATA_LIMIT).bytes()),
+        boundary,
+        multer::Constraints::new()
+            .allowed_fields(vec!["metadata", "file"])
+            .size_limit(limits),
+    );
+    let malformed = |e: multer::Error| match e {
+        multer::Error::FieldSizeExceeded { .. } => too_large(),
+        e => Error::BadRequest(Some(format!("Could not read multipart body: {}", e))),
+    };
+
+    let field = multipart.next_field().await.map_err(malformed)?;
+    let metadata = match field {
+        Some(field) if field.name() == Some("metadata") => {
+            field.bytes().await.map_err(malformed)?
+        }
+        _ => {
+            return Err(Error::BadRequest(Some(
+                "The metadata part must come first".to_owned(),
+            )))
+        }
+    };
+    let request: InitBody = serde_json::from_slice(&metadata)
+        .map_err(|e| Error::BadRequest(Some(format!("Could not parse metadata: {}", e))))?;
+    let file = match multipart.next_field().await.map_err(malformed)? {
+        Some(field) if field.name() == Some("file") => field,
+        _ => {
+            return Err(Error::BadRequest(Some(
+                "The file part must follow the metadata".to_owned(),
+            )))
+        }
+    };
+
+    let session = create_session(
+        config,
+        storage.inner().as_ref(),
+        store,
+        api_key,
+        &request,
+        client_headers,
+        None,
+    )
+    .await?;
+    let uuid = session.uuid;
+    let state = store
+        .get(&uuid)
+        .ok_or_else(|| Error::upload_session_not_found(&uuid, "expired_or_unknown"))?;
+    let mut state = state.lock().await;
+
+    let mut body =
+        tokio_util::io::StreamReader::new(file.map(|chunk| chunk.map_err(std::io::Error::other)));
+    let written = storage.write_at(&uuid, 0, &mut body).await;
+    drop(body);
+    let written =
+        match written {
+            Ok(written) => multipart
+                .next_field()
+                .await
+                .map_err(malformed)
+                .and_then(|next| match next {
+                    None => Ok(written),
+                    Some(_) => Err(Error::BadRequest(Some(
+                        "Nothing may follow the file part".to_owned(),
+                    ))),
+                }),
+            Err(e) => Err(match multipart_error(&e) {
+                Some(multer::Error::FieldSizeExceeded { .. }) => too_large(),
+                Some(_) => Error::BadRequest(Some("Data not complete".to_owned())),
+                None => {
+                    log::error!("could not write single-request upload {}: {}", uuid, e);
+                    Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+                }
+            }),
+        };
+    let result = match written {
+        Ok(written) => {
+            state.uploaded = written;
+            store.save(&uuid, &state);
+            finalize_upload(
+                config,
+                storage.inner().as_ref(),
+                store,
+                vk,
+                metrics,
+                &uuid,
+                state,
+            )
+            .await
+        }
+        Err(e) => {
+            drop(state);
+            Err(e)
+        }
+    };
+    let finalized = match result {
+        Ok(finalized) => finalized,
+        Err(e) => {
+            // The client holds no token to resume with; leave nothing behind.
+            discard_upload(storage.inner().as_ref(), store, &uuid).await;
+            return Err(e);
+        }
+    };
+
+    Ok(Json(SingleUploadResponse {
+        uuid,
+        unsealable_recipients: finalized.unsealable,
+        download_links: finalized.download_links,
+    }))
 }
 
 /// Snapshot of an in-flight upload's rolling-token state, returned by
@@ -1046,6 +2125,10 @@ struct UploadStatusResponse {
     prev_token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     prev_offset: Option<u64>,
+    /// In parallel mode: the indexes of the chunks stored so far, as
+    /// half-open `[start, end)` runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    received_chunks: Option<Vec<(u64, u64)>>,
 }
 
 /// Constant-time string equality. `subtle::ConstantTimeEq` makes the timing
@@ -1093,6 +2176,7 @@ async fn upload_status(
         cryptify_token: state.cryptify_token.clone(),
         prev_token,
         prev_offset,
+        received_chunks: state.chunked.as_ref().map(|c| c.received.clone()),
     };
 
     drop(state);
@@ -1129,51 +2213,604 @@ impl<'r> FromRequest<'r> for RecoveryTokenHeader {
     }
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "snake_case")]
-struct UsageResponse {
-    email: String,
-    used_bytes: u64,
-    limit_bytes: u64,
-    window_days: u64,
-    per_upload_limit_bytes: u64,
-    resets_at: Option<String>,
+/// Credential for cancelling an upload: the session's recovery token in
+/// `X-Recovery-Token`, or its current token in `CryptifyToken`. Neither
+/// header → 401.
+enum CancelToken {
+    Recovery(String),
+    Cryptify(String),
 }
 
-#[get("/usage?<email>")]
-fn usage(
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CancelToken {
+    type Error = ();
+    async fn from_request(request: &'r rocket::Request<'_>) -> rocket::request::Outcome<Self, ()> {
+        let header = |name| {
+            request
+                .headers()
+                .get_one(name)
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(str::to_owned)
+        };
+        match (header("X-Recovery-Token"), header("CryptifyToken")) {
+            (Some(t), _) => rocket::request::Outcome::Success(CancelToken::Recovery(t)),
+            (None, Some(t)) => rocket::request::Outcome::Success(CancelToken::Cryptify(t)),
+            (None, None) => {
+                rocket::request::Outcome::Error((rocket::http::Status::Unauthorized, ()))
+            }
+        }
+    }
+}
+
+/// Cancel an unfinished upload: its session and partial payload are dropped
+/// right away instead of lingering until the idle TTL. A wrong token gets
+/// the same 404 as an unknown session; a finalized upload is revoked
+/// through `DELETE /filedownload/<uuid>` instead.
+#[rocket::delete("/fileupload/<uuid>")]
+async fn upload_cancel(
+    storage: &State<Arc<dyn Storage>>,
     store: &State<Store>,
-    api_key: ValidatedApiKey,
-    email: Option<String>,
-) -> Json<UsageResponse> {
-    let now = chrono::offset::Utc::now().timestamp();
-    // Usage is accounted per validated tenant, keyed by the tenant proven via
-    // the `Authorization` header — never by a caller-supplied email. The
-    // `ValidatedApiKey` guard has already rejected any unauthenticated caller
-    // with 401, so there is no way to query usage for an arbitrary address and
-    // hence no user-enumeration / activity-monitoring oracle. The `email`
-    // query parameter is retained only so the response can echo it back for
-    // frontends; it does not influence the lookup.
-    let lookup_key = format!("api-key:{}", api_key.tenant);
-    let usage = store.get_usage(&lookup_key, now);
-    let resets_at = usage
-        .oldest_expires_at
-        .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
-        .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
-    Json(UsageResponse {
-        email: email.unwrap_or_default(),
-        used_bytes: usage.used_bytes,
-        limit_bytes: API_KEY_ROLLING_LIMIT,
-        window_days: (ROLLING_WINDOW_SECS / 86_400) as u64,
-        per_upload_limit_bytes: API_KEY_PER_UPLOAD_LIMIT,
-        resets_at,
-    })
+    metrics: &State<Arc<Metrics>>,
+    uuid: &str,
+    token: CancelToken,
+) -> Result<(), Error> {
+    if uuid::Uuid::parse_str(uuid).is_err() {
+        return Err(Error::upload_session_not_found(uuid, "invalid_uuid"));
+    }
+    let state = store
+        .get(uuid)
+        .ok_or_else(|| Error::upload_session_not_found(uuid, "expired_or_unknown"))?;
+    let state = state.lock().await;
+    let authorized = match &token {
+        CancelToken::Recovery(t) => constant_time_eq(t, &state.recovery_token),
+        CancelToken::Cryptify(t) => cryptify_tokens_match(t, &state.cryptify_token),
+    };
+    if !authorized {
+        return Err(Error::upload_session_not_found(uuid, "expired_or_unknown"));
+    }
+    if state.finalized {
+        return Err(Error::Conflict(Some(UPLOAD_FINALIZED_MSG.to_owned())));
+    }
+    drop(state);
+
+    discard_upload(storage.inner().as_ref(), store, uuid).await;
+    metrics.record_cancelled();
+    log::info!("upload {} cancelled by its sender", uuid);
+    Ok(())
 }
 
-/// Body returned by `GET /email-template` for a validated API key that has
-/// a template configured on pg-pkg.
-#[derive(Serialize)]
-struct EmailTemplateResponse {
+/// The tus protocol version spoken by the `/fileupload/tus` endpoints.
+const TUS_VERSION: &str = "1.0.0";
+
+/// The tus extensions the endpoints implement, for `Tus-Extension`.
+const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+
+/// Wire-level error message for a cryptify chunk PUT or finalize against a
+/// session that was created through tus.
+const TUS_SESSION_MSG: &str = "Upload was created through tus; PATCH its upload URL";
+
+/// Adds `Tus-Resumable` to every response of the tus endpoints, errors
+/// included, and `Tus-Version` to a 412 for an unsupported version.
+struct Tus<R>(R);
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Tus<R> {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = self.0.respond_to(request)?;
+        response.set_raw_header("Tus-Resumable", TUS_VERSION);
+        if response.status() == rocket::http::Status::PreconditionFailed {
+            response.set_raw_header("Tus-Version", TUS_VERSION);
+        }
+        Ok(response)
+    }
+}
+
+/// A bodiless tus response.
+struct TusResponse {
+    status: rocket::http::Status,
+    headers: Vec<Header<'static>>,
+}
+
+impl TusResponse {
+    fn new(status: rocket::http::Status) -> Self {
+        TusResponse {
+            status,
+            headers: Vec::new(),
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push(Header::new(name, value.to_string()));
+        self
+    }
+
+    /// `Upload-Expires`: when the session is evicted unless it is used
+    /// again, as an HTTP date.
+    fn expires(self, store: &Store) -> Self {
+        let expires = chrono::offset::Utc::now() + store.idle_ttl();
+        self.header(
+            "Upload-Expires",
+            expires.format("%a, %d %b %Y %H:%M:%S GMT"),
+        )
+    }
+}
+
+impl<'r> Responder<'r, 'static> for TusResponse {
+    fn respond_to(self, _: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = rocket::Response::build().status(self.status).finalize();
+        for header in self.headers {
+            response.set_header(header);
+        }
+        Ok(response)
+    }
+}
+
+/// The tus request headers. Which of them are required depends on the
+/// endpoint, so each is checked by the handler.
+struct TusHeaders {
+    resumable: Option<String>,
+    upload_offset: Option<String>,
+    upload_length: Option<String>,
+    upload_defer_length: bool,
+    upload_metadata: Option<String>,
+    content_type: Option<String>,
+    content_length: Option<String>,
+    cryptify_token: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TusHeaders {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let header = |name| request.headers().get_one(name).map(str::to_owned);
+        rocket::request::Outcome::Success(TusHeaders {
+            resumable: header("Tus-Resumable"),
+            upload_offset: header("Upload-Offset"),
+            upload_length: header("Upload-Length"),
+            upload_defer_length: request.headers().contains("Upload-Defer-Length"),
+            upload_metadata: header("Upload-Metadata"),
+            content_type: header("Content-Type"),
+            content_length: header("Content-Length"),
+            cryptify_token: header("CryptifyToken"),
+        })
+    }
+}
+
+impl TusHeaders {
+    /// Every tus request but `OPTIONS` must name the protocol version.
+    fn check_version(&self) -> Result<(), Error> {
+        if self.resumable.as_deref() != Some(TUS_VERSION) {
+            return Err(Error::PreconditionFailed(Some(format!(
+                "Tus-Resumable must be {}",
+                TUS_VERSION
+            ))));
+        }
+        Ok(())
+    }
+}
+
+fn parse_tus_number(value: Option<&str>, name: &str) -> Result<u64, Error> {
+    value
+        .ok_or_else(|| Error::BadRequest(Some(format!("Missing {} header", name))))?
+        .trim()
+        .parse()
+        .map_err(|_| Error::BadRequest(Some(format!("Could not read {} header", name))))
+}
+
+/// Parse an `Upload-Metadata` header: comma-separated pairs of a key and
+/// its base64-encoded value, where the value may be left out.
+fn parse_tus_metadata(header: &str) -> Result<HashMap<String, String>, Error> {
+    use base64::Engine;
+
+    let mut metadata = HashMap::new();
+    for pair in header.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+        let value = base64::engine::general_purpose::STANDARD
+            .decode(value.trim())
+            .ok()
+            .and_then(|v| String::from_utf8(v).ok())
+            .ok_or_else(|| {
+                Error::BadRequest(Some(format!(
+                    "Upload-Metadata value of {} is not valid",
+                    key
+                )))
+            })?;
+        if metadata.insert(key.to_owned(), value).is_some() {
+            return Err(Error::BadRequest(Some(format!(
+                "Upload-Metadata has {} twice",
+                key
+            ))));
+        }
+    }
+    Ok(metadata)
+}
+
+/// The [`InitBody`] a tus creation request describes in its metadata:
+/// `recipient`, `mailContent` and `mailLang` as in `upload_init`, plus the
+/// optional `confirm` (default `false`) and `notifyRecipients` (default
+/// `true`). The upload length is its declared size.
+fn tus_metadata_number<T: FromStr>(
+    metadata: &mut HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>, Error> {
+    metadata
+        .remove(key)
+        .map(|n| {
+            n.parse().map_err(|_| {
+                Error::BadRequest(Some(format!("Upload-Metadata {} must be a number", key)))
+            })
+        })
+        .transpose()
+}
+
+fn tus_init_body(mut metadata: HashMap<String, String>, length: u64) -> Result<InitBody, Error> {
+    let mut field = |key: &str| {
+        metadata
+            .remove(key)
+            .ok_or_else(|| Error::BadRequest(Some(format!("Upload-Metadata is missing {}", key))))
+    };
+    let recipient = field("recipient")?;
+    let mail_content = field("mailContent")?;
+    let mail_lang = serde_json::from_value(serde_json::Value::String(field("mailLang")?))
+        .map_err(|_| Error::BadRequest(Some("Unknown mailLang".to_owned())))?;
+    let max_downloads = tus_metadata_number(&mut metadata, "maxDownloads")?;
+    let expires_in = tus_metadata_number(&mut metadata, "expiresIn")?;
+    let mut flag = |key: &str, default: bool| match metadata.remove(key).as_deref() {
+        None => Ok(default),
+        Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(_) => Err(Error::BadRequest(Some(format!(
+            "Upload-Metadata {} must be true or false",
+            key
+        )))),
+    };
+    Ok(InitBody {
+        recipient,
+        mail_content,
+        mail_lang,
+        confirm: flag("confirm", false)?,
+        notify_recipients: flag("notifyRecipients", true)?,
+        declared_size: Some(length),
+        upload_mode: UploadMode::Sequential,
+        max_downloads,
+        expires_in,
+    })
+}
+
+/// Check that `token`, the `CryptifyToken` header of a tus request, is the
+/// capability handed out at creation of the tus session `state`, returning
+/// its `Upload-Length`. A missing or wrong token reads as an unknown
+/// session, like on the status endpoint.
+fn check_tus_token(state: &FileState, uuid: &str, token: Option<&str>) -> Result<u64, Error> {
+    match state.upload_length {
+        Some(length)
+            if token.is_some_and(|token| cryptify_tokens_match(token, &state.cryptify_token)) =>
+        {
+            Ok(length)
+        }
+        _ => Err(Error::upload_session_not_found(uuid, "expired_or_unknown")),
+    }
+}
+
+/// tus discovery: the supported version, extensions and maximum size.
+#[rocket::options("/fileupload/tus")]
+fn tus_options(api_key: ApiKey) -> Tus<TusResponse> {
+    let max_size = if api_key.tenant.is_some() {
+        API_KEY_PER_UPLOAD_LIMIT
+    } else {
+        PER_UPLOAD_LIMIT
+    };
+    Tus(TusResponse::new(rocket::http::Status::NoContent)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Max-Size", max_size))
+}
+
+/// tus creation. The session is an ordinary upload session. Its initial
+/// cryptify token, which tus sessions never roll, comes back in the
+/// `CryptifyToken` header, and every request on the upload URL in
+/// `Location` has to send it along: the URL alone grants nothing.
+#[post("/fileupload/tus")]
+async fn tus_create(
+    config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    api_key: ApiKey,
+    headers: TusHeaders,
+    client_headers: ClientHeaders,
+) -> Tus<Result<TusResponse, Error>> {
+    Tus(async {
+        headers.check_version()?;
+        if headers.upload_defer_length {
+            return Err(Error::BadRequest(Some(
+                "Upload-Defer-Length is not supported".to_owned(),
+            )));
+        }
+        let length = parse_tus_number(headers.upload_length.as_deref(), "Upload-Length")?;
+        if length == 0 {
+            return Err(Error::BadRequest(Some(
+                "Upload-Length must be positive".to_owned(),
+            )));
+        }
+        check_per_upload_limit(
+            api_key.tenant.is_some(),
+            api_key.validation_failed,
+            0,
+            length,
+        )?;
+        let metadata = parse_tus_metadata(headers.upload_metadata.as_deref().unwrap_or(""))?;
+        let request = tus_init_body(metadata, length)?;
+
+        let session = create_session(
+            config,
+            storage.inner().as_ref(),
+            store,
+            api_key,
+            &request,
+            client_headers,
+            Some(length),
+        )
+        .await?;
+
+        Ok(TusResponse::new(rocket::http::Status::Created)
+            .header("Location", format!("/fileupload/tus/{}", session.uuid))
+            .header("CryptifyToken", session.cryptify_token)
+            .expires(store))
+    }
+    .await)
+}
+
+/// tus offset retrieval.
+#[rocket::head("/fileupload/tus/<uuid>")]
+async fn tus_head(
+    store: &State<Store>,
+    uuid: &str,
+    headers: TusHeaders,
+) -> Tus<Result<TusResponse, Error>> {
+    Tus(async {
+        headers.check_version()?;
+        let state = store
+            .get(uuid)
+            .ok_or_else(|| Error::upload_session_not_found(uuid, "expired_or_unknown"))?;
+        let state = state.lock().await;
+        let length = check_tus_token(&state, uuid, headers.cryptify_token.as_deref())?;
+
+        let response = TusResponse::new(rocket::http::Status::Ok)
+            .header("Upload-Offset", state.uploaded)
+            .header("Upload-Length", length)
+            .header("Cache-Control", "no-store");
+        let finalized = state.finalized;
+        drop(state);
+        if finalized {
+            return Ok(response);
+        }
+        store.touch(uuid);
+        Ok(response.expires(store))
+    }
+    .await)
+}
+
+/// tus upload. The body is stored in pieces of at most `chunk_size`, each
+/// rolled back on its own if the connection breaks, so the client can
+/// resume from the offset a `HEAD` reports. The request that stores the
+/// last byte finalizes the upload exactly like `upload_finalize`; should
+/// that fail, an empty `PATCH` at the final offset retries it.
+#[allow(clippy::too_many_arguments)]
+#[rocket::patch("/fileupload/tus/<uuid>", data = "<data>")]
+async fn tus_patch(
+    config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    vk: &State<Parameters<VerifyingKey>>,
+    metrics: &State<Arc<Metrics>>,
+    uuid: &str,
+    headers: TusHeaders,
+    data: Data<'_>,
+) -> Tus<Result<TusResponse, Error>> {
+    use rocket::tokio::io::AsyncReadExt;
+
+    Tus(async {
+        headers.check_version()?;
+        let media_type = headers.content_type.as_deref().unwrap_or("");
+        if !media_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .eq_ignore_ascii_case("application/offset+octet-stream")
+        {
+            return Err(Error::UnsupportedMediaType(Some(
+                "Content-Type must be application/offset+octet-stream".to_owned(),
+            )));
+        }
+        let offset = parse_tus_number(headers.upload_offset.as_deref(), "Upload-Offset")?;
+        let len = parse_tus_number(headers.content_length.as_deref(), "Content-Length")?;
+
+        let state = store
+            .get(uuid)
+            .ok_or_else(|| Error::upload_session_not_found(uuid, "expired_or_unknown"))?;
+        let mut state = state.lock().await;
+        let length = check_tus_token(&state, uuid, headers.cryptify_token.as_deref())?;
+
+        if state.finalized {
+            if offset == state.uploaded && len == 0 {
+                return Ok(TusResponse::new(rocket::http::Status::NoContent)
+                    .header("Upload-Offset", state.uploaded));
+            }
+            return Err(Error::Conflict(Some(UPLOAD_FINALIZED_MSG.to_owned())));
+        }
+        if offset != state.uploaded {
+            return Err(Error::Conflict(Some(format!(
+                "Upload-Offset does not match the stored offset {}",
+                state.uploaded
+            ))));
+        }
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= length)
+            .ok_or_else(|| {
+                Error::BadRequest(Some("Upload would exceed its Upload-Length".to_owned()))
+            })?;
+        check_chunk_limits(&state, end)?;
+
+        let mut body = ChunkBody::new(data.open((len + 1).bytes()), len, "");
+        let mut written = Ok(());
+        while state.uploaded < end && written.is_ok() {
+            let start = state.uploaded;
+            let piece = (end - start).min(config.chunk_size());
+            let collect_prefix =
+                !state.header_verified && state.sealed_prefix.len() as u64 == start;
+            if collect_prefix {
+                body.keep_prefix(
+                    std::mem::take(&mut state.sealed_prefix),
+                    MAX_SEALED_HEADER_LEN,
+                );
+            }
+            let result = storage
+                .write_at(uuid, start, &mut (&mut body).take(piece))
+                .await;
+            if collect_prefix {
+                let mut prefix = body.take_prefix();
+                if result.is_err() {
+                    prefix.truncate(start as usize);
+                }
+                state.sealed_prefix = prefix;
+            }
+            written = result.map(|_| state.uploaded += piece);
+            if written.is_ok() && collect_prefix {
+                match check_sealed_prefix(&state.sealed_prefix, &vk.public_key)
+                    .await
+                    .and_then(|header| reserve_for_sender(config, store, uuid, &mut state, header))
+                {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        state.header_verified = true;
+                        state.sealed_prefix = Vec::new();
+                    }
+                    Err(e) => {
+                        drop(state);
+                        discard_upload(storage.inner().as_ref(), store, uuid).await;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        // Whatever pieces made it are kept, even when a later one broke.
+        store.save(uuid, &state);
+        written.map_err(|e| match e.kind() {
+            _ if body.failed() => Error::BadRequest(Some("Data not complete".to_owned())),
+            std::io::ErrorKind::NotFound => Error::upload_session_not_found(uuid, "file_missing"),
+            _ => {
+                log::error!("could not write tus upload {}: {}", uuid, e);
+                Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+            }
+        })?;
+        store.touch(uuid);
+
+        let response = TusResponse::new(rocket::http::Status::NoContent)
+            .header("Upload-Offset", state.uploaded);
+        if state.uploaded < length {
+            return Ok(response.expires(store));
+        }
+        let finalized = finalize_upload(
+            config,
+            storage.inner().as_ref(),
+            store,
+            vk,
+            metrics,
+            uuid,
+            state,
+        )
+        .await?;
+        let mut response = response;
+        for (header, values) in [
+            (UNSEALABLE_RECIPIENTS_HEADER, finalized.unsealable),
+            (DOWNLOAD_LINKS_HEADER, finalized.download_links),
+        ] {
+            if !values.is_empty() {
+                response = response.header(header, values.join(", "));
+            }
+        }
+        Ok(response)
+    }
+    .await)
+}
+
+/// tus termination: drop an unfinished upload and its stored bytes.
+#[rocket::delete("/fileupload/tus/<uuid>")]
+async fn tus_delete(
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    metrics: &State<Arc<Metrics>>,
+    uuid: &str,
+    headers: TusHeaders,
+) -> Tus<Result<TusResponse, Error>> {
+    Tus(async {
+        headers.check_version()?;
+        let state = store
+            .get(uuid)
+            .ok_or_else(|| Error::upload_session_not_found(uuid, "expired_or_unknown"))?;
+        let state = state.lock().await;
+        check_tus_token(&state, uuid, headers.cryptify_token.as_deref())?;
+        if state.finalized {
+            return Err(Error::Conflict(Some(UPLOAD_FINALIZED_MSG.to_owned())));
+        }
+        drop(state);
+        discard_upload(storage.inner().as_ref(), store, uuid).await;
+        metrics.record_cancelled();
+        Ok(TusResponse::new(rocket::http::Status::NoContent))
+    }
+    .await)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+struct UsageResponse {
+    email: String,
+    used_bytes: u64,
+    limit_bytes: u64,
+    window_days: u64,
+    per_upload_limit_bytes: u64,
+    resets_at: Option<String>,
+}
+
+#[get("/usage?<email>")]
+fn usage(
+    store: &State<Store>,
+    api_key: ValidatedApiKey,
+    email: Option<String>,
+) -> Json<UsageResponse> {
+    let now = chrono::offset::Utc::now().timestamp();
+    // Usage is accounted per validated tenant, keyed by the tenant proven via
+    // the `Authorization` header — never by a caller-supplied email. The
+    // `ValidatedApiKey` guard has already rejected any unauthenticated caller
+    // with 401, so there is no way to query usage for an arbitrary address and
+    // hence no user-enumeration / activity-monitoring oracle. The `email`
+    // query parameter is retained only so the response can echo it back for
+    // frontends; it does not influence the lookup.
+    let lookup_key = format!("api-key:{}", api_key.tenant);
+    let usage = store.get_usage(&lookup_key, now);
+    let resets_at = usage
+        .oldest_expires_at
+        .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+        .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+    Json(UsageResponse {
+        email: email.unwrap_or_default(),
+        used_bytes: usage.used_bytes,
+        limit_bytes: API_KEY_ROLLING_LIMIT,
+        window_days: (ROLLING_WINDOW_SECS / 86_400) as u64,
+        per_upload_limit_bytes: API_KEY_PER_UPLOAD_LIMIT,
+        resets_at,
+    })
+}
+
+/// Body returned by `GET /email-template` for a validated API key that has
+/// a template configured on pg-pkg.
+#[derive(Serialize)]
+struct EmailTemplateResponse {
     /// Tenant the API key resolved to on pg-pkg.
     tenant_id: String,
     /// The email template linked to the key.
@@ -1342,9 +2979,9 @@ fn parse_range_header(header: &str, total_size: u64) -> Option<ByteRange> {
     })
 }
 
-/// Cryptify stores upload payloads as flat UUID-named files under
-/// `data_dir`. Reject anything that could escape that or address an
-/// unintended path before touching the filesystem.
+/// Cryptify stores upload payloads under their UUID, as flat files in
+/// `data_dir` or as object keys. Reject anything that could escape that or
+/// address an unintended path before touching storage.
 fn is_safe_download_segment(name: &str) -> bool {
     !name.is_empty()
         && name.len() <= 128
@@ -1381,21 +3018,215 @@ impl<'r> Responder<'r, 'static> for RawResponse {
     }
 }
 
-#[get("/filedownload/<filename>")]
+/// Wraps a download body and counts the download once the stream reaches
+/// the end of the payload.
+struct CountingReader {
+    inner: PayloadReader,
+    counter: Option<DownloadCounter>,
+}
+
+impl rocket::tokio::io::AsyncRead for CountingReader {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut rocket::tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = self.inner.as_mut().poll_read(cx, buf);
+        if let std::task::Poll::Ready(Ok(())) = poll {
+            if buf.filled().len() == before && buf.remaining() > 0 {
+                if let Some(counter) = self.counter.take() {
+                    counter.count();
+                }
+            }
+        }
+        poll
+    }
+}
+
+/// Why [`download_gate`] refused a request.
+#[derive(Debug, PartialEq, Eq)]
+enum GateRefusal {
+    /// No proof of who the request is for, or a forged one.
+    Unauthorized,
+    /// A disclosed address that is not a recipient of the upload.
+    Forbidden,
+    /// The upload is past its expiry or has no record to tell, or the
+    /// sender revoked this link.
+    Gone,
+}
+
+impl From<GateRefusal> for rocket::http::Status {
+    fn from(refusal: GateRefusal) -> Self {
+        match refusal {
+            GateRefusal::Unauthorized => rocket::http::Status::Unauthorized,
+            GateRefusal::Forbidden => rocket::http::Status::Forbidden,
+            GateRefusal::Gone => rocket::http::Status::Gone,
+        }
+    }
+}
+
+impl From<GateRefusal> for Error {
+    fn from(refusal: GateRefusal) -> Self {
+        match refusal {
+            GateRefusal::Unauthorized => Error::Unauthorized(Some(
+                "Present the download link's token or a Yivi disclosure".to_owned(),
+            )),
+            GateRefusal::Forbidden => Error::Forbidden(Some(
+                "The disclosed address is not a recipient of this upload".to_owned(),
+            )),
+            GateRefusal::Gone => Error::Gone(Some("Upload is no longer available".to_owned())),
+        }
+    }
+}
+
+/// The recipient a request for upload `uuid` proves it is for. With
+/// `yivi_downloads`, `disclosure` must name a finished Yivi session for
+/// this upload (see `download_disclosure_start`) that disclosed one of its
+/// recipients. Otherwise, with a `download_link_secret`, `token` must be
+/// the one signed for `recipient` in their link. Either way a proven link
+/// lasts as long as the upload, not past its current expiry, and opens
+/// nothing without the upload's record to hold it to. Without either gate
+/// nobody is proven and `Ok(None)` is returned.
+///
+/// A recipient whose link the sender revoked is refused in any case.
+fn download_gate(
+    config: &CryptifyConfig,
+    yivi: Option<&Yivi>,
+    upload: Option<&FinalizedUpload>,
+    uuid: &str,
+    recipient: Option<&str>,
+    token: Option<&str>,
+    disclosure: Option<&str>,
+) -> Result<Option<String>, GateRefusal> {
+    let proven = if config.yivi_downloads() {
+        let purpose = Purpose::Download(uuid.to_owned());
+        let disclosed = disclosure
+            .zip(yivi)
+            .and_then(|(id, yivi)| yivi.disclosed(id, &purpose))
+            .ok_or(GateRefusal::Unauthorized)?;
+        if !upload.is_some_and(|upload| upload.is_recipient(&disclosed)) {
+            return Err(GateRefusal::Forbidden);
+        }
+        Some(disclosed)
+    } else if let Some(secret) = config.download_link_secret() {
+        let (recipient, token) = recipient.zip(token).ok_or(GateRefusal::Unauthorized)?;
+        if !links::verify(secret, uuid, recipient, token) {
+            return Err(GateRefusal::Unauthorized);
+        }
+        Some(recipient.to_owned())
+    } else {
+        None
+    };
+    let now = chrono::offset::Utc::now().timestamp();
+    if proven.is_some() && upload.is_none_or(|upload| upload.expires <= now) {
+        return Err(GateRefusal::Gone);
+    }
+    if upload
+        .zip(proven.as_deref().or(recipient))
+        .is_some_and(|(upload, recipient)| upload.is_link_revoked(recipient))
+    {
+        return Err(GateRefusal::Gone);
+    }
+    Ok(proven)
+}
+
+/// The headers of a download without its body, so the download page can
+/// check the file before fetching it. Does not count as a download. Gated
+/// like the download itself; see [`download_gate`].
+#[rocket::head("/filedownload/<filename>?<recipient>&<token>&<disclosure>")]
+#[allow(clippy::too_many_arguments)]
+async fn download_head(
+    filename: &str,
+    recipient: Option<&str>,
+    token: Option<&str>,
+    disclosure: Option<&str>,
+    config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    yivi: &State<Option<Yivi>>,
+) -> Result<RawResponse, rocket::http::Status> {
+    use rocket::http::Status;
+
+    if !is_safe_download_segment(filename) {
+        return Err(Status::NotFound);
+    }
+    let upload = store.finalized(filename);
+    if upload.as_ref().is_some_and(|upload| !upload.is_available()) {
+        return Err(Status::Gone);
+    }
+    download_gate(
+        config,
+        yivi.inner().as_ref(),
+        upload.as_ref(),
+        filename,
+        recipient,
+        token,
+        disclosure,
+    )?;
+    if !storage.is_finalized(filename).await.unwrap_or(false) {
+        return Err(Status::NotFound);
+    }
+    let total_size = storage.size(filename).await.map_err(|_| Status::NotFound)?;
+    Ok(RawResponse(
+        rocket::Response::build()
+            .status(Status::Ok)
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("Content-Length", total_size.to_string())
+            .finalize(),
+    ))
+}
+
+/// `recipient` is the address from the download link. Only a response that
+/// ran to the end of the payload counts as a download: a download resumed
+/// with `Range` is counted by the request that fetched its last byte.
+///
+/// With `yivi_downloads` or a `download_link_secret`, the request must
+/// prove which recipient it is for; see [`download_gate`]. Missing or
+/// forged proof → 401, a disclosed address that is not a recipient → 403,
+/// an upload past its expiry or a revoked link → 410.
+///
+/// A recipient proven either way counts once against a download limit,
+/// however often they download. Without either, `recipient` is just a
+/// claim anyone can make up, so every download counts.
+#[allow(clippy::too_many_arguments)]
+#[get("/filedownload/<filename>?<recipient>&<token>&<disclosure>")]
 async fn download(
     filename: &str,
+    recipient: Option<&str>,
+    token: Option<&str>,
+    disclosure: Option<&str>,
     range: RangeHeader,
     config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    yivi: &State<Option<Yivi>>,
 ) -> Result<RawResponse, rocket::http::Status> {
     use rocket::http::Status;
-    use std::io::SeekFrom;
 
     if !is_safe_download_segment(filename) {
         return Err(Status::NotFound);
     }
-    let path = Path::new(config.data_dir()).join(filename);
-    let mut file = File::open(&path).await.map_err(|_| Status::NotFound)?;
-    let total_size = file.metadata().await.map_err(|_| Status::NotFound)?.len();
+    let upload = store.finalized(filename);
+    if upload.as_ref().is_some_and(|upload| !upload.is_available()) {
+        return Err(Status::Gone);
+    }
+    let proven = download_gate(
+        config,
+        yivi.inner().as_ref(),
+        upload.as_ref(),
+        filename,
+        recipient,
+        token,
+        disclosure,
+    )?;
+    let proven = proven.as_deref();
+    // An upload that was never finalized may be incomplete or unverified;
+    // it does not exist as far as recipients are concerned.
+    if !storage.is_finalized(filename).await.unwrap_or(false) {
+        return Err(Status::NotFound);
+    }
+    let total_size = storage.size(filename).await.map_err(|_| Status::NotFound)?;
 
     let mut builder = rocket::Response::build();
     builder.raw_header("Accept-Ranges", "bytes");
@@ -1403,10 +3234,20 @@ async fn download(
     match range.0 {
         Some(header) => match parse_range_header(&header, total_size) {
             Some(br) => {
-                file.seek(SeekFrom::Start(br.start))
-                    .await
-                    .map_err(|_| Status::InternalServerError)?;
                 let len = br.len();
+                let body = storage
+                    .read(filename, Some(br.start..br.start + len))
+                    .await
+                    .map_err(|_| Status::NotFound)?;
+                let counter = if br.end_inclusive + 1 == total_size {
+                    store.download_counter(filename, proven)
+                } else {
+                    None
+                };
+                let body = CountingReader {
+                    inner: body,
+                    counter,
+                };
                 builder
                     .status(Status::PartialContent)
                     .raw_header(
@@ -1414,7 +3255,7 @@ async fn download(
                         format!("bytes {}-{}/{}", br.start, br.end_inclusive, total_size),
                     )
                     .raw_header("Content-Length", len.to_string())
-                    .streamed_body(file.take(len));
+                    .streamed_body(body);
             }
             None => {
                 builder
@@ -1423,60 +3264,706 @@ async fn download(
             }
         },
         None => {
+            let body = storage
+                .read(filename, None)
+                .await
+                .map_err(|_| Status::NotFound)?;
+            let body = CountingReader {
+                inner: body,
+                counter: store.download_counter(filename, proven),
+            };
             builder
                 .status(Status::Ok)
                 .raw_header("Content-Length", total_size.to_string())
-                .streamed_body(file);
+                .streamed_body(body);
         }
     }
     Ok(RawResponse(builder.finalize()))
 }
 
-/// Base Rocket figment shared by the production launch path and the integration
-/// test harness. Body-size limits are applied later in [`build_rocket`] once
-/// the merged config has been extracted (chunk_size is now configurable via
-/// TOML, so it isn't known at this point in the test path).
-pub fn default_figment() -> Figment {
-    rocket::Config::figment()
+/// Where an upload stands, as far as its download page is concerned.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum DownloadState {
+    /// Still being uploaded.
+    Pending,
+    Available,
+    /// Revoked by the sender.
+    Revoked,
+    /// Downloaded as often as the sender allowed.
+    Exhausted,
+    /// Past its expiry, awaiting deletion.
+    Expired,
 }
 
-/// Build the CORS fairing. Shared by the production launch path and the
-/// preflight smoke tests so the header allow-list under test is the one
-/// actually deployed (a test-local copy is how `X-Cryptify-Source` regressed
-/// unnoticed when the website started sending it).
-fn build_cors(allowed_origins: AllowedOrigins) -> rocket_cors::Cors {
-    CorsOptions::default()
-        .allowed_origins(allowed_origins)
-        .allowed_methods(
-            vec![Method::Get, Method::Post, Method::Put, Method::Delete]
-                .into_iter()
-                .map(From::from)
-                .collect(),
-        )
-        // Browser preflight needs to allow our custom request headers.
-        // `Authorization` is here for the Bearer-API-key tier flow;
-        // `cryptifytoken`, `content-range`, and `content-type` ride on
-        // chunk PUTs; `x-recovery-token` authenticates GET /…/status;
-        // `x-cryptify-source` tags requests for per-channel metrics.
-        .allowed_headers(AllowedHeaders::some(&[
-            "Authorization",
-            "Content-Type",
-            "Content-Range",
-            "CryptifyToken",
-            "Range",
-            "X-Cryptify-Source",
+impl DownloadState {
+    /// The state of a finalized upload at `now`.
+    fn of(upload: &FinalizedUpload, now: i64) -> Self {
+        if upload.revoked {
+            DownloadState::Revoked
+        } else if upload.expires <= now {
+            DownloadState::Expired
+        } else if !upload.is_available() {
+            DownloadState::Exhausted
+        } else {
+            DownloadState::Available
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SenderAttribute {
+    #[serde(rename = "type")]
+    atype: String,
+    value: String,
+}
+
+/// Public facts about an upload, for the download page. Everything but
+/// `state` is only known once the upload is finalized.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+struct DownloadInfo {
+    state: DownloadState,
+    size: Option<u64>,
+    expires_at: Option<String>,
+    /// The attributes the sender disclosed when signing.
+    sender_attributes: Vec<SenderAttribute>,
+    /// `None` when the sender set no download limit.
+    max_downloads: Option<u32>,
+    downloads_remaining: Option<u32>,
+}
+
+#[get("/filedownload/<uuid>/info")]
+async fn download_info(
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    uuid: &str,
+) -> Result<Json<DownloadInfo>, Error> {
+    let not_found = || Error::NotFound(Some("Upload not found".to_owned()));
+    let Some(upload) = store.finalized(uuid) else {
+        if store.get(uuid).is_none() {
+            return Err(not_found());
+        }
+        return Ok(Json(DownloadInfo {
+            state: DownloadState::Pending,
+            size: None,
+            expires_at: None,
+            sender_attributes: Vec::new(),
+            max_downloads: None,
+            downloads_remaining: None,
+        }));
+    };
+
+    let state = DownloadState::of(&upload, chrono::offset::Utc::now().timestamp());
+    let size = match upload.size {
+        Some(size) => Some(size),
+        // Records from before sizes were kept.
+        None if state == DownloadState::Available => storage.size(uuid).await.ok(),
+        None => None,
+    };
+    let expires_at = chrono::DateTime::from_timestamp(upload.expires, 0)
+        .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+    let (max_downloads, downloads_remaining) = (upload.max_downloads, upload.downloads_remaining());
+    let sender_attributes = upload
+        .mailing
+        .map(|m| m.sender_attributes)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(atype, value)| SenderAttribute { atype, value })
+        .collect();
+    Ok(Json(DownloadInfo {
+        state,
+        size,
+        expires_at,
+        sender_attributes,
+        max_downloads,
+        downloads_remaining,
+    }))
+}
+
+/// A started Yivi session. `sessionPtr` keeps the IRMA server's name, so
+/// the Yivi frontend can show it as is.
+#[derive(Serialize)]
+struct DisclosureSession {
+    session: String,
+    #[serde(rename = "sessionPtr")]
+    session_ptr: irma::Qr,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum DisclosureState {
+    /// The user has not finished the session yet.
+    Pending,
+    /// Cancelled, timed out or without a valid disclosure; start again.
+    Failed,
+    /// The disclosed address is not one the upload was sent to.
+    NotARecipient,
+    /// The session id now unlocks the download.
+    Done,
+}
+
+#[derive(Serialize)]
+struct DisclosureStatus {
+    state: DisclosureState,
+}
+
+/// The finalized, still available upload a disclosure is for, and the
+/// Yivi client, when the deployment requires a disclosure for downloads.
+fn disclosure_target<'a>(
+    config: &CryptifyConfig,
+    store: &Store,
+    yivi: &'a Option<Yivi>,
+    uuid: &str,
+) -> Result<(FinalizedUpload, &'a Yivi), Error> {
+    let Some(yivi) = yivi.as_ref().filter(|_| config.yivi_downloads()) else {
+        return Err(Error::NotFound(Some(
+            "Downloads do not require a Yivi disclosure".to_owned(),
+        )));
+    };
+    let upload = store
+        .finalized(uuid)
+        .ok_or_else(|| Error::NotFound(Some("Upload not found".to_owned())))?;
+    if !upload.is_available() {
+        return Err(Error::Gone(Some(
+            "Upload is no longer available".to_owned(),
+        )));
+    }
+    Ok((upload, yivi))
+}
+
+fn yivi_unavailable(e: irma::Error) -> Error {
+    log::error!("IRMA server request failed: {}", e);
+    Error::ServiceUnavailable(Some("Could not reach the Yivi server".to_owned()))
+}
+
+/// Start the Yivi session in which a recipient discloses their email
+/// before downloading, with `yivi_downloads` set.
+#[post("/filedownload/<uuid>/disclosure")]
+async fn download_disclosure_start(
+    config: &State<CryptifyConfig>,
+    store: &State<Store>,
+    yivi: &State<Option<Yivi>>,
+    uuid: &str,
+) -> Result<Json<DisclosureSession>, Error> {
+    let (_, yivi) = disclosure_target(config, store, yivi, uuid)?;
+    let (session, session_ptr) = yivi
+        .start(Purpose::Download(uuid.to_owned()))
+        .await
+        .map_err(yivi_unavailable)?;
+    Ok(Json(DisclosureSession {
+        session,
+        session_ptr,
+    }))
+}
+
+/// Poll a session from `download_disclosure_start`. Once `done`, the
+/// session id is passed to the download as `?disclosure=`.
+#[get("/filedownload/<uuid>/disclosure/<session>")]
+async fn download_disclosure_status(
+    config: &State<CryptifyConfig>,
+    store: &State<Store>,
+    yivi: &State<Option<Yivi>>,
+    uuid: &str,
+    session: &str,
+) -> Result<Json<DisclosureStatus>, Error> {
+    let (upload, yivi) = disclosure_target(config, store, yivi, uuid)?;
+    let outcome = yivi
+        .poll(session, &Purpose::Download(uuid.to_owned()))
+        .await
+        .map_err(yivi_unavailable)?
+        .ok_or_else(|| Error::NotFound(Some("Disclosure session not found".to_owned())))?;
+    let state = match outcome {
+        Outcome::Pending(_) => DisclosureState::Pending,
+        Outcome::Failed(_) => DisclosureState::Failed,
+        Outcome::Disclosed(email) if upload.is_recipient(&email) => DisclosureState::Done,
+        Outcome::Disclosed(_) => DisclosureState::NotARecipient,
+    };
+    Ok(Json(DisclosureStatus { state }))
+}
+
+#[derive(Serialize)]
+struct RecipientPolicies {
+    /// Hidden policy per recipient identifier, in pg_core's serialization.
+    recipients: BTreeMap<String, HiddenPolicy>,
+}
+
+/// The recipients' hidden policies from the sealed header, so the download
+/// page can start the right disclosure before downloading the payload.
+/// Where downloads are gated, the request must prove its recipient like a
+/// download does (see [`download_gate`]) and gets only their policy: the
+/// identifiers are the recipients' addresses.
+#[allow(clippy::too_many_arguments)]
+#[get("/filedownload/<uuid>/header?<recipient>&<token>&<disclosure>")]
+async fn download_header(
+    config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    vk: &State<Parameters<VerifyingKey>>,
+    yivi: &State<Option<Yivi>>,
+    uuid: &str,
+    recipient: Option<&str>,
+    token: Option<&str>,
+    disclosure: Option<&str>,
+) -> Result<Json<RecipientPolicies>, Error> {
+    let not_found = || Error::NotFound(Some("Upload not found".to_owned()));
+    let upload = store.finalized(uuid).ok_or_else(not_found)?;
+    let now = chrono::offset::Utc::now().timestamp();
+    if !upload.is_available() || upload.expires <= now {
+        return Err(GateRefusal::Gone.into());
+    }
+    let proven = download_gate(
+        config,
+        yivi.inner().as_ref(),
+        Some(&upload),
+        uuid,
+        recipient,
+        token,
+        disclosure,
+    )?;
+    let only_proven = |recipients: BTreeMap<String, HiddenPolicy>| match &proven {
+        Some(proven) => recipients
+            .into_iter()
+            .filter(|(rid, _)| rid.trim().eq_ignore_ascii_case(proven))
+            .collect(),
+        None => recipients,
+    };
+    if let Some(recipients) = upload.recipient_policies {
+        return Ok(Json(RecipientPolicies {
+            recipients: only_proven(recipients),
+        }));
+    }
+
+    // Records from before the policies were kept: read them from the
+    // payload, which was verified at finalize.
+    let file = storage.read(uuid, None).await.map_err(|_| not_found())?;
+    let mut compat = file.compat();
+    let header = unseal_header(&mut compat, &vk.public_key)
+        .await
+        .map(SealedHeader::from)
+        .map_err(|e| {
+            log::error!("stored upload {} has an unreadable header: {:?}", uuid, e);
+            Error::InternalServerError(Some(GENERIC_INTERNAL_ERROR_MSG.to_owned()))
+        })?;
+    Ok(Json(RecipientPolicies {
+        recipients: only_proven(header.recipients),
+    }))
+}
+
+#[derive(Deserialize)]
+struct ExtendBody {
+    /// The new lifetime, in seconds from now.
+    #[serde(rename = "expiresIn")]
+    expires_in: u64,
+    /// Whether to tell the recipients about the new expiry date.
+    #[serde(rename = "notifyRecipients", default)]
+    notify_recipients: bool,
+}
+
+#[derive(Serialize)]
+struct ExtendResponse {
+    expires_at: String,
+}
+
+/// Push the expiry of a finalized upload forward, up to the maximum
+/// lifetime of its tier counted from finalize. Authenticated with the
+/// upload's recovery token, an API key of the tenant that uploaded it or
+/// its sender's dashboard session; none → 401, a wrong one → 404 like an
+/// unknown upload.
+#[post("/filedownload/<uuid>/extend", data = "<request>")]
+async fn extend_upload(
+    config: &State<CryptifyConfig>,
+    store: &State<Store>,
+    uuid: &str,
+    recovery_token: Option<RecoveryTokenHeader>,
+    api_key: ApiKey,
+    dashboard: Option<DashboardSession>,
+    request: Json<ExtendBody>,
+) -> Result<Json<ExtendResponse>, Error> {
+    if recovery_token.is_none() && api_key.tenant.is_none() && dashboard.is_none() {
+        return Err(Error::Unauthorized(Some(
+            "Present the upload's recovery token, the uploading tenant's API key or a dashboard session"
+                .to_owned(),
+        )));
+    }
+    let not_found = || Error::NotFound(Some("Upload not found".to_owned()));
+    let upload = store.finalized(uuid).ok_or_else(not_found)?;
+    let by_tenant = api_key.tenant.is_some() && api_key.tenant == upload.api_key_tenant;
+    if !is_senders(&upload, recovery_token.as_ref(), dashboard.as_ref()) && !by_tenant {
+        return Err(not_found());
+    }
+    if !upload.is_available() {
+        return Err(Error::Conflict(Some(
+            "Upload has been withdrawn".to_owned(),
+        )));
+    }
+
+    let gone = || Error::Gone(Some("Upload has expired".to_owned()));
+    let now = chrono::offset::Utc::now().timestamp();
+    if upload.expires <= now {
+        return Err(gone());
+    }
+    let max = config.max_upload_lifetime_secs(upload.api_key_tenant.is_some()) as i64;
+    let latest = upload.started() + max;
+    let expires = now.saturating_add(request.expires_in.min(i64::MAX as u64) as i64);
+    if expires <= upload.expires || expires > latest {
+        return Err(Error::BadRequest(Some(format!(
+            "expiresIn must move the expiry past {} and not beyond {}",
+            upload.expires, latest
+        ))));
+    }
+    // Expired since it was read above, and possibly being reaped.
+    let upload = store.extend(uuid, expires).ok_or_else(gone)?;
+    log::info!("upload {} extended to {}", uuid, expires);
+
+    if request.notify_recipients {
+        match upload.mailing {
+            Some(mailing) => {
+                let notice = Notice::Extended { expires };
+                if let Err(e) = send_notice_email(config, &notice, &mailing, uuid).await {
+                    log::error!("could not send extension notice for {}: {}", uuid, e);
+                }
+            }
+            None => log::warn!(
+                "no recipients recorded for upload {}, nobody notified",
+                uuid
+            ),
+        }
+    }
+
+    let expires_at = chrono::DateTime::from_timestamp(expires, 0)
+        .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_default();
+    Ok(Json(ExtendResponse { expires_at }))
+}
+
+/// Revoke a finalized upload: its payload is deleted and downloads answer
+/// 410 Gone from then on. Authenticated with the upload's recovery token
+/// or its sender's dashboard session; an unknown UUID and a wrong
+/// credential are both 404, as on the status route.
+/// With `notify=true` the recipients are told the share was withdrawn.
+/// Usage counted at finalize is not refunded.
+#[rocket::delete("/filedownload/<uuid>?<notify>")]
+async fn revoke_upload(
+    config: &State<CryptifyConfig>,
+    storage: &State<Arc<dyn Storage>>,
+    store: &State<Store>,
+    uuid: &str,
+    notify: Option<bool>,
+    recovery_token: Option<RecoveryTokenHeader>,
+    dashboard: Option<DashboardSession>,
+) -> Result<(), Error> {
+    if recovery_token.is_none() && dashboard.is_none() {
+        return Err(Error::Unauthorized(Some(
+            "Present the upload's recovery token or a dashboard session".to_owned(),
+        )));
+    }
+    let not_found = || Error::NotFound(Some("Upload not found".to_owned()));
+    let upload = store.finalized(uuid).ok_or_else(not_found)?;
+    if !is_senders(&upload, recovery_token.as_ref(), dashboard.as_ref()) {
+        return Err(not_found());
+    }
+    if upload.revoked {
+        return Ok(());
+    }
+
+    store.revoke(uuid);
+    // Downloads are refused from here on; a failed delete is retried by the
+    // startup sweep and the reaper.
+    if let Err(e) = storage.delete(uuid).await {
+        log::error!("could not delete payload of revoked upload {}: {}", uuid, e);
+    }
+    log::info!("upload {} revoked by its sender", uuid);
+
+    if notify.unwrap_or(false) {
+        match upload.mailing {
+            Some(mailing) => {
+                if let Err(e) = send_notice_email(config, &Notice::Revoked, &mailing, uuid).await {
+                    log::error!("could not send revocation notice for {}: {}", uuid, e);
+                }
+            }
+            None => log::warn!(
+                "no recipients recorded for upload {}, nobody notified",
+                uuid
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Revoke the download link of one recipient of a finalized upload; the
+/// other recipients can still download. Only binding where the recipient
+/// is proven, i.e. with signed links or `yivi_downloads`. Authenticated
+/// like `revoke_upload`; an address the upload was not sent to is 404.
+#[rocket::delete("/filedownload/<uuid>/links/<recipient>")]
+fn revoke_link(
+    store: &State<Store>,
+    uuid: &str,
+    recipient: &str,
+    recovery_token: Option<RecoveryTokenHeader>,
+    dashboard: Option<DashboardSession>,
+) -> Result<(), Error> {
+    if recovery_token.is_none() && dashboard.is_none() {
+        return Err(Error::Unauthorized(Some(
+            "Present the upload's recovery token or a dashboard session".to_owned(),
+        )));
+    }
+    let not_found = || Error::NotFound(Some("Upload not found".to_owned()));
+    let upload = store.finalized(uuid).ok_or_else(not_found)?;
+    if !is_senders(&upload, recovery_token.as_ref(), dashboard.as_ref()) {
+        return Err(not_found());
+    }
+    if !upload.is_recipient(recipient) {
+        return Err(Error::NotFound(Some(
+            "The upload was not sent to this address".to_owned(),
+        )));
+    }
+    store.revoke_link(uuid, recipient);
+    log::info!("a link to upload {} revoked by its sender", uuid);
+    Ok(())
+}
+
+/// Whether the credentials prove that the caller sent `upload`: its recovery
+/// token, or a dashboard session of its sender.
+fn is_senders(
+    upload: &FinalizedUpload,
+    recovery_token: Option<&RecoveryTokenHeader>,
+    dashboard: Option<&DashboardSession>,
+) -> bool {
+    let by_token = match (recovery_token, &upload.recovery_token) {
+        (Some(presented), Some(expected)) => constant_time_eq(&presented.0, expected),
+        _ => false,
+    };
+    let by_dashboard =
+        dashboard.is_some_and(|session| upload.sender().as_ref() == Some(&session.email));
+    by_token || by_dashboard
+}
+
+/// A sender signed in to the dashboard: the `X-Dashboard-Session` header
+/// names a finished Yivi session started at `POST /dashboard/session`.
+/// Missing, unknown or expired → 401.
+struct DashboardSession {
+    /// The disclosed address, lowercased.
+    email: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DashboardSession {
+    type Error = ();
+    async fn from_request(request: &'r rocket::Request<'_>) -> rocket::request::Outcome<Self, ()> {
+        let email = request
+            .headers()
+            .get_one("X-Dashboard-Session")
+            .zip(
+                request
+                    .rocket()
+                    .state::<Option<Yivi>>()
+                    .and_then(Option::as_ref),
+            )
+            .and_then(|(id, yivi)| yivi.disclosed(id.trim(), &Purpose::Dashboard));
+        match email {
+            Some(email) => rocket::request::Outcome::Success(DashboardSession { email }),
+            None => rocket::request::Outcome::Error((rocket::http::Status::Unauthorized, ())),
+        }
+    }
+}
+
+fn dashboard_yivi(yivi: &Option<Yivi>) -> Result<&Yivi, Error> {
+    yivi.as_ref().ok_or_else(|| {
+        Error::NotFound(Some(
+            "The dashboard needs a Yivi server, which is not configured".to_owned(),
+        ))
+    })
+}
+
+/// Start the Yivi session in which a sender signs in to the dashboard by
+/// disclosing their email.
+#[post("/dashboard/session")]
+async fn dashboard_session_start(
+    yivi: &State<Option<Yivi>>,
+) -> Result<Json<DisclosureSession>, Error> {
+    let (session, session_ptr) = dashboard_yivi(yivi)?
+        .start(Purpose::Dashboard)
+        .await
+        .map_err(yivi_unavailable)?;
+    Ok(Json(DisclosureSession {
+        session,
+        session_ptr,
+    }))
+}
+
+/// Poll a session from `dashboard_session_start`. Once `done`, the session
+/// id goes in the `X-Dashboard-Session` header.
+#[get("/dashboard/session/<session>")]
+async fn dashboard_session_status(
+    yivi: &State<Option<Yivi>>,
+    session: &str,
+) -> Result<Json<DisclosureStatus>, Error> {
+    let outcome = dashboard_yivi(yivi)?
+        .poll(session, &Purpose::Dashboard)
+        .await
+        .map_err(yivi_unavailable)?
+        .ok_or_else(|| Error::NotFound(Some("Dashboard session not found".to_owned())))?;
+    let state = match outcome {
+        Outcome::Pending(_) => DisclosureState::Pending,
+        Outcome::Failed(_) => DisclosureState::Failed,
+        Outcome::Disclosed(_) => DisclosureState::Done,
+    };
+    Ok(Json(DisclosureStatus { state }))
+}
+
+/// A finalized upload as the dashboard lists it.
+#[derive(Serialize)]
+struct DashboardUpload {
+    uuid: String,
+    state: DownloadState,
+    recipients: Vec<String>,
+    size: Option<u64>,
+    finalized_at: Option<String>,
+    expires_at: Option<String>,
+    max_downloads: Option<u32>,
+    downloads: u32,
+    downloads_remaining: Option<u32>,
+    /// The recipients whose download was counted.
+    downloaded_by: Vec<String>,
+    /// The recipients whose link was revoked.
+    revoked_links: Vec<String>,
+}
+
+/// The signed-in sender's finalized uploads, newest first, until the
+/// reaper deletes them. Revoke and extend them with the dashboard session
+/// on `DELETE /filedownload/<uuid>` and `POST /filedownload/<uuid>/extend`.
+#[get("/dashboard/uploads")]
+fn dashboard_uploads(
+    store: &State<Store>,
+    session: DashboardSession,
+) -> Json<Vec<DashboardUpload>> {
+    let now = chrono::offset::Utc::now().timestamp();
+    let rfc3339 = |ts: i64| {
+        chrono::DateTime::from_timestamp(ts, 0)
+            .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+    };
+    let mut uploads = store.uploads_of(&session.email);
+    uploads.sort_by_key(|(_, upload)| std::cmp::Reverse(upload.started()));
+    Json(
+        uploads
+            .into_iter()
+            .map(|(uuid, upload)| DashboardUpload {
+                uuid,
+                state: DownloadState::of(&upload, now),
+                recipients: upload
+                    .mailing
+                    .as_ref()
+                    .map(|m| m.recipients.iter().map(|mb| mb.email.to_string()).collect())
+                    .unwrap_or_default(),
+                size: upload.size,
+                finalized_at: upload.finalized_at.and_then(rfc3339),
+                expires_at: rfc3339(upload.expires),
+                max_downloads: upload.max_downloads,
+                downloads: upload.downloads,
+                downloads_remaining: upload.downloads_remaining(),
+                downloaded_by: upload.downloaded_by,
+                revoked_links: upload.revoked_links,
+            })
+            .collect(),
+    )
+}
+
+/// Base Rocket figment shared by the production launch path and the integration
+/// test harness.
+pub fn default_figment() -> Figment {
+    rocket::Config::figment()
+}
+
+/// Build the CORS fairing. Shared by the production launch path and the
+/// preflight smoke tests so the header allow-list under test is the one
+/// actually deployed (a test-local copy is how `X-Cryptify-Source` regressed
+/// unnoticed when the website started sending it).
+fn build_cors(allowed_origins: AllowedOrigins) -> rocket_cors::Cors {
+    CorsOptions::default()
+        .allowed_origins(allowed_origins)
+        .allowed_methods(
+            vec![
+                Method::Get,
+                Method::Head,
+                Method::Post,
+                Method::Put,
+                Method::Patch,
+                Method::Delete,
+            ]
+            .into_iter()
+            .map(From::from)
+            .collect(),
+        )
+        // Browser preflight needs to allow our custom request headers.
+        // `Authorization` is here for the Bearer-API-key tier flow;
+        // `CryptifyToken`, `Content-Range` and `Content-Type` ride on chunk
+        // PUTs, and `ChunkHash` on parallel ones; `Range` resumes
+        // downloads; `X-Recovery-Token` and `X-Dashboard-Session`
+        // authenticate the sender's status, extend and revoke requests;
+        // `X-Cryptify-Source` tags requests for per-channel metrics.
+        .allowed_headers(AllowedHeaders::some(&[
+            "Authorization",
+            "ChunkHash",
+            "Content-Type",
+            "Content-Range",
+            "CryptifyToken",
+            "Range",
+            "X-Cryptify-Source",
             "X-Recovery-Token",
+            "X-Dashboard-Session",
             // Browser clients (pg-js) send this on every request; without it
             // in the preflight allowlist the browser blocks cross-origin
             // uploads. Captured for the per-app upload metric + logs.
             "X-POSTGUARD-CLIENT-VERSION",
+            // The tus endpoints.
+            "Tus-Resumable",
+            "Upload-Length",
+            "Upload-Metadata",
+            "Upload-Offset",
         ]))
-        .expose_headers(["cryptifytoken"].iter().map(ToString::to_string).collect())
+        .expose_headers(
+            [
+                "cryptifytoken",
+                "Location",
+                UNSEALABLE_RECIPIENTS_HEADER,
+                DOWNLOAD_LINKS_HEADER,
+                "Tus-Resumable",
+                "Tus-Version",
+                "Tus-Extension",
+                "Tus-Max-Size",
+                "Upload-Expires",
+                "Upload-Length",
+                "Upload-Offset",
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        )
         .max_age(Some(86400))
         .to_cors()
         .expect("unable to configure CORS")
 }
 
+/// The payload store selected by `storage_backend`. An unusable `[s3]`
+/// table is a deployment error and panics, like a malformed config.
+fn build_storage(config: &CryptifyConfig) -> Arc<dyn Storage> {
+    match config.storage_backend() {
+        StorageBackend::Local => Arc::new(LocalStorage::new(config.data_dir())),
+        StorageBackend::S3 => {
+            let s3 = config
+                .s3()
+                .expect("storage_backend = \"s3\" requires an [s3] table")
+                .clone();
+            let endpoint = s3.endpoint.clone();
+            Arc::new(
+                S3Storage::new(s3)
+                    .unwrap_or_else(|e| panic!("Invalid s3.endpoint {}: {}", endpoint, e)),
+            )
+        }
+    }
+}
+
 /// Build a Rocket instance from a pre-loaded config figment and verifying key.
 ///
 /// Extracted so integration tests can inject their own figment (temp data_dir,
@@ -1487,30 +3974,47 @@ pub fn build_rocket(figment: Figment, vk: Parameters<VerifyingKey>) -> Rocket<Bu
         .extract::<CryptifyConfig>()
         .expect("Missing configuration");
 
-    // Raise Rocket's default body-size limits so chunked uploads up to
-    // chunk_size do not trip "Data limit reached while reading the request
-    // body". `data.open((end - start).bytes())` already caps the per-request
-    // read; this lifts the framework-level cap that runs before it.
-    // A small headroom above chunk_size leaves room for HTTP overhead.
-    let chunk_size = config.chunk_size();
-    let limits = rocket::data::Limits::default()
-        .limit("bytes", (chunk_size + 1024 * 1024).bytes())
-        .limit("data-form", (chunk_size + 1024 * 1024).bytes())
-        .limit("file", (chunk_size + 1024 * 1024).bytes());
-
-    let rocket = rocket::custom(figment.merge(("limits", limits)));
+    // Chunk bodies are streamed with an explicit per-request limit from
+    // `chunk_size`, so Rocket's default body-size limits stay as they are:
+    // no route buffers a chunk-sized body.
+    let rocket = rocket::custom(figment);
 
     let cors = build_cors(AllowedOrigins::some_regex(&[config.allowed_origins()]));
 
+    let storage = build_storage(&config);
+
     let metrics = Arc::new(Metrics::new());
     rocket::tokio::spawn(storage_sampler(
         metrics.clone(),
-        std::path::PathBuf::from(config.data_dir()),
+        storage.clone(),
         Duration::from_secs(config.metrics_scan_interval_secs()),
     ));
 
     let pkg_client = PkgClient::new(config.pkg_url().to_string());
 
+    let store = Store::with_idle_ttl(
+        std::time::Duration::from_secs(config.session_ttl_secs()),
+        metrics.clone(),
+        config.usage_db(),
+        Some(storage.clone()),
+    );
+    store.set_max_upload_lifetime(
+        config
+            .max_upload_lifetime_secs(true)
+            .max(config.max_upload_lifetime_secs(false)) as i64,
+    );
+    store.spawn_retention_reaper(Duration::from_secs(config.retention_scan_interval_secs()));
+    let (receipts, receipts_rx) = rocket::tokio::sync::mpsc::unbounded_channel();
+    store.set_receipt_sender(receipts);
+    rocket::tokio::spawn(receipt_mailer(config.clone(), receipts_rx));
+
+    let yivi = config.irma().map(|irma| {
+        Yivi::new(irma, config.email_attribute()).unwrap_or_else(|e| {
+            log::error!("invalid [irma] configuration: {}", e);
+            panic!("invalid [irma] configuration: {}", e)
+        })
+    });
+
     rocket
         .attach(cors)
         .mount(
@@ -1520,22 +4024,47 @@ pub fn build_rocket(figment: Figment, vk: Parameters<VerifyingKey>) -> Rocket<Bu
                 metrics_endpoint,
                 upload_init,
                 upload_chunk,
+                upload_chunk_at,
                 upload_finalize,
+                upload_single,
                 upload_status,
+                upload_cancel,
+                tus_options,
+                tus_create,
+                tus_head,
+                tus_patch,
+                tus_delete,
                 usage,
                 email_template,
                 download,
+                download_head,
+                download_info,
+                download_header,
+                download_disclosure_start,
+                download_disclosure_status,
+                extend_upload,
+                revoke_upload,
+                revoke_link,
+                dashboard_session_start,
+                dashboard_session_status,
+                dashboard_uploads,
                 staging_preview
             ],
         )
         .attach(AdHoc::config::<CryptifyConfig>())
-        .manage(Store::with_idle_ttl(
-            std::time::Duration::from_secs(config.session_ttl_secs()),
-            metrics.clone(),
-            config.usage_db(),
-        ))
+        // Before the first request, so a fresh upload is never mistaken for
+        // a crash leftover.
+        .attach(AdHoc::on_ignite("Startup sweep", |rocket| async {
+            if let Some(store) = rocket.state::<Store>() {
+                store.sweep_partial_uploads().await;
+            }
+            rocket
+        }))
+        .manage(store)
+        .manage(storage)
         .manage(vk)
         .manage(pkg_client)
+        .manage(yivi)
         .manage(metrics)
 }
 
@@ -1769,6 +4298,40 @@ mod tests {
         );
     }
 
+    async fn read_chunk_body(
+        body: &[u8],
+        len: u64,
+    ) -> (std::io::Result<Vec<u8>>, ChunkBody<&[u8]>) {
+        use rocket::tokio::io::AsyncReadExt;
+
+        let mut reader = ChunkBody::new(body, len, "token");
+        let mut out = Vec::new();
+        let result = reader.read_to_end(&mut out).await.map(|_| out);
+        (result, reader)
+    }
+
+    #[rocket::async_test]
+    async fn chunk_body_hashes_like_compute_hash() {
+        let (result, reader) = read_chunk_body(b"data", 4).await;
+        assert_eq!(result.unwrap(), b"data");
+        assert!(!reader.failed());
+        assert_eq!(reader.finish(), compute_hash(b"token", b"data"));
+    }
+
+    #[rocket::async_test]
+    async fn chunk_body_rejects_short_and_long_bodies() {
+        let (result, reader) = read_chunk_body(b"dat", 4).await;
+        assert_eq!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+        assert!(reader.failed());
+
+        let (result, reader) = read_chunk_body(b"data!", 4).await;
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert!(reader.failed());
+    }
+
     #[test]
     fn cryptify_tokens_match_accepts_equal_and_rejects_different() {
         assert!(cryptify_tokens_match("abc123", "abc123"));
@@ -1831,6 +4394,7 @@ mod tests {
         let rocket = rocket::custom(figment)
             .mount("/", routes![upload_init])
             .attach(AdHoc::config::<CryptifyConfig>())
+            .manage(Arc::new(LocalStorage::new(data_dir)) as Arc<dyn Storage>)
             .manage(Store::new(Arc::new(Metrics::new())));
 
         Client::tracked(rocket).await.expect("valid rocket")
@@ -1920,6 +4484,7 @@ mod tests {
         let rocket = rocket::custom(figment)
             .mount("/", routes![upload_init, upload_status])
             .attach(AdHoc::config::<CryptifyConfig>())
+            .manage(Arc::new(LocalStorage::new(data_dir)) as Arc<dyn Storage>)
             .manage(Store::new(Arc::new(Metrics::new())));
 
         Client::tracked(rocket).await.expect("valid rocket")
@@ -1951,6 +4516,7 @@ mod tests {
             .attach(cors)
             .mount("/", routes![upload_init, upload_status])
             .attach(AdHoc::config::<CryptifyConfig>())
+            .manage(Arc::new(LocalStorage::new(data_dir)) as Arc<dyn Storage>)
             .manage(Store::new(Arc::new(Metrics::new())));
 
         Client::tracked(rocket).await.expect("valid rocket")
@@ -2193,35 +4759,78 @@ mod tests {
         let _ = std::fs::remove_dir_all(&data_dir);
     }
 
-    // Design AC for #146: a successful `/status` call must reset the idle
-    // eviction deadline (otherwise rehydrate succeeds, then the very next
-    // chunk PUT 404s because the session aged out between the GET and the
-    // PUT). Inspect the deadline directly via the test-only accessor.
+    // Parallel chunk PUTs carry `ChunkHash`; without it in the allow-list a
+    // browser never gets past the preflight of the first chunk.
     #[rocket::async_test]
-    async fn status_extends_eviction_deadline() {
+    async fn parallel_chunk_preflight_advertises_chunk_hash() {
         let data_dir = std::env::temp_dir().join(format!(
             "cryptify-test-{}",
             uuid::Uuid::new_v4().hyphenated()
         ));
-        let client = status_client(&data_dir).await;
-
-        let (uuid, recovery_token) = init_upload(&client).await;
-
-        let store = client.rocket().state::<Store>().expect("Store managed");
-        let before = store
-            .deadline_for(&uuid)
-            .expect("session has a deadline after init");
-
-        // tokio::time::Instant has millisecond resolution on most
-        // platforms; sleep enough that a fresh `now() + ttl` is strictly
-        // later than the value captured at init.
-        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+        let client = status_client_with_cors(&data_dir).await;
 
         let res = client
-            .get(format!("/fileupload/{}/status", uuid))
-            .header(Header::new("X-Recovery-Token", recovery_token))
-            .dispatch()
-            .await;
+            .req(
+                rocket::http::Method::Options,
+                format!("/fileupload/{}/chunks/0", uuid::Uuid::new_v4()),
+            )
+            .header(Header::new("Origin", "https://example.com"))
+            .header(Header::new("Access-Control-Request-Method", "PUT"))
+            .header(Header::new(
+                "Access-Control-Request-Headers",
+                "Content-Type, CryptifyToken, ChunkHash",
+            ))
+            .dispatch()
+            .await;
+
+        assert!(
+            res.status().code < 400,
+            "expected 2xx preflight, got {}",
+            res.status()
+        );
+        let allow_headers = res
+            .headers()
+            .get_one("Access-Control-Allow-Headers")
+            .expect("CORS allow-headers in preflight response")
+            .to_ascii_lowercase();
+        assert!(
+            allow_headers.contains("chunkhash"),
+            "Access-Control-Allow-Headers `{}` should include chunkhash",
+            allow_headers
+        );
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    // Design AC for #146: a successful `/status` call must reset the idle
+    // eviction deadline (otherwise rehydrate succeeds, then the very next
+    // chunk PUT 404s because the session aged out between the GET and the
+    // PUT). Inspect the deadline directly via the test-only accessor.
+    #[rocket::async_test]
+    async fn status_extends_eviction_deadline() {
+        let data_dir = std::env::temp_dir().join(format!(
+            "cryptify-test-{}",
+            uuid::Uuid::new_v4().hyphenated()
+        ));
+        let client = status_client(&data_dir).await;
+
+        let (uuid, recovery_token) = init_upload(&client).await;
+
+        let store = client.rocket().state::<Store>().expect("Store managed");
+        let before = store
+            .deadline_for(&uuid)
+            .expect("session has a deadline after init");
+
+        // tokio::time::Instant has millisecond resolution on most
+        // platforms; sleep enough that a fresh `now() + ttl` is strictly
+        // later than the value captured at init.
+        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let res = client
+            .get(format!("/fileupload/{}/status", uuid))
+            .header(Header::new("X-Recovery-Token", recovery_token))
+            .dispatch()
+            .await;
         assert_eq!(res.status(), Status::Ok);
 
         let after = store
@@ -2293,6 +4902,15 @@ mod tests {
             api_key_validation_failed: false,
             last_chunk: None,
             recovery_token: String::new(),
+            finalized: false,
+            reservation: None,
+            header_verified: false,
+            sealed_prefix: Vec::new(),
+            chunked: None,
+            upload_length: None,
+            declared_size: None,
+            max_downloads: None,
+            unsealable_recipients: Vec::new(),
         }
     }
 
@@ -2321,7 +4939,12 @@ mod tests {
     #[test]
     fn classify_normal_next_chunk() {
         let state = empty_filestate(100, "tok-current");
-        match classify_chunk_request(&state, "tok-current", 100, b"chunk") {
+        match classify_chunk_request(
+            &state,
+            "tok-current",
+            100,
+            &compute_hash(b"tok-current", b"chunk"),
+        ) {
             ChunkClassification::NormalNext => {}
             _ => panic!("expected NormalNext"),
         }
@@ -2333,7 +4956,7 @@ mod tests {
         let last = last_chunk_for("tok-prev", 100, body);
         let response_token = last.response_token.clone();
         let state = filestate_with_last_chunk(100 + body.len() as u64, &response_token, last);
-        match classify_chunk_request(&state, "tok-prev", 100, body) {
+        match classify_chunk_request(&state, "tok-prev", 100, &compute_hash(b"tok-prev", body)) {
             ChunkClassification::ReplayLastChunk(t) => assert_eq!(t, response_token),
             _ => panic!("expected ReplayLastChunk"),
         }
@@ -2346,7 +4969,12 @@ mod tests {
         let response_token = last.response_token.clone();
         let state = filestate_with_last_chunk(100 + body.len() as u64, &response_token, last);
         let tampered = b"tampered";
-        let result = classify_chunk_request(&state, "tok-prev", 100, tampered);
+        let result = classify_chunk_request(
+            &state,
+            "tok-prev",
+            100,
+            &compute_hash(b"tok-prev", tampered),
+        );
         match result {
             ChunkClassification::Reject(Error::BadRequest(Some(msg))) => {
                 assert!(msg.contains("body differs"), "got: {}", msg);
@@ -2364,7 +4992,12 @@ mod tests {
         let last = last_chunk_for("tok-prev", 100, body);
         let response_token = last.response_token.clone();
         let state = filestate_with_last_chunk(100 + body.len() as u64, &response_token, last);
-        let result = classify_chunk_request(&state, "tok-prev", 100, b"short");
+        let result = classify_chunk_request(
+            &state,
+            "tok-prev",
+            100,
+            &compute_hash(b"tok-prev", b"short"),
+        );
         match result {
             ChunkClassification::Reject(Error::BadRequest(Some(msg))) => {
                 assert!(msg.contains("body differs"), "got: {}", msg);
@@ -2377,7 +5010,12 @@ mod tests {
     fn classify_rejects_offset_mismatch_with_no_replay() {
         // No last_chunk recorded → offset mismatch is just the regular 400.
         let state = empty_filestate(100, "tok-current");
-        let result = classify_chunk_request(&state, "tok-current", 50, b"abc");
+        let result = classify_chunk_request(
+            &state,
+            "tok-current",
+            50,
+            &compute_hash(b"tok-current", b"abc"),
+        );
         match result {
             ChunkClassification::Reject(Error::BadRequest(Some(msg))) => {
                 assert_eq!(msg, "Incorrect Content-Range header");
@@ -2389,7 +5027,12 @@ mod tests {
     #[test]
     fn classify_rejects_token_mismatch_at_correct_offset() {
         let state = empty_filestate(100, "tok-current");
-        let result = classify_chunk_request(&state, "tok-wrong", 100, b"chunk");
+        let result = classify_chunk_request(
+            &state,
+            "tok-wrong",
+            100,
+            &compute_hash(b"tok-wrong", b"chunk"),
+        );
         match result {
             ChunkClassification::Reject(Error::BadRequest(Some(msg))) => {
                 assert_eq!(msg, TOKEN_MISMATCH_MSG);
@@ -2406,7 +5049,12 @@ mod tests {
         let last = last_chunk_for("tok-prev", 100, body);
         let response_token = last.response_token.clone();
         let state = filestate_with_last_chunk(100 + body.len() as u64, &response_token, last);
-        let result = classify_chunk_request(&state, "tok-something-else", 100, body);
+        let result = classify_chunk_request(
+            &state,
+            "tok-something-else",
+            100,
+            &compute_hash(b"tok-something-else", body),
+        );
         match result {
             ChunkClassification::Reject(Error::BadRequest(Some(msg))) => {
                 assert_eq!(msg, "Incorrect Content-Range header");
@@ -2590,11 +5238,20 @@ mod tests {
 
         let rocket = rocket::custom(figment)
             .mount("/", routes![download])
-            .attach(AdHoc::config::<CryptifyConfig>());
+            .attach(AdHoc::config::<CryptifyConfig>())
+            .manage(Store::new(Arc::new(Metrics::new())))
+            .manage(None::<Yivi>)
+            .manage(Arc::new(LocalStorage::new(data_dir)) as Arc<dyn Storage>);
 
         Client::tracked(rocket).await.expect("valid rocket")
     }
 
+    /// Lay out a payload the way a finalized upload leaves it on disk.
+    fn write_finalized(data_dir: &std::path::Path, name: &str, body: &[u8]) {
+        std::fs::write(data_dir.join(name), body).unwrap();
+        std::fs::write(data_dir.join(format!("{}.finalized", name)), b"").unwrap();
+    }
+
     fn fresh_data_dir() -> std::path::PathBuf {
         std::env::temp_dir().join(format!("cryptify-dl-{}", uuid::Uuid::new_v4().hyphenated()))
     }
@@ -2604,7 +5261,7 @@ mod tests {
         let data_dir = fresh_data_dir();
         let client = download_client(&data_dir).await;
         let body: Vec<u8> = (0u8..100).collect();
-        std::fs::write(data_dir.join("file1"), &body).unwrap();
+        write_finalized(&data_dir, "file1", &body);
 
         let res = client.get("/filedownload/file1").dispatch().await;
         assert_eq!(res.status(), Status::Ok);
@@ -2625,7 +5282,7 @@ mod tests {
         let data_dir = fresh_data_dir();
         let client = download_client(&data_dir).await;
         let body: Vec<u8> = (0u8..100).collect();
-        std::fs::write(data_dir.join("file1"), &body).unwrap();
+        write_finalized(&data_dir, "file1", &body);
 
         let res = client
             .get("/filedownload/file1")
@@ -2650,7 +5307,7 @@ mod tests {
         let data_dir = fresh_data_dir();
         let client = download_client(&data_dir).await;
         let body: Vec<u8> = (0u8..100).collect();
-        std::fs::write(data_dir.join("file1"), &body).unwrap();
+        write_finalized(&data_dir, "file1", &body);
 
         let res = client
             .get("/filedownload/file1")
@@ -2673,7 +5330,7 @@ mod tests {
         let data_dir = fresh_data_dir();
         let client = download_client(&data_dir).await;
         let body: Vec<u8> = (0u8..100).collect();
-        std::fs::write(data_dir.join("file1"), &body).unwrap();
+        write_finalized(&data_dir, "file1", &body);
 
         let res = client
             .get("/filedownload/file1")
@@ -2696,7 +5353,7 @@ mod tests {
         let data_dir = fresh_data_dir();
         let client = download_client(&data_dir).await;
         let body: Vec<u8> = (0u8..100).collect();
-        std::fs::write(data_dir.join("file1"), &body).unwrap();
+        write_finalized(&data_dir, "file1", &body);
 
         let res = client
             .get("/filedownload/file1")
@@ -2720,6 +5377,24 @@ mod tests {
         let _ = std::fs::remove_dir_all(&data_dir);
     }
 
+    #[rocket::async_test]
+    async fn download_without_finalized_marker_returns_404() {
+        let data_dir = fresh_data_dir();
+        let client = download_client(&data_dir).await;
+        std::fs::write(data_dir.join("file1"), b"complete but unverified").unwrap();
+        std::fs::write(data_dir.join("file2.part"), b"still uploading").unwrap();
+
+        for name in ["file1", "file2", "file2.part"] {
+            let res = client
+                .get(format!("/filedownload/{}", name))
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::NotFound, "{}", name);
+        }
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
     #[rocket::async_test]
     async fn download_rejects_path_traversal() {
         let data_dir = fresh_data_dir();
@@ -2786,6 +5461,15 @@ mod tests {
                 api_key_validation_failed: false,
                 last_chunk: None,
                 recovery_token: String::new(),
+                finalized: true,
+                reservation: None,
+                header_verified: false,
+                sealed_prefix: Vec::new(),
+                chunked: None,
+                upload_length: None,
+                declared_size: None,
+                max_downloads: None,
+                unsealable_recipients: Vec::new(),
             };
             store.create(uuid.to_owned(), state);
         }
@@ -2903,11 +5587,26 @@ mod integration {
     /// Seal `payload` for the encryption policy from `TestSetup`, producing a
     /// byte stream that `Unsealer` (and therefore `upload_finalize`) accepts.
     async fn seal_payload(setup: &TestSetup, payload: &[u8]) -> Vec<u8> {
+        seal_payload_as(setup, 2, payload).await // Bob: email + name
+    }
+
+    /// [`seal_payload`], signed with `setup.signing_keys[signer]`.
+    async fn seal_payload_as(setup: &TestSetup, signer: usize, payload: &[u8]) -> Vec<u8> {
+        seal_payload_for(setup, &setup.policy, signer, payload).await
+    }
+
+    /// [`seal_payload_as`], sealed for `policy` instead of `setup.policy`.
+    async fn seal_payload_for(
+        setup: &TestSetup,
+        policy: &pg_core::identity::EncryptionPolicy,
+        signer: usize,
+        payload: &[u8],
+    ) -> Vec<u8> {
         let mut rng = rand08::thread_rng();
-        let signing_key = &setup.signing_keys[2]; // Bob: email + name
+        let signing_key = &setup.signing_keys[signer];
         let mut input = futures::io::Cursor::new(payload.to_vec());
         let mut sealed = Vec::new();
-        Sealer::<_, SealerStreamConfig>::new(&setup.ibe_pk, &setup.policy, signing_key, &mut rng)
+        Sealer::<_, SealerStreamConfig>::new(&setup.ibe_pk, policy, signing_key, &mut rng)
             .expect("build sealer")
             .seal(&mut input, &mut sealed)
             .await
@@ -3126,25 +5825,30 @@ mod integration {
         let final_status = do_finalize(&client, &uuid, &token, sealed.len() as u64).await;
         assert_eq!(final_status, Status::Ok);
 
+        // Finalize hands the upload to the retention reaper with the same
+        // expiry the notification email quotes.
+        let store = client.rocket().state::<Store>().expect("Store managed");
+        let record = store.finalized(&uuid).expect("retention record");
+        let now = chrono::offset::Utc::now().timestamp();
+        assert!((record.expires - (now + UPLOAD_LIFETIME_SECS)).abs() < 60);
+
         let _ = std::fs::remove_dir_all(dir);
     }
 
-    /// Finalizing a session whose bytes are not a valid postguard stream makes
-    /// the `Unsealer` fail, driving `upload_finalize` down its 500 path. The
-    /// response body must carry only the generic message — never the internal
-    /// diagnostic detail, which now goes to the server log (GHSA-r95f-qf3j-xccw).
+    /// Finalizing a stream signed by a sender without an email attribute
+    /// drives `upload_finalize` down its 500 path. The response body must
+    /// carry only the generic message — never the internal diagnostic
+    /// detail, which now goes to the server log (GHSA-r95f-qf3j-xccw).
     #[rocket::async_test]
     async fn finalize_internal_error_body_is_generic() {
         let mut rng = rand08::thread_rng();
         let setup = TestSetup::new(&mut rng);
         let (client, dir) = test_client(&setup).await;
 
-        // Upload arbitrary bytes: the chunk path only advances the rolling
-        // token, it does not validate the postguard framing.
-        let garbage = b"this is not a postguard sealed stream";
+        let sealed = seal_payload_as(&setup, 1, b"signed with a BSN only").await;
         let (uuid, token, status) = do_init(&client, SENDER_EMAIL).await;
         assert_eq!(status, Status::Ok);
-        let (chunk_status, next) = do_chunk(&client, &uuid, &token, garbage, 0).await;
+        let (chunk_status, next) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
         assert_eq!(chunk_status, Status::Ok);
 
         let res = client
@@ -3152,7 +5856,7 @@ mod integration {
             .header(Header::new("CryptifyToken", next))
             .header(Header::new(
                 "Content-Range",
-                format!("bytes */{}", garbage.len()),
+                format!("bytes */{}", sealed.len()),
             ))
             .dispatch()
             .await;
@@ -3169,147 +5873,2566 @@ mod integration {
         let _ = std::fs::remove_dir_all(dir);
     }
 
+    /// Length of the preamble, header and header signature of `sealed`, i.e.
+    /// the offset of the first encrypted segment.
+    fn sealed_header_len(sealed: &[u8]) -> usize {
+        let header_len = u32::from_be_bytes(sealed[6..10].try_into().unwrap()) as usize;
+        let sig_at = pg_core::PREAMBLE_SIZE + header_len;
+        let sig_len = u32::from_be_bytes(sealed[sig_at..sig_at + 4].try_into().unwrap()) as usize;
+        sig_at + 4 + sig_len
+    }
+
+    /// Upload `bytes` in one chunk and finalize them, returning the
+    /// finalize status and body.
+    async fn upload_and_finalize(client: &Client, bytes: &[u8]) -> (String, Status, String) {
+        let (uuid, token, status) = do_init(client, "alice@example.com").await;
+        assert_eq!(status, Status::Ok);
+        let (status, token) = do_chunk(client, &uuid, &token, bytes, 0).await;
+        assert_eq!(status, Status::Ok);
+        let res = client
+            .post(format!("/fileupload/finalize/{}", uuid))
+            .header(Header::new("CryptifyToken", token))
+            .header(Header::new(
+                "Content-Range",
+                format!("bytes */{}", bytes.len()),
+            ))
+            .dispatch()
+            .await;
+        let status = res.status();
+        (uuid, status, res.into_string().await.unwrap_or_default())
+    }
+
+    /// Every corrupted tail is turned away with a 422 and its reason, and
+    /// the upload is gone: nothing was mailed, nothing is downloadable.
     #[rocket::async_test]
-    async fn upload_records_client_app_metric() {
+    async fn finalize_rejects_corrupted_sealed_streams() {
         let mut rng = rand08::thread_rng();
         let setup = TestSetup::new(&mut rng);
-        let sealed = seal_payload(&setup, b"hello metric test").await;
+        let (client, dir) = test_client(&setup).await;
+
+        let small = seal_payload(&setup, b"one segment").await;
+        let header_len = sealed_header_len(&small);
+        // One full segment plus a short final one.
+        let segment = pg_core::SYMMETRIC_CRYPTO_DEFAULT_CHUNK as usize;
+        let large = seal_payload(&setup, &vec![7u8; segment + 100]).await;
+        let full_segment = segment + SIG_BYTES + TAG_SIZE;
+
+        let mut appended = large.clone();
+        appended.extend_from_slice(&vec![
+            0u8;
+            full_segment
+                - (large.len() - header_len - full_segment)
+        ]);
+
+        // A bad header is normally caught by the first chunk already (see
+        // `upload_chunk_rejects_non_postguard_prefix`); one that never
+        // arrived whole is caught here.
+        let cases: Vec<(&str, Vec<u8>, &str)> = vec![
+            (
+                "header only",
+                small[..header_len].to_vec(),
+                BAD_SEGMENTS_MSG,
+            ),
+            (
+                "cut inside the first segment",
+                small[..header_len + 20].to_vec(),
+                BAD_SEGMENTS_MSG,
+            ),
+            (
+                "cut at a segment boundary",
+                large[..header_len + full_segment].to_vec(),
+                BAD_SEGMENTS_MSG,
+            ),
+            ("padded to a segment boundary", appended, BAD_SEGMENTS_MSG),
+            (
+                "cut inside the header",
+                small[..header_len - 10].to_vec(),
+                BAD_HEADER_MSG,
+            ),
+        ];
+        for (what, bytes, reason) in cases {
+            let (uuid, status, body) = upload_and_finalize(&client, &bytes).await;
+            assert_eq!(status, Status::UnprocessableEntity, "{what}");
+            assert_eq!(body, reason, "{what}");
+            assert!(!dir.join(&uuid).exists(), "{what}: payload is deleted");
+            assert!(!dir.join(format!("{}.finalized", uuid)).exists(), "{what}");
+            assert_eq!(
+                do_finalize(&client, &uuid, "", bytes.len() as u64).await,
+                Status::NotFound,
+                "{what}: the session is dropped"
+            );
+        }
+
+        // The intact streams still go through.
+        for sealed in [small, large] {
+            let (_, status, _) = upload_and_finalize(&client, &sealed).await;
+            assert_eq!(status, Status::Ok);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
 
+    /// A chunk that shows the upload is not a PostGuard stream ends the
+    /// session on the spot, wherever in the header the problem sits.
+    #[rocket::async_test]
+    async fn upload_chunk_rejects_non_postguard_prefix() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
         let (client, dir) = test_client(&setup).await;
 
-        // Init carrying a client-version header whose `app` field is pg-js.
+        let sealed = seal_payload(&setup, b"checked early").await;
+        let header_len = sealed_header_len(&sealed);
+        let mut bad_header_sig = sealed.clone();
+        bad_header_sig[header_len - 5] ^= 0x01;
+        let mut tampered_header = sealed.clone();
+        tampered_header[pg_core::PREAMBLE_SIZE + 1] ^= 0x01;
+        let mut huge_header = sealed.clone();
+        huge_header[6..10].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let cases: Vec<(&str, Vec<u8>, &str)> = vec![
+            (
+                "garbage",
+                b"not a postguard stream".to_vec(),
+                NOT_POSTGUARD_MSG,
+            ),
+            (
+                "bad prelude",
+                vec![pg_core::PRELUDE[0], 0],
+                NOT_POSTGUARD_MSG,
+            ),
+            ("oversized header", huge_header, BAD_HEADER_MSG),
+            (
+                "tampered header signature",
+                bad_header_sig,
+                BAD_SIGNATURE_MSG,
+            ),
+            ("tampered header", tampered_header, BAD_SIGNATURE_MSG),
+        ];
+        for (what, bytes, reason) in cases {
+            let (uuid, token, _) = do_init(&client, SENDER_EMAIL).await;
+            let res = client
+                .put(format!("/fileupload/{}", uuid))
+                .header(Header::new("CryptifyToken", token.clone()))
+                .header(Header::new(
+                    "Content-Range",
+                    format!("bytes 0-{}/*", bytes.len()),
+                ))
+                .body(&bytes)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::UnprocessableEntity, "{what}");
+            assert_eq!(res.into_string().await.unwrap(), reason, "{what}");
+            assert!(
+                !dir.join(format!("{}.part", uuid)).exists(),
+                "{what}: the partial file is deleted"
+            );
+            let (status, _) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+            assert_eq!(status, Status::NotFound, "{what}: the session is gone");
+        }
+
+        // A header split over several chunks is checked once it is whole:
+        // the garbage after a good header is not the header's problem.
+        let (uuid, mut token, _) = do_init(&client, SENDER_EMAIL).await;
+        for (start, end) in [(0, 3), (3, 12), (12, header_len)] {
+            let (status, next) =
+                do_chunk(&client, &uuid, &token, &sealed[start..end], start as u64).await;
+            assert_eq!(status, Status::Ok);
+            token = next;
+        }
+        let (status, _) = do_chunk(&client, &uuid, &token, b"no segments", header_len as u64).await;
+        assert_eq!(status, Status::Ok);
+
+        // But a split header that turns out bad is still refused.
+        let (uuid, token, _) = do_init(&client, SENDER_EMAIL).await;
+        let (status, token) = do_chunk(&client, &uuid, &token, &sealed[..12], 0).await;
+        assert_eq!(status, Status::Ok);
+        let mut rest = sealed[12..header_len].to_vec();
+        rest[0] ^= 0x01;
+        let (status, _) = do_chunk(&client, &uuid, &token, &rest, 12).await;
+        assert_eq!(status, Status::UnprocessableEntity);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Init asking for parallel mode. Returns the uuid, the token, the
+    /// recovery token and the chunk size if parallel mode was granted.
+    async fn init_parallel(client: &Client) -> (String, String, String, Option<u64>) {
         let res = client
             .post("/fileupload/init")
             .header(ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "recipient": "alice@example.com",
+                    "mailContent": "hello",
+                    "mailLang": "EN",
+                    "confirm": false,
+                    "uploadMode": "parallel",
+                })
+                .to_string(),
+            )
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let chunk_size = match body["upload_mode"].as_str() {
+            Some("parallel") => Some(body["chunk_size"].as_u64().unwrap()),
+            other => {
+                assert_eq!(other, Some("sequential"));
+                None
+            }
+        };
+        (
+            body["uuid"].as_str().unwrap().to_owned(),
+            token,
+            body["recovery_token"].as_str().unwrap().to_owned(),
+            chunk_size,
+        )
+    }
+
+    /// PUT chunk `index` of a parallel upload, with `hash` as its ChunkHash.
+    async fn put_indexed<'c>(
+        client: &'c Client,
+        uuid: &str,
+        token: &str,
+        index: u64,
+        start: u64,
+        chunk: &[u8],
+        hash: &str,
+    ) -> rocket::local::asynchronous::LocalResponse<'c> {
+        client
+            .put(format!("/fileupload/{}/chunks/{}", uuid, index))
+            .header(Header::new("CryptifyToken", token.to_owned()))
             .header(Header::new(
-                "X-POSTGUARD-CLIENT-VERSION",
-                "node,22.1.0,pg-js,1.2.3",
+                "Content-Range",
+                format!("bytes {}-{}/*", start, start + chunk.len() as u64),
             ))
-            .body(init_body_json(SENDER_EMAIL))
+            .header(Header::new("ChunkHash", hash.to_owned()))
+            .body(chunk)
+            .dispatch()
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn parallel_upload_accepts_concurrent_out_of_order_chunks() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let payload: Vec<u8> = (0..20_000).map(|i| (i % 251) as u8).collect();
+        let sealed = seal_payload(&setup, &payload).await;
+        let (figment, dir) = test_figment();
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment.merge(("chunk_size", 4096u64)), vk))
+            .await
+            .expect("valid rocket");
+
+        let (uuid, token, recovery_token, chunk_size) = init_parallel(&client).await;
+        assert_eq!(chunk_size, Some(4096));
+        let chunks: Vec<&[u8]> = sealed.chunks(4096).collect();
+        let last = chunks.len() - 1;
+
+        let requests = chunks[1..].iter().enumerate().rev().map(|(i, chunk)| {
+            let index = i as u64 + 1;
+            let hash = compute_hash(b"", chunk);
+            let (client, uuid, token) = (&client, &uuid, &token);
+            async move {
+                let res = put_indexed(client, uuid, token, index, index * 4096, chunk, &hash).await;
+                assert_eq!(res.status(), Status::Ok, "chunk {index}");
+                assert_eq!(res.headers().get_one("cryptifytoken"), Some(token.as_str()));
+            }
+        });
+        futures::future::join_all(requests).await;
+
+        assert_eq!(
+            do_finalize(&client, &uuid, &token, (sealed.len() - 4096) as u64).await,
+            Status::UnprocessableEntity,
+            "chunk 0 is missing"
+        );
+        let res = client
+            .get(format!("/fileupload/{}/status", uuid))
+            .header(Header::new("X-Recovery-Token", recovery_token))
             .dispatch()
             .await;
+        let status: serde_json::Value = res.into_json().await.unwrap();
+        assert_eq!(
+            status["received_chunks"],
+            serde_json::json!([[1, last + 1]])
+        );
+
+        let res = put_indexed(
+            &client,
+            &uuid,
+            &token,
+            0,
+            0,
+            chunks[0],
+            &compute_hash(b"", chunks[0]),
+        )
+        .await;
+        assert_eq!(res.status(), Status::Ok);
+        // Sending a chunk again replaces it.
+        let res = put_indexed(
+            &client,
+            &uuid,
+            &token,
+            last as u64,
+            last as u64 * 4096,
+            chunks[last],
+            &compute_hash(b"", chunks[last]),
+        )
+        .await;
         assert_eq!(res.status(), Status::Ok);
-        let mut token = res
-            .headers()
-            .get_one("cryptifytoken")
-            .map(|s| s.to_string())
-            .unwrap_or_default();
-        let body = res.into_string().await.unwrap_or_default();
-        let uuid = serde_json::from_str::<serde_json::Value>(&body)
-            .ok()
-            .and_then(|v| v.get("uuid").and_then(|u| u.as_str().map(String::from)))
-            .unwrap_or_default();
-        assert!(!uuid.is_empty());
 
-        let (chunk_status, next) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
-        assert_eq!(chunk_status, Status::Ok);
-        token = next;
+        assert_eq!(
+            do_finalize(&client, &uuid, &token, sealed.len() as u64).await,
+            Status::Ok
+        );
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
 
-        let final_status = do_finalize(&client, &uuid, &token, sealed.len() as u64).await;
-        assert_eq!(final_status, Status::Ok);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[rocket::async_test]
+    async fn parallel_upload_rejects_bad_chunks() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, &[7u8; 10_000]).await;
+        let (figment, dir) = test_figment();
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment.merge(("chunk_size", 4096u64)), vk))
+            .await
+            .expect("valid rocket");
+        let (uuid, token, _, _) = init_parallel(&client).await;
+        let first = &sealed[..4096];
+        let second = &sealed[4096..8192];
+
+        let res = put_indexed(
+            &client,
+            &uuid,
+            &token,
+            1,
+            4096,
+            second,
+            &compute_hash(b"", first),
+        )
+        .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        assert_eq!(
+            res.into_string().await.unwrap(),
+            "Chunk does not match its ChunkHash"
+        );
+        let res = put_indexed(
+            &client,
+            &uuid,
+            &token,
+            2,
+            4096,
+            second,
+            &compute_hash(b"", second),
+        )
+        .await;
+        assert_eq!(res.status(), Status::BadRequest, "offset of another index");
+        let res = put_indexed(
+            &client,
+            &uuid,
+            &token,
+            1,
+            4096,
+            &second[..10],
+            &compute_hash(b"", &second[..10]),
+        )
+        .await;
+        assert_eq!(res.status(), Status::Ok, "a short chunk can be the last");
+        let res = put_indexed(
+            &client,
+            &uuid,
+            &token,
+            0,
+            0,
+            &first[..10],
+            &compute_hash(b"", &first[..10]),
+        )
+        .await;
+        assert_eq!(
+            res.status(),
+            Status::BadRequest,
+            "but nothing before it may be short"
+        );
+        let (status, _) = do_chunk(&client, &uuid, &token, first, 0).await;
+        assert_eq!(
+            status,
+            Status::BadRequest,
+            "no sequential chunks in parallel mode"
+        );
+
+        // A non-PostGuard chunk 0 ends the upload like it does sequentially.
+        let garbage = vec![0u8; 4096];
+        let res = put_indexed(
+            &client,
+            &uuid,
+            &token,
+            0,
+            0,
+            &garbage,
+            &compute_hash(b"", &garbage),
+        )
+        .await;
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        assert!(!dir.join(format!("{}.part", uuid)).exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A chunk whose write failed counts as missing until it is sent again,
+    /// and finalize names it.
+    #[rocket::async_test]
+    async fn parallel_finalize_names_a_chunk_whose_write_failed() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, &[7u8; 10_000]).await;
+        let (figment, dir) = test_figment();
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment.merge(("chunk_size", 4096u64)), vk))
+            .await
+            .expect("valid rocket");
+        let (uuid, token, _, _) = init_parallel(&client).await;
+
+        for (index, chunk) in sealed.chunks(4096).enumerate() {
+            let index = index as u64;
+            let hash = compute_hash(b"", chunk);
+            let res = put_indexed(&client, &uuid, &token, index, index * 4096, chunk, &hash).await;
+            assert_eq!(res.status(), Status::Ok);
+        }
+        // Sent again, but broken off by a bad body.
+        let second = &sealed[4096..8192];
+        let res = put_indexed(&client, &uuid, &token, 1, 4096, second, "00").await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client
+            .post(format!("/fileupload/finalize/{}", uuid))
+            .header(Header::new("CryptifyToken", token.clone()))
+            .header(Header::new(
+                "Content-Range",
+                format!("bytes */{}", sealed.len()),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        assert_eq!(
+            res.into_string().await.unwrap(),
+            "Upload is missing chunk 1"
+        );
+
+        let hash = compute_hash(b"", second);
+        let res = put_indexed(&client, &uuid, &token, 1, 4096, second, &hash).await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(
+            do_finalize(&client, &uuid, &token, sealed.len() as u64).await,
+            Status::Ok
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A `multipart/form-data` body with the given parts, and its boundary.
+    fn multipart_body(parts: &[(&str, &[u8])]) -> (ContentType, Vec<u8>) {
+        let boundary = "cryptify-test-boundary";
+        let mut body = Vec::new();
+        for (name, content) in parts {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                    boundary, name
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        let content_type =
+            ContentType::new("multipart", "form-data").with_params(("boundary", boundary));
+        (content_type, body)
+    }
+
+    #[rocket::async_test]
+    async fn single_request_upload_runs_the_whole_pipeline() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"one round trip").await;
+        let (client, dir) = test_client(&setup).await;
+
+        let metadata = init_body_json(SENDER_EMAIL);
+        let (content_type, body) =
+            multipart_body(&[("metadata", metadata.as_bytes()), ("file", &sealed)]);
+        let res = client
+            .post("/fileupload/single")
+            .header(content_type)
+            .body(body)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let uuid = res.into_json::<serde_json::Value>().await.unwrap()["uuid"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let store = client.rocket().state::<Store>().expect("Store managed");
+        assert!(store.finalized(&uuid).is_some());
+        let state = store.get(&uuid).unwrap();
+        assert_eq!(state.lock().await.sender.as_deref(), Some(SENDER_EMAIL));
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[rocket::async_test]
+    async fn single_request_upload_leaves_nothing_behind_on_errors() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, &[7u8; 5000]).await;
+        let (figment, dir) = test_figment();
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment.merge(("chunk_size", 4096u64)), vk))
+            .await
+            .expect("valid rocket");
+        let metadata = init_body_json(SENDER_EMAIL);
+
+        type Parts<'a> = &'a [(&'a str, &'a [u8])];
+        let cases: [(Parts, Status); 5] = [
+            (
+                &[("metadata", metadata.as_bytes()), ("file", &sealed)],
+                Status::BadRequest,
+            ),
+            (
+                &[("file", &sealed[..100]), ("metadata", metadata.as_bytes())],
+                Status::BadRequest,
+            ),
+            (
+                &[("metadata", b"{}"), ("file", &sealed[..100])],
+                Status::BadRequest,
+            ),
+            (
+                &[("metadata", metadata.as_bytes()), ("file", &[0u8; 100])],
+                Status::UnprocessableEntity,
+            ),
+            (
+                &[
+                    ("metadata", metadata.as_bytes()),
+                    ("file", &sealed[..100]),
+                    ("file", &sealed[100..200]),
+                ],
+                Status::BadRequest,
+            ),
+        ];
+        for (i, (parts, expected)) in cases.iter().enumerate() {
+            let (content_type, body) = multipart_body(parts);
+            let res = client
+                .post("/fileupload/single")
+                .header(content_type)
+                .body(body)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), *expected, "case {i}");
+        }
+
+        let (_, mut truncated) =
+            multipart_body(&[("metadata", metadata.as_bytes()), ("file", &sealed[..3000])]);
+        truncated.truncate(truncated.len() - 40);
+        let (content_type, _) = multipart_body(&[]);
+        let res = client
+            .post("/fileupload/single")
+            .header(content_type)
+            .body(truncated)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest, "truncated body");
+        let res = client
+            .post("/fileupload/single")
+            .header(ContentType::JSON)
+            .body(metadata)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::UnsupportedMediaType);
+
+        assert_eq!(
+            std::fs::read_dir(&dir).unwrap().count(),
+            0,
+            "no payload survives a failed request"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// An `Upload-Metadata` header for `pairs`.
+    fn tus_metadata(pairs: &[(&str, &str)]) -> String {
+        use base64::Engine;
+        pairs
+            .iter()
+            .map(|(key, value)| {
+                format!(
+                    "{} {}",
+                    key,
+                    base64::engine::general_purpose::STANDARD.encode(value)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Create a tus upload of `length` bytes, returning its upload URL and
+    /// the token every request on it must carry.
+    async fn tus_create_upload(client: &Client, length: usize) -> (String, String) {
+        let res = client
+            .post("/fileupload/tus")
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("Upload-Length", length.to_string()))
+            .header(Header::new(
+                "Upload-Metadata",
+                tus_metadata(&[
+                    ("recipient", SENDER_EMAIL),
+                    ("mailContent", "hello"),
+                    ("mailLang", "EN"),
+                ]),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Created);
+        assert_eq!(res.headers().get_one("Tus-Resumable"), Some("1.0.0"));
+        assert!(res.headers().get_one("Upload-Expires").is_some());
+        (
+            res.headers().get_one("Location").unwrap().to_owned(),
+            res.headers().get_one("CryptifyToken").unwrap().to_owned(),
+        )
+    }
+
+    async fn tus_patch_at<'c>(
+        client: &'c Client,
+        url: &str,
+        token: &str,
+        offset: usize,
+        body: &[u8],
+    ) -> rocket::local::asynchronous::LocalResponse<'c> {
+        client
+            .patch(url.to_owned())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("CryptifyToken", token.to_owned()))
+            .header(Header::new("Upload-Offset", offset.to_string()))
+            .header(Header::new(
+                "Content-Type",
+                "application/offset+octet-stream",
+            ))
+            .header(Header::new("Content-Length", body.len().to_string()))
+            .body(body)
+            .dispatch()
+            .await
+    }
+
+    async fn tus_offset(client: &Client, url: &str, token: &str) -> Option<String> {
+        let res = client
+            .head(url.to_owned())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("CryptifyToken", token.to_owned()))
+            .dispatch()
+            .await;
+        assert_eq!(res.headers().get_one("Cache-Control"), Some("no-store"));
+        res.headers().get_one("Upload-Offset").map(str::to_owned)
+    }
+
+    /// A tus client drives an ordinary upload session: the last PATCH
+    /// finalizes it like `upload_finalize` would.
+    #[rocket::async_test]
+    async fn tus_upload_is_finalized_by_its_last_patch() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, &[7u8; 20_000]).await;
+        let (figment, dir) = test_figment();
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment.merge(("chunk_size", 4096u64)), vk))
+            .await
+            .expect("valid rocket");
+
+        let res = client
+            .req(rocket::http::Method::Options, "/fileupload/tus")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NoContent);
+        assert_eq!(res.headers().get_one("Tus-Version"), Some("1.0.0"));
+        assert_eq!(
+            res.headers().get_one("Tus-Extension"),
+            Some("creation,termination,expiration")
+        );
+        assert_eq!(
+            res.headers().get_one("Tus-Max-Size"),
+            Some(PER_UPLOAD_LIMIT.to_string().as_str())
+        );
+
+        let (url, token) = tus_create_upload(&client, sealed.len()).await;
+        let uuid = url.strip_prefix("/fileupload/tus/").unwrap();
+        assert!(
+            uuid::Uuid::parse_str(uuid).is_ok(),
+            "the URL holds no token"
+        );
+        let token = token.as_str();
+        assert_eq!(tus_offset(&client, &url, token).await.as_deref(), Some("0"));
+
+        // Spans several `chunk_size` pieces.
+        let res = tus_patch_at(&client, &url, token, 0, &sealed[..10_000]).await;
+        assert_eq!(res.status(), Status::NoContent);
+        assert_eq!(res.headers().get_one("Upload-Offset"), Some("10000"));
+        assert!(res.headers().get_one("Upload-Expires").is_some());
+        assert_eq!(
+            tus_offset(&client, &url, token).await.as_deref(),
+            Some("10000")
+        );
+
+        let res = tus_patch_at(&client, &url, token, 0, &sealed[..10]).await;
+        assert_eq!(res.status(), Status::Conflict, "offset is behind");
+        let res = client
+            .patch(url.clone())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("CryptifyToken", token.to_owned()))
+            .header(Header::new("Upload-Offset", "10000"))
+            .header(ContentType::Binary)
+            .header(Header::new("Content-Length", "10"))
+            .body(&sealed[10_000..10_010])
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::UnsupportedMediaType);
+        let res = client
+            .head(url.clone())
+            .header(Header::new("Tus-Resumable", "0.2.2"))
+            .header(Header::new("CryptifyToken", token.to_owned()))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PreconditionFailed);
+        assert_eq!(res.headers().get_one("Tus-Version"), Some("1.0.0"));
+        let (status, _) = do_chunk(&client, uuid, token, &sealed[10_000..], 10_000).await;
+        assert_eq!(status, Status::BadRequest, "no cryptify chunks on tus");
+
+        let res = tus_patch_at(&client, &url, token, 10_000, &sealed[10_000..]).await;
+        assert_eq!(res.status(), Status::NoContent);
+        let store = client.rocket().state::<Store>().expect("Store managed");
+        assert!(store.finalized(uuid).is_some());
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
+
+        // A retried last PATCH is answered again; more bytes are not.
+        let res = tus_patch_at(&client, &url, token, sealed.len(), b"").await;
+        assert_eq!(res.status(), Status::NoContent);
+        let res = tus_patch_at(&client, &url, token, sealed.len(), b"x").await;
+        assert_eq!(res.status(), Status::Conflict);
+        let res = client
+            .delete(url.clone())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("CryptifyToken", token.to_owned()))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict, "finalized uploads stay");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[rocket::async_test]
+    async fn tus_rejects_bad_uploads_and_terminates() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let (client, dir) = test_client(&setup).await;
+
+        let create = |length: String, metadata: String| {
+            client
+                .post("/fileupload/tus")
+                .header(Header::new("Tus-Resumable", "1.0.0"))
+                .header(Header::new("Upload-Length", length))
+                .header(Header::new("Upload-Metadata", metadata))
+                .dispatch()
+        };
+        let complete = tus_metadata(&[
+            ("recipient", SENDER_EMAIL),
+            ("mailContent", "hello"),
+            ("mailLang", "EN"),
+        ]);
+        let res = create("100".into(), tus_metadata(&[("recipient", SENDER_EMAIL)])).await;
+        assert_eq!(res.status(), Status::BadRequest);
+        assert_eq!(
+            res.into_string().await.unwrap(),
+            "Upload-Metadata is missing mailContent"
+        );
+        let res = create((PER_UPLOAD_LIMIT + 1).to_string(), complete.clone()).await;
+        assert_eq!(res.status(), Status::PayloadTooLarge);
+        let res = create("0".into(), complete.clone()).await;
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = create("100".into(), format!("{},confirm dHJ1ZQ==", complete)).await;
+        assert_eq!(res.status(), Status::Created, "confirm is optional");
+
+        let (url, token) = tus_create_upload(&client, 100).await;
+        let token = token.as_str();
+        let uuid = url.split('/').nth(3).unwrap().to_owned();
+        let res = client
+            .head(url.clone())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("CryptifyToken", "0".repeat(64)))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound, "wrong token");
+        let res = client
+            .head(url.clone())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound, "no token");
+        let res = tus_patch_at(&client, &url, token, 0, &[0u8; 101]).await;
+        assert_eq!(res.status(), Status::BadRequest, "past Upload-Length");
+
+        let sealed = seal_payload(&setup, b"terminated").await;
+        let res = tus_patch_at(&client, &url, token, 0, &sealed[..10]).await;
+        assert_eq!(res.status(), Status::NoContent, "header still incomplete");
+        let res = client
+            .delete(url.clone())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("CryptifyToken", token.to_owned()))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NoContent);
+        assert_eq!(res.headers().get_one("Tus-Resumable"), Some("1.0.0"));
+        assert!(!dir.join(format!("{}.part", uuid)).exists());
+        let res = client
+            .head(url.clone())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("CryptifyToken", token.to_owned()))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        // A non-PostGuard upload ends as soon as its prefix gives it away.
+        let (url, token) = tus_create_upload(&client, 100).await;
+        let token = token.as_str();
+        let uuid = url.split('/').nth(3).unwrap().to_owned();
+        let res = tus_patch_at(&client, &url, token, 0, &[0u8; 100]).await;
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        assert!(!dir.join(format!("{}.part", uuid)).exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[rocket::async_test]
+    async fn upload_records_client_app_metric() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"hello metric test").await;
+
+        let (client, dir) = test_client(&setup).await;
+
+        // Init carrying a client-version header whose `app` field is pg-js.
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .header(Header::new(
+                "X-POSTGUARD-CLIENT-VERSION",
+                "node,22.1.0,pg-js,1.2.3",
+            ))
+            .body(init_body_json(SENDER_EMAIL))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let mut token = res
+            .headers()
+            .get_one("cryptifytoken")
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        let body = res.into_string().await.unwrap_or_default();
+        let uuid = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|v| v.get("uuid").and_then(|u| u.as_str().map(String::from)))
+            .unwrap_or_default();
+        assert!(!uuid.is_empty());
+
+        let (chunk_status, next) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        assert_eq!(chunk_status, Status::Ok);
+        token = next;
+
+        let final_status = do_finalize(&client, &uuid, &token, sealed.len() as u64).await;
+        assert_eq!(final_status, Status::Ok);
+
+        // The finalized upload is attributed to app="pg-js".
+        let metrics = client
+            .get("/metrics")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap_or_default();
+        assert!(
+            metrics.contains("cryptify_uploads_by_app_total{app=\"pg-js\"} 1"),
+            "expected pg-js app counter in metrics:\n{metrics}"
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    // Minimal Rocket exposing only /metrics, with the given config managed so
+    // the MetricsAuth guard can read `metrics_token`. Avoids needing a real
+    // VerifyingKey / TestSetup.
+    fn metrics_only_config(with_token: bool) -> CryptifyConfig {
+        let (figment, _dir) = test_figment();
+        let figment = if with_token {
+            figment.merge(("metrics_token", "s3cret"))
+        } else {
+            figment
+        };
+        figment.extract::<CryptifyConfig>().expect("extract config")
+    }
+
+    async fn metrics_only_client(config: CryptifyConfig) -> Client {
+        let rocket = rocket::build()
+            .mount("/", routes![metrics_endpoint])
+            .manage(config)
+            .manage(std::sync::Arc::new(Metrics::new()));
+        Client::tracked(rocket).await.expect("valid rocket")
+    }
+
+    #[rocket::async_test]
+    async fn metrics_requires_bearer_when_token_configured() {
+        let client = metrics_only_client(metrics_only_config(true)).await;
+
+        // No Authorization header → 401.
+        assert_eq!(
+            client.get("/metrics").dispatch().await.status(),
+            Status::Unauthorized
+        );
+        // Wrong token → 401.
+        assert_eq!(
+            client
+                .get("/metrics")
+                .header(Header::new("Authorization", "Bearer wrong"))
+                .dispatch()
+                .await
+                .status(),
+            Status::Unauthorized
+        );
+        // Correct token → 200 with the metrics body.
+        let ok = client
+            .get("/metrics")
+            .header(Header::new("Authorization", "Bearer s3cret"))
+            .dispatch()
+            .await;
+        assert_eq!(ok.status(), Status::Ok);
+        assert!(ok
+            .into_string()
+            .await
+            .unwrap_or_default()
+            .contains("cryptify_uploads_total"));
+    }
+
+    #[rocket::async_test]
+    async fn metrics_open_when_token_unset() {
+        let client = metrics_only_client(metrics_only_config(false)).await;
+        assert_eq!(client.get("/metrics").dispatch().await.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn upload_happy_path_multi_chunk() {
+        // Two chunks >1 MiB to exercise the rolling token chain across
+        // multiple PUTs. Keeps payload well under CHUNK_SIZE.
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let payload: Vec<u8> = (0..(2 * 1024 * 1024 + 17))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let sealed = seal_payload(&setup, &payload).await;
+
+        let (client, dir) = test_client(&setup).await;
+
+        let (uuid, mut token, _) = do_init(&client, SENDER_EMAIL).await;
+
+        let split = sealed.len() / 2;
+        let (s1, next1) = do_chunk(&client, &uuid, &token, &sealed[..split], 0).await;
+        assert_eq!(s1, Status::Ok);
+        token = next1;
+
+        let (s2, next2) = do_chunk(&client, &uuid, &token, &sealed[split..], split as u64).await;
+        assert_eq!(s2, Status::Ok);
+        token = next2;
+
+        let final_status = do_finalize(&client, &uuid, &token, sealed.len() as u64).await;
+        assert_eq!(final_status, Status::Ok);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A redeploy between two chunks must not lose the session: with
+    /// `usage_db` configured the second process reloads it, `/status` hands
+    /// the rolling token back, and the upload completes as usual.
+    #[rocket::async_test]
+    async fn upload_resumes_across_restart() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let payload: Vec<u8> = (0..(64 * 1024)).map(|i| (i % 251) as u8).collect();
+        let sealed = seal_payload(&setup, &payload).await;
+        let split = sealed.len() / 2;
+
+        let (figment, dir) = test_figment();
+        let figment = figment.merge((
+            "usage_db",
+            dir.join("state.db").to_string_lossy().to_string(),
+        ));
+        let vk = || Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+
+        let (uuid, recovery_token) = {
+            let client = Client::tracked(build_rocket(figment.clone(), vk()))
+                .await
+                .expect("valid rocket");
+            let res = client
+                .post("/fileupload/init")
+                .header(ContentType::JSON)
+                .body(init_body_json(SENDER_EMAIL))
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok);
+            let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+            let body: serde_json::Value = res.into_json().await.unwrap();
+            let uuid = body["uuid"].as_str().unwrap().to_owned();
+            let (status, _) = do_chunk(&client, &uuid, &token, &sealed[..split], 0).await;
+            assert_eq!(status, Status::Ok);
+            (uuid, body["recovery_token"].as_str().unwrap().to_owned())
+            // client (and its Store) dropped here — the pod goes away.
+        };
+
+        let client = Client::tracked(build_rocket(figment, vk()))
+            .await
+            .expect("valid rocket");
+        let res = client
+            .get(format!("/fileupload/{}/status", uuid))
+            .header(Header::new("X-Recovery-Token", recovery_token))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let status: serde_json::Value = res.into_json().await.unwrap();
+        assert_eq!(status["uploaded"].as_u64(), Some(split as u64));
+        let token = status["cryptify_token"].as_str().unwrap().to_owned();
+
+        let (s2, token) = do_chunk(&client, &uuid, &token, &sealed[split..], split as u64).await;
+        assert_eq!(s2, Status::Ok);
+        let final_status = do_finalize(&client, &uuid, &token, sealed.len() as u64).await;
+        assert_eq!(final_status, Status::Ok);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// The whole upload flow against an S3-compatible bucket: chunks go into
+    /// a multipart upload, finalize reads the sealed header back from the
+    /// bucket, and downloads (ranged or not) are served from the object.
+    #[rocket::async_test]
+    async fn upload_and_download_through_s3_backend() {
+        use crate::storage::s3::fake::FakeS3;
+
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let payload: Vec<u8> = (0..(64 * 1024)).map(|i| (i % 251) as u8).collect();
+        let sealed = seal_payload(&setup, &payload).await;
+
+        let s3 = FakeS3::start().await;
+        let config = s3.config("uploads/");
+        let (figment, dir) = test_figment();
+        let figment = figment.merge(("storage_backend", "s3")).merge((
+            "s3",
+            serde_json::json!({
+                "endpoint": config.endpoint,
+                "bucket": config.bucket,
+                "access_key": config.access_key,
+                "secret_key": config.secret_key,
+                "prefix": config.prefix,
+            }),
+        ));
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment, vk))
+            .await
+            .expect("valid rocket");
+
+        let (uuid, mut token, status) = do_init(&client, "alice@example.com").await;
+        assert_eq!(status, Status::Ok);
+        let mut offset = 0;
+        for chunk in sealed.chunks(sealed.len() / 3 + 1) {
+            let (status, next) = do_chunk(&client, &uuid, &token, chunk, offset).await;
+            assert_eq!(status, Status::Ok);
+            token = next;
+            offset += chunk.len() as u64;
+        }
+        assert_eq!(
+            do_finalize(&client, &uuid, &token, sealed.len() as u64).await,
+            Status::Ok
+        );
+
+        assert_eq!(
+            s3.object(&format!("uploads/{}", uuid)).as_deref(),
+            Some(&sealed[..])
+        );
+        assert_eq!(s3.in_flight_uploads(), 0);
+        assert!(!dir.join(&uuid).exists(), "nothing is written to data_dir");
+
+        // S3 can only append, so parallel mode is not granted.
+        let (_, _, _, mode) = init_parallel(&client).await;
+        assert_eq!(mode, None);
+
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .header(Header::new("Range", "bytes=100-199"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PartialContent);
+        assert_eq!(res.into_bytes().await.unwrap(), &sealed[100..200]);
+
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Every byte being on disk is not enough: until finalize has verified
+    /// the payload, the download link must look like it does not exist.
+    #[rocket::async_test]
+    async fn download_is_refused_until_finalize_succeeds() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"not yet").await;
+        let (client, dir) = test_client(&setup).await;
+
+        let (uuid, token, status) = do_init(&client, "alice@example.com").await;
+        assert_eq!(status, Status::Ok);
+        let (status, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        assert_eq!(status, Status::Ok);
+
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        assert_eq!(
+            do_finalize(&client, &uuid, &token, sealed.len() as u64).await,
+            Status::Ok
+        );
+        assert!(dir.join(format!("{}.finalized", uuid)).exists());
+        assert!(!dir.join(format!("{}.part", uuid)).exists());
+
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Only the recovery token handed out at init revokes an upload; after
+    /// that its link answers 410 Gone, while its usage stays booked.
+    #[rocket::async_test]
+    async fn sender_revokes_a_finalized_upload() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"take it back").await;
+        let (client, dir) = test_client(&setup).await;
+
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(init_body_json("alice@example.com"))
+            .dispatch()
+            .await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value =
+            serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let recovery_token = body["recovery_token"].as_str().unwrap().to_owned();
+
+        let revoke = |token: &str| {
+            client
+                .delete(format!("/filedownload/{}?notify=true", uuid))
+                .header(Header::new("X-Recovery-Token", token.to_owned()))
+        };
+        assert_eq!(
+            revoke(&recovery_token).dispatch().await.status(),
+            Status::NotFound,
+            "an unfinished upload cannot be revoked"
+        );
+
+        let (status, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        assert_eq!(status, Status::Ok);
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let res = client
+            .delete(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+        assert_eq!(
+            revoke(&token).dispatch().await.status(),
+            Status::NotFound,
+            "the cryptifytoken does not revoke"
+        );
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        assert_eq!(
+            revoke(&recovery_token).dispatch().await.status(),
+            Status::Ok
+        );
+        assert!(!dir.join(&uuid).exists());
+        assert!(!dir.join(format!("{}.finalized", uuid)).exists());
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Gone);
+        assert_eq!(
+            revoke(&recovery_token).dispatch().await.status(),
+            Status::Ok,
+            "revoking twice succeeds"
+        );
+
+        let store = client.rocket().state::<Store>().unwrap();
+        let usage = store.get_usage(SENDER_EMAIL, chrono::Utc::now().timestamp());
+        assert_eq!(usage.used_bytes, total, "usage is not refunded");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// The recovery token extends a finalized upload, but only forward and
+    /// not past the maximum lifetime counted from finalize.
+    #[rocket::async_test]
+    async fn sender_extends_the_expiry_of_an_upload() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"a little longer").await;
+        let (client, dir) = test_client(&setup).await;
+
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(init_body_json("alice@example.com"))
+            .dispatch()
+            .await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value =
+            serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let recovery_token = body["recovery_token"].as_str().unwrap().to_owned();
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        assert_eq!(
+            do_finalize(&client, &uuid, &token, sealed.len() as u64).await,
+            Status::Ok
+        );
+
+        let extend = |token: Option<&str>, secs: i64| {
+            let mut req = client
+                .post(format!("/filedownload/{}/extend", uuid))
+                .header(ContentType::JSON)
+                .body(
+                    serde_json::json!({ "expiresIn": secs, "notifyRecipients": true }).to_string(),
+                );
+            if let Some(token) = token {
+                req = req.header(Header::new("X-Recovery-Token", token.to_owned()));
+            }
+            req
+        };
+        const DAY: i64 = 86_400;
+        let status = |res: rocket::local::asynchronous::LocalResponse<'_>| res.status();
+
+        assert_eq!(
+            status(extend(None, 7 * DAY).dispatch().await),
+            Status::Unauthorized
+        );
+        assert_eq!(
+            status(extend(Some(&token), 7 * DAY).dispatch().await),
+            Status::NotFound
+        );
+        assert_eq!(
+            status(extend(Some(&recovery_token), 7 * DAY).dispatch().await),
+            Status::BadRequest,
+            "an extension cannot shorten the lifetime"
+        );
+        assert_eq!(
+            status(extend(Some(&recovery_token), 15 * DAY).dispatch().await),
+            Status::BadRequest,
+            "nor go past the maximum lifetime"
+        );
+
+        // Pretend a nine-day upload was finalized a week ago: it can gain
+        // the five days left of the maximum lifetime.
+        let store = client.rocket().state::<Store>().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let upload = store.finalized(&uuid).unwrap();
+        store.record_finalized(
+            &uuid,
+            FinalizedUpload {
+                finalized_at: Some(now - 7 * DAY),
+                expires: now + 2 * DAY,
+                ..upload
+            },
+        );
+        assert_eq!(
+            status(
+                extend(Some(&recovery_token), 7 * DAY + 3600)
+                    .dispatch()
+                    .await
+            ),
+            Status::BadRequest
+        );
+        let res = extend(Some(&recovery_token), 7 * DAY - 3600)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        assert!(body["expires_at"].as_str().is_some());
+        let expires = store.finalized(&uuid).unwrap().expires;
+        assert!((expires - (now + 7 * DAY - 3600)).abs() < 60);
+
+        // Once expired, the reaper may be deleting it: too late to extend.
+        store.extend(&uuid, now - 1);
+        assert_eq!(
+            status(extend(Some(&recovery_token), DAY).dispatch().await),
+            Status::Gone
+        );
+        assert_eq!(store.finalized(&uuid).unwrap().expires, now - 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Cancelling drops the session and its partial payload at once, with
+    /// either the recovery token or the current cryptify token, and counts
+    /// as a cancellation rather than an expiry.
+    #[rocket::async_test]
+    async fn sender_cancels_an_unfinished_upload() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"never mind").await;
+        let (client, dir) = test_client(&setup).await;
+        let cancel = |uuid: &str, header: Option<(&str, &str)>| {
+            let mut req = client.delete(format!("/fileupload/{}", uuid));
+            if let Some((name, value)) = header {
+                req = req.header(Header::new(name.to_owned(), value.to_owned()));
+            }
+            req
+        };
+
+        let (uuid, first, _) = do_init(&client, "alice@example.com").await;
+        let (status, current) = do_chunk(&client, &uuid, &first, &sealed[..10], 0).await;
+        assert_eq!(status, Status::Ok);
+        assert!(dir.join(format!("{}.part", uuid)).exists());
+
+        assert_eq!(
+            cancel(&uuid, None).dispatch().await.status(),
+            Status::Unauthorized
+        );
+        assert_eq!(
+            cancel(&uuid, Some(("CryptifyToken", &first)))
+                .dispatch()
+                .await
+                .status(),
+            Status::NotFound,
+            "a superseded token does not cancel"
+        );
+        assert_eq!(
+            cancel(&uuid, Some(("CryptifyToken", &current)))
+                .dispatch()
+                .await
+                .status(),
+            Status::Ok
+        );
+        assert!(!dir.join(format!("{}.part", uuid)).exists());
+        let (status, _) = do_chunk(&client, &uuid, &current, &sealed[10..], 10).await;
+        assert_eq!(status, Status::NotFound, "the session is gone");
+
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(init_body_json("alice@example.com"))
+            .dispatch()
+            .await;
+        let body: serde_json::Value =
+            serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let recovery_token = body["recovery_token"].as_str().unwrap().to_owned();
+        assert_eq!(
+            cancel(&uuid, Some(("X-Recovery-Token", &recovery_token)))
+                .dispatch()
+                .await
+                .status(),
+            Status::Ok
+        );
+        assert!(client
+            .rocket()
+            .state::<Store>()
+            .unwrap()
+            .get(&uuid)
+            .is_none());
+
+        let (uuid, token, _) = do_init(&client, "alice@example.com").await;
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        assert_eq!(
+            do_finalize(&client, &uuid, &token, sealed.len() as u64).await,
+            Status::Ok
+        );
+        assert_eq!(
+            cancel(&uuid, Some(("CryptifyToken", &token)))
+                .dispatch()
+                .await
+                .status(),
+            Status::Conflict,
+            "a finalized upload is revoked, not cancelled"
+        );
+
+        let metrics = client.rocket().state::<Arc<Metrics>>().unwrap().render();
+        assert!(metrics.contains("cryptify_cancelled_files_total 2"));
+        assert!(metrics.contains("cryptify_expired_files_total 0"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// The download page can follow an upload from pending to revoked
+    /// without fetching it; HEAD gives the size without a body.
+    /// Finalize compares the notified addresses with the recipients in the
+    /// sealed header: by default it reports the ones missing, and with
+    /// `recipient_check = "reject"` it refuses the upload.
+    #[rocket::async_test]
+    async fn finalize_checks_notified_recipients_against_the_header() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let policy = pg_core::identity::EncryptionPolicy::from([(
+            "bob@example.com".to_owned(),
+            setup.policies[2].clone(),
+        )]);
+        let sealed = seal_payload_for(&setup, &policy, 2, b"for bob only").await;
+        /// Upload `sealed` notifying `recipient` and finalize it twice.
+        /// Returns the status of the first finalize and the flagged
+        /// recipients of both.
+        async fn finalize(
+            client: &Client,
+            sealed: &[u8],
+            recipient: &str,
+        ) -> (Status, Option<String>, Option<String>, String) {
+            let (uuid, token, _) = do_init(client, recipient).await;
+            let (_, token) = do_chunk(client, &uuid, &token, sealed, 0).await;
+            let mut flagged = Vec::new();
+            let mut statuses = Vec::new();
+            for _ in 0..2 {
+                let res = client
+                    .post(format!("/fileupload/finalize/{}", uuid))
+                    .header(Header::new("CryptifyToken", token.clone()))
+                    .header(Header::new(
+                        "Content-Range",
+                        format!("bytes */{}", sealed.len()),
+                    ))
+                    .dispatch()
+                    .await;
+                statuses.push(res.status());
+                flagged.push(
+                    res.headers()
+                        .get_one(UNSEALABLE_RECIPIENTS_HEADER)
+                        .map(str::to_owned),
+                );
+            }
+            let retried = flagged.pop().unwrap();
+            (statuses[0], flagged.pop().unwrap(), retried, uuid)
+        }
+
+        let (client, dir) = test_client(&setup).await;
+        let (status, flagged, _, _) = finalize(&client, &sealed, "Bob@Example.com").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(flagged, None);
+        let (status, flagged, retried, _) =
+            finalize(&client, &sealed, "bob@example.com, alice@example.com").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(flagged.as_deref(), Some("alice@example.com"));
+        assert_eq!(retried, flagged, "a retried finalize reports them again");
+        let _ = std::fs::remove_dir_all(dir);
+
+        let (figment, dir) = test_figment();
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let rocket = build_rocket(figment.merge(("recipient_check", "reject")), vk);
+        let client = Client::tracked(rocket).await.expect("valid rocket");
+        let (status, _, _, _) = finalize(&client, &sealed, "bob@example.com").await;
+        assert_eq!(status, Status::Ok);
+        let (status, _, _, uuid) = finalize(&client, &sealed, "alice@example.com").await;
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert!(
+            client
+                .rocket()
+                .state::<Store>()
+                .unwrap()
+                .get(&uuid)
+                .is_none(),
+            "the rejected upload is dropped"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// What the mock IRMA server discloses once a session is done; `None`
+    /// keeps every session waiting for the user.
+    type MockDisclosure = Arc<std::sync::Mutex<Option<String>>>;
+
+    #[post("/session")]
+    fn mock_irma_session() -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "sessionPtr": {"u": "https://irma.example.com/irma/session/ptr", "irmaqr": "disclosing"},
+            "token": uuid::Uuid::new_v4().to_string(),
+        }))
+    }
+
+    #[get("/session/<_token>/status")]
+    fn mock_irma_status(_token: &str, disclosed: &State<MockDisclosure>) -> Json<&'static str> {
+        match *disclosed.lock().unwrap() {
+            Some(_) => Json("DONE"),
+            None => Json("CONNECTED"),
+        }
+    }
+
+    #[get("/session/<token>/result")]
+    fn mock_irma_result(token: &str, disclosed: &State<MockDisclosure>) -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "token": token,
+            "type": "disclosing",
+            "status": "DONE",
+            "proofStatus": "VALID",
+            "disclosed": [[{
+                "id": "pbdf.sidn-pbdf.email.email",
+                "rawvalue": *disclosed.lock().unwrap(),
+                "status": "PRESENT",
+            }]],
+        }))
+    }
+
+    /// Launch a stand-in for an IRMA server's requestor API on an ephemeral
+    /// port, returning its URL and the handle that decides what it
+    /// discloses.
+    async fn spawn_mock_irma() -> (String, MockDisclosure) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind ephemeral port");
+        let port = listener.local_addr().expect("local addr").port();
+        drop(listener);
+
+        let disclosed = MockDisclosure::default();
+        let figment = rocket::Config::figment()
+            .merge(("port", port))
+            .merge(("address", "127.0.0.1"))
+            .merge(("log_level", "off"));
+        let rocket = rocket::custom(figment)
+            .mount(
+                "/irma",
+                routes![mock_irma_session, mock_irma_status, mock_irma_result],
+            )
+            .manage(disclosed.clone());
+        rocket::tokio::spawn(async move {
+            let _ = rocket.launch().await;
+        });
+        for _ in 0..200 {
+            if std::net::TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                break;
+            }
+            rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        (format!("http://127.0.0.1:{}/irma", port), disclosed)
+    }
+
+    /// With `yivi_downloads`, only a recipient who disclosed their address
+    /// in a Yivi session for the upload can download it.
+    #[rocket::async_test]
+    async fn yivi_downloads_require_a_recipient_disclosure() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"only for alice").await;
+        let (irma_url, disclosed) = spawn_mock_irma().await;
+        let (figment, dir) = test_figment();
+        let figment = figment
+            .merge(("yivi_downloads", true))
+            .merge(("irma", serde_json::json!({ "url": irma_url })));
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment, vk))
+            .await
+            .expect("valid rocket");
+
+        let (uuid, token, _) = do_init(&client, "Alice@example.com").await;
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let (client, uuid) = (&client, uuid.as_str());
+        let download = |session: &str| {
+            client
+                .get(format!("/filedownload/{}?disclosure={}", uuid, session))
+                .dispatch()
+        };
+        let start = || async {
+            let res = client
+                .post(format!("/filedownload/{}/disclosure", uuid))
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok);
+            let body: serde_json::Value = res.into_json().await.unwrap();
+            assert_eq!(body["sessionPtr"]["irmaqr"], "disclosing");
+            body["session"].as_str().unwrap().to_owned()
+        };
+        let state = |session: String| async move {
+            let res = client
+                .get(format!("/filedownload/{}/disclosure/{}", uuid, session))
+                .dispatch()
+                .await;
+            let body: serde_json::Value = res.into_json().await.unwrap();
+            body["state"].as_str().unwrap().to_owned()
+        };
+
+        let res = client
+            .get(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+
+        let session = start().await;
+        assert_eq!(state(session.clone()).await, "pending");
+        assert_eq!(download(&session).await.status(), Status::Unauthorized);
+
+        *disclosed.lock().unwrap() = Some("mallory@example.com".to_owned());
+        assert_eq!(state(session.clone()).await, "not_a_recipient");
+        assert_eq!(download(&session).await.status(), Status::Forbidden);
+
+        let session = start().await;
+        *disclosed.lock().unwrap() = Some("alice@example.com".to_owned());
+        assert_eq!(state(session.clone()).await, "done");
+        let res = download(&session).await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// With a `download_link_secret`, only a link signed for the recipient
+    /// downloads, until the upload expires or the sender revokes the link.
+    /// Extending the upload keeps the link working, without a new mail.
+    #[rocket::async_test]
+    async fn signed_links_are_bound_to_their_recipient() {
+        const SECRET: &str = "a secret of at least thirty-two bytes";
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"for alice and dave").await;
+        let (figment, dir) = test_figment();
+        let figment = figment.merge(("download_link_secret", SECRET));
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment, vk))
+            .await
+            .expect("valid rocket");
+
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(init_body_json("alice@example.com, dave@example.com"))
+            .dispatch()
+            .await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let recovery_token = body["recovery_token"].as_str().unwrap().to_owned();
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let (client, uuid) = (&client, uuid.as_str());
+        let link = |recipient: &str| links::sign(SECRET.as_bytes(), uuid, recipient);
+        let download = |recipient: &str, token: &str| {
+            client
+                .get(format!(
+                    "/filedownload/{}?recipient={}&token={}",
+                    uuid, recipient, token
+                ))
+                .dispatch()
+        };
+
+        let res = client
+            .get(format!(
+                "/filedownload/{}?recipient=alice@example.com",
+                uuid
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+        let alice = link("alice@example.com");
+        assert_eq!(
+            download("dave@example.com", &alice).await.status(),
+            Status::Unauthorized,
+            "a link does not open the download for another recipient"
+        );
+        let res = download("alice@example.com", &alice).await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
+
+        // An upload about to expire is extended without mailing anyone:
+        // the link already sent keeps working.
+        let store = client.rocket().state::<Store>().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let upload = store.finalized(uuid).unwrap();
+        store.record_finalized(
+            uuid,
+            FinalizedUpload {
+                expires: now + 60,
+                ..upload
+            },
+        );
+        let res = client
+            .post(format!("/filedownload/{}/extend", uuid))
+            .header(ContentType::JSON)
+            .header(Header::new("X-Recovery-Token", recovery_token.clone()))
+            .body(serde_json::json!({ "expiresIn": 3600, "notifyRecipients": false }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(
+            download("alice@example.com", &alice).await.status(),
+            Status::Ok,
+            "the link mailed before the extension still works"
+        );
+
+        let revoke = |recipient: &str| {
+            client
+                .delete(format!("/filedownload/{}/links/{}", uuid, recipient))
+                .header(Header::new("X-Recovery-Token", recovery_token.clone()))
+                .dispatch()
+        };
+        assert_eq!(
+            revoke("mallory@example.com").await.status(),
+            Status::NotFound
+        );
+        assert_eq!(revoke("Dave@example.com").await.status(), Status::Ok);
+        let dave = link("dave@example.com");
+        assert_eq!(
+            download("dave@example.com", &dave).await.status(),
+            Status::Gone
+        );
+        assert_eq!(
+            download("alice@example.com", &alice).await.status(),
+            Status::Ok
+        );
+
+        store.extend(uuid, now - 1);
+        assert_eq!(
+            download("alice@example.com", &alice).await.status(),
+            Status::Gone,
+            "the link dies with the upload"
+        );
+
+        // A finalized payload whose record is lost has no expiry to check.
+        let orphan = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(&orphan), &sealed).unwrap();
+        std::fs::write(dir.join(format!("{}.finalized", orphan)), b"").unwrap();
+        let token = links::sign(SECRET.as_bytes(), &orphan, "alice@example.com");
+        let res = client
+            .get(format!(
+                "/filedownload/{}?recipient=alice@example.com&token={}",
+                orphan, token
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Gone);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// An upload that does not mail its recipients hands their signed links
+    /// back on finalize, and those links download.
+    #[rocket::async_test]
+    async fn unmailed_recipients_get_signed_links_on_finalize() {
+        const SECRET: &str = "a secret of at least thirty-two bytes";
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"delivered by the sender").await;
+        let (figment, dir) = test_figment();
+        let figment = figment.merge(("download_link_secret", SECRET));
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment, vk))
+            .await
+            .expect("valid rocket");
+
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "recipient": "alice@example.com, dave@example.com",
+                    "mailContent": "hello",
+                    "mailLang": "EN",
+                    "confirm": false,
+                    "notifyRecipients": false,
+                })
+                .to_string(),
+            )
+            .dispatch()
+            .await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        let finalize = || {
+            client
+                .post(format!("/fileupload/finalize/{}", uuid))
+                .header(Header::new("CryptifyToken", token.clone()))
+                .header(Header::new(
+                    "Content-Range",
+                    format!("bytes */{}", sealed.len()),
+                ))
+                .dispatch()
+        };
+        let res = finalize().await;
+        assert_eq!(res.status(), Status::Ok);
+        let links = res
+            .headers()
+            .get_one(DOWNLOAD_LINKS_HEADER)
+            .expect("links for the unmailed recipients")
+            .to_owned();
+        let retried = finalize().await;
+        assert_eq!(
+            retried.headers().get_one(DOWNLOAD_LINKS_HEADER),
+            Some(links.as_str()),
+            "a retried finalize hands out the same links"
+        );
+
+        let links: Vec<url::Url> = links.split(", ").map(|l| l.parse().unwrap()).collect();
+        assert_eq!(links.len(), 2);
+        for link in links {
+            let query: HashMap<_, _> = link.query_pairs().into_owned().collect();
+            assert_eq!(query["uuid"], uuid);
+            let res = client
+                .get(format!(
+                    "/filedownload/{}?recipient={}&token={}",
+                    uuid, query["recipient"], query["token"]
+                ))
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok, "{} downloads", query["recipient"]);
+            assert_eq!(res.into_bytes().await.unwrap(), sealed);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A sender who disclosed their address sees only their own uploads on
+    /// the dashboard, and can extend and revoke them with the session.
+    #[rocket::async_test]
+    async fn sender_dashboard_lists_and_manages_own_uploads() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let by_bob = seal_payload(&setup, b"from bob").await;
+        let by_alice = seal_payload_as(&setup, 0, b"from alice").await;
+        let (irma_url, disclosed) = spawn_mock_irma().await;
+        let (figment, dir) = test_figment();
+        let figment = figment.merge(("irma", serde_json::json!({ "url": irma_url })));
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment, vk))
+            .await
+            .expect("valid rocket");
+
+        let mut uuids = Vec::new();
+        for sealed in [&by_bob, &by_bob, &by_alice] {
+            let (uuid, token, _) = do_init(&client, "carol@example.com").await;
+            let (_, token) = do_chunk(&client, &uuid, &token, sealed, 0).await;
+            let total = sealed.len() as u64;
+            assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+            uuids.push(uuid);
+        }
+
+        // Counted even without a download limit or receipts.
+        let res = client
+            .get(format!("/filedownload/{}", uuids[0]))
+            .dispatch()
+            .await;
+        assert_eq!(res.into_bytes().await.unwrap(), by_bob);
+
+        let res = client.get("/dashboard/uploads").dispatch().await;
+        assert_eq!(res.status(), Status::Unauthorized);
+
+        let res = client.post("/dashboard/session").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let session = body["session"].as_str().unwrap().to_owned();
+        let state = || async {
+            let res = client
+                .get(format!("/dashboard/session/{}", session))
+                .dispatch()
+                .await;
+            let body: serde_json::Value = res.into_json().await.unwrap();
+            body["state"].as_str().unwrap().to_owned()
+        };
+        assert_eq!(state().await, "pending");
+        let res = client
+            .get("/dashboard/uploads")
+            .header(Header::new("X-Dashboard-Session", session.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+
+        *disclosed.lock().unwrap() = Some("Bob@example.com".to_owned());
+        assert_eq!(state().await, "done");
+        let session = Header::new("X-Dashboard-Session", session.clone());
+        let list = || async {
+            let res = client
+                .get("/dashboard/uploads")
+                .header(session.clone())
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok);
+            res.into_json::<Vec<serde_json::Value>>().await.unwrap()
+        };
+        let uploads = list().await;
+        let mut listed: Vec<&str> = uploads
+            .iter()
+            .map(|u| u["uuid"].as_str().unwrap())
+            .collect();
+        listed.sort_unstable();
+        let mut own = vec![uuids[0].as_str(), uuids[1].as_str()];
+        own.sort_unstable();
+        assert_eq!(listed, own, "only bob's uploads are listed");
+        let downloaded = uploads.iter().find(|u| u["uuid"] == uuids[0]).unwrap();
+        assert_eq!(downloaded["downloads"], 1);
+        assert_eq!(downloaded["downloads_remaining"], serde_json::Value::Null);
+        assert_eq!(uploads[0]["state"], "available");
+        assert_eq!(
+            uploads[0]["recipients"],
+            serde_json::json!(["carol@example.com"])
+        );
+
+        let store = client.rocket().state::<Store>().unwrap();
+        let mut upload = store.finalized(&uuids[0]).unwrap();
+        upload.expires = chrono::offset::Utc::now().timestamp() + 86_400;
+        store.record_finalized(&uuids[0], upload);
+        let res = client
+            .post(format!("/filedownload/{}/extend", uuids[0]))
+            .header(session.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::json!({ "expiresIn": 2 * 86_400 }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let revoke = |uuid: &str| {
+            client
+                .delete(format!("/filedownload/{}", uuid))
+                .header(session.clone())
+                .dispatch()
+        };
+        assert_eq!(revoke(&uuids[2]).await.status(), Status::NotFound);
+        assert_eq!(revoke(&uuids[1]).await.status(), Status::Ok);
+        let uploads = list().await;
+        let revoked = uploads.iter().find(|u| u["uuid"] == uuids[1].as_str());
+        assert_eq!(revoked.unwrap()["state"], "revoked");
+
+        let res = client
+            .delete(format!("/filedownload/{}", uuids[2]))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// The download page reads the recipients' hidden policies without
+    /// touching the payload; older records fall back to the stored header.
+    #[rocket::async_test]
+    async fn download_header_serves_the_recipient_policies() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"who may read this").await;
+        let (client, dir) = test_client(&setup).await;
+        let expected: serde_json::Value = serde_json::to_value(
+            setup
+                .policy
+                .iter()
+                .map(|(rid, policy)| (rid.clone(), policy.to_hidden()))
+                .collect::<BTreeMap<_, _>>(),
+        )
+        .unwrap();
+
+        let (uuid, token, _) = do_init(&client, "alice@example.com").await;
+        let res = client
+            .get(format!("/filedownload/{}/header", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+        let header = |uuid: &str| {
+            client
+                .get(format!("/filedownload/{}/header", uuid))
+                .dispatch()
+        };
+        let res = header(&uuid).await;
+        assert_eq!(res.status(), Status::Ok);
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        assert_eq!(body["recipients"], expected);
+
+        let store = client.rocket().state::<Store>().unwrap();
+        let mut upload = store.finalized(&uuid).unwrap();
+        upload.recipient_policies = None;
+        store.record_finalized(&uuid, upload.clone());
+        let body: serde_json::Value = header(&uuid).await.into_json().await.unwrap();
+        assert_eq!(body["recipients"], expected);
+
+        store.extend(&uuid, chrono::Utc::now().timestamp() - 1);
+        assert_eq!(
+            header(&uuid).await.status(),
+            Status::Gone,
+            "an expired upload awaiting deletion is gone"
+        );
+        upload.revoked = true;
+        store.record_finalized(&uuid, upload);
+        assert_eq!(header(&uuid).await.status(), Status::Gone);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Where downloads need a signed link, so do the header and `HEAD`, and
+    /// the header names only the recipient the link was signed for.
+    #[rocket::async_test]
+    async fn gated_header_and_head_need_a_signed_link() {
+        const SECRET: &str = "a secret of at least thirty-two bytes";
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let policy = pg_core::identity::EncryptionPolicy::from([
+            ("alice@example.com".to_owned(), setup.policies[1].clone()),
+            ("dave@example.com".to_owned(), setup.policies[2].clone()),
+        ]);
+        let sealed = seal_payload_for(&setup, &policy, 2, b"whose is it").await;
+        let (figment, dir) = test_figment();
+        let figment = figment.merge(("download_link_secret", SECRET));
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment, vk))
+            .await
+            .expect("valid rocket");
+
+        let (uuid, token, _) = do_init(&client, "alice@example.com, dave@example.com").await;
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let (client, uuid) = (&client, uuid.as_str());
+        let query = |recipient: Option<&str>| match recipient {
+            Some(recipient) => format!(
+                "?recipient={}&token={}",
+                recipient,
+                links::sign(SECRET.as_bytes(), uuid, recipient)
+            ),
+            None => String::new(),
+        };
+        let header = |recipient: Option<&str>| {
+            let uri = format!("/filedownload/{}/header{}", uuid, query(recipient));
+            async move { client.get(uri).dispatch().await }
+        };
+        let head = |recipient: Option<&str>| {
+            let uri = format!("/filedownload/{}{}", uuid, query(recipient));
+            async move { client.head(uri).dispatch().await.status() }
+        };
+
+        assert_eq!(header(None).await.status(), Status::Unauthorized);
+        assert_eq!(head(None).await, Status::Unauthorized);
+        let res = client
+            .get(format!(
+                "/filedownload/{}/header?recipient=alice@example.com&token=00",
+                uuid
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized, "a forged token");
+
+        let res = header(Some("alice@example.com")).await;
+        assert_eq!(res.status(), Status::Ok);
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let recipients = body["recipients"].as_object().unwrap();
+        assert_eq!(
+            recipients.keys().collect::<Vec<_>>(),
+            vec!["alice@example.com"],
+            "other recipients' addresses stay hidden"
+        );
+        assert_eq!(head(Some("alice@example.com")).await, Status::Ok);
+
+        let store = client.rocket().state::<Store>().unwrap();
+        store.extend(uuid, chrono::Utc::now().timestamp() - 1);
+        assert_eq!(
+            header(Some("alice@example.com")).await.status(),
+            Status::Gone
+        );
+        assert_eq!(head(Some("alice@example.com")).await, Status::Gone);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[rocket::async_test]
+    async fn download_info_and_head_describe_the_upload() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"what is this").await;
+        let (client, dir) = test_client(&setup).await;
+        let client = &client;
+        let info = |uuid: &str| {
+            let uri = format!("/filedownload/{}/info", uuid);
+            async move {
+                let res = client.get(uri).dispatch().await;
+                (res.status(), res.into_json::<serde_json::Value>().await)
+            }
+        };
+
+        let (status, _) = info(&uuid::Uuid::new_v4().to_string()).await;
+        assert_eq!(status, Status::NotFound);
+
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(init_body_json("alice@example.com"))
+            .dispatch()
+            .await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let recovery_token = body["recovery_token"].as_str().unwrap().to_owned();
+        let (status, body) = info(&uuid).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body.unwrap()["state"], "pending");
+        let res = client
+            .head(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        let (_, token) = do_chunk(client, &uuid, &token, &sealed, 0).await;
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(client, &uuid, &token, total).await, Status::Ok);
+        let (_, body) = info(&uuid).await;
+        let body = body.unwrap();
+        assert_eq!(body["state"], "available");
+        assert_eq!(body["size"], total);
+        assert!(body["expires_at"].as_str().unwrap().ends_with('Z'));
+        assert!(body["sender_attributes"].is_array());
+        assert_eq!(body["max_downloads"], serde_json::Value::Null);
+
+        let res = client
+            .head(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(
+            res.headers().get_one("Content-Length"),
+            Some(total.to_string().as_str())
+        );
+        assert!(res.into_bytes().await.unwrap_or_default().is_empty());
+
+        let res = client
+            .delete(format!("/filedownload/{}", uuid))
+            .header(Header::new("X-Recovery-Token", recovery_token))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let (_, body) = info(&uuid).await;
+        assert_eq!(body.unwrap()["state"], "revoked");
+        let res = client
+            .head(format!("/filedownload/{}", uuid))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Gone);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// An anonymous sender's declared size caps their chunks too, and is
+    /// reserved as soon as the sealed header names them: over quota, the
+    /// chunk that completes the header is the last one accepted.
+    #[rocket::async_test]
+    async fn anonymous_declared_size_is_reserved_once_the_header_is_in() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"over quota").await;
+        let (client, dir) = test_client(&setup).await;
+        let init = |declared: u64| {
+            let req = client
+                .post("/fileupload/init")
+                .header(ContentType::JSON)
+                .body(
+                    serde_json::json!({
+                        "recipient": "alice@example.com",
+                        "mailContent": "hello",
+                        "mailLang": "EN",
+                        "confirm": false,
+                        "declaredSize": declared,
+                    })
+                    .to_string(),
+                );
+            async move {
+                let res = req.dispatch().await;
+                assert_eq!(res.status(), Status::Ok);
+                let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+                let body: serde_json::Value = res.into_json().await.unwrap();
+                (body["uuid"].as_str().unwrap().to_owned(), token)
+            }
+        };
+
+        let (uuid, token) = init(4).await;
+        let prelude = pg_core::PRELUDE;
+        let (status, _) = do_chunk(&client, &uuid, &token, &[&prelude[..], b"5"].concat(), 0).await;
+        assert_eq!(status, Status::BadRequest, "no chunk goes past it");
+
+        let store = client.rocket().state::<Store>().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        store.record_upload("bob@example.com".into(), ROLLING_LIMIT - 4, now);
+        let (uuid, token) = init(sealed.len() as u64).await;
+        let res = client
+            .put(format!("/fileupload/{}", uuid))
+            .header(Header::new("CryptifyToken", token.clone()))
+            .header(Header::new(
+                "Content-Range",
+                format!("bytes 0-{}/*", sealed.len()),
+            ))
+            .body(&sealed)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PayloadTooLarge);
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        assert_eq!(body["limit"], "rolling_window");
+        assert!(store.get(&uuid).is_none(), "the upload is dropped");
+        assert!(!dir.join(&uuid).exists() && !dir.join(format!("{}.part", uuid)).exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A download limit counts each completed download, and withdraws the
+    /// file when it runs out. A recipient that no link proves counts every
+    /// time.
+    #[rocket::async_test]
+    async fn download_limit_withdraws_the_file_when_reached() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"twice at most").await;
+        let (client, dir) = test_client(&setup).await;
+
+        let init = |max_downloads: u32| {
+            client
+                .post("/fileupload/init")
+                .header(ContentType::JSON)
+                .body(
+                    serde_json::json!({
+                        "recipient": "Alice@Example.com",
+                        "mailContent": "hello",
+                        "mailLang": "EN",
+                        "confirm": false,
+                        "maxDownloads": max_downloads,
+                    })
+                    .to_string(),
+                )
+        };
+        assert_eq!(init(0).dispatch().await.status(), Status::BadRequest);
+
+        let res = init(2).dispatch().await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value =
+            serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let (status, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        assert_eq!(status, Status::Ok);
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let remaining = || async {
+            let res = client
+                .get(format!("/filedownload/{}/info", uuid))
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok);
+            let info: serde_json::Value =
+                serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+            assert_eq!(info["max_downloads"], 2);
+            info["downloads_remaining"].as_u64().unwrap()
+        };
+        let download = |query: &str, range: Option<&str>| {
+            let mut req = client.get(format!("/filedownload/{}{}", uuid, query));
+            if let Some(range) = range {
+                req = req.header(Header::new("Range", range.to_owned()));
+            }
+            req
+        };
+        assert_eq!(remaining().await, 2);
+
+        let res = download("", Some("bytes=0-9")).dispatch().await;
+        assert_eq!(res.into_bytes().await.unwrap(), &sealed[..10]);
+        assert_eq!(remaining().await, 2, "a partial download is not counted");
+
+        let res = download("?recipient=alice@example.com", Some("bytes=10-"))
+            .dispatch()
+            .await;
+        assert_eq!(res.into_bytes().await.unwrap(), &sealed[10..]);
+        assert_eq!(remaining().await, 1, "the resumed download is counted");
+        let res = download("?recipient=alice@example.com", None)
+            .dispatch()
+            .await;
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
+        assert_eq!(remaining().await, 0, "an unproven recipient counts again");
+        assert_eq!(download("", None).dispatch().await.status(), Status::Gone);
+        for _ in 0..100 {
+            if !dir.join(&uuid).exists() {
+                break;
+            }
+            rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!dir.join(&uuid).exists(), "the payload is deleted");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A recipient proven by their signed link counts once against the
+    /// download limit, however often they download.
+    #[rocket::async_test]
+    async fn signed_recipients_count_once_against_the_limit() {
+        const SECRET: &str = "a secret of at least thirty-two bytes";
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"once per recipient").await;
+        let (figment, dir) = test_figment();
+        let figment = figment.merge(("download_link_secret", SECRET));
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
+        };
+        let client = Client::tracked(build_rocket(figment, vk))
+            .await
+            .expect("valid rocket");
+
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "recipient": "Alice@Example.com, dave@example.com",
+                    "mailContent": "hello",
+                    "mailLang": "EN",
+                    "confirm": false,
+                    "maxDownloads": 2,
+                })
+                .to_string(),
+            )
+            .dispatch()
+            .await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let (client, uuid) = (&client, uuid.as_str());
+        let download = |recipient: &str| {
+            let token = links::sign(SECRET.as_bytes(), uuid, recipient);
+            let url = format!(
+                "/filedownload/{}?recipient={}&token={}",
+                uuid, recipient, token
+            );
+            async move { client.get(url).dispatch().await }
+        };
+        let remaining = || async {
+            let res = client
+                .get(format!("/filedownload/{}/info", uuid))
+                .dispatch()
+                .await;
+            let info: serde_json::Value = res.into_json().await.unwrap();
+            info["downloads_remaining"].as_u64().unwrap()
+        };
 
-        // The finalized upload is attributed to app="pg-js".
-        let metrics = client
-            .get("/metrics")
-            .dispatch()
-            .await
-            .into_string()
-            .await
-            .unwrap_or_default();
-        assert!(
-            metrics.contains("cryptify_uploads_by_app_total{app=\"pg-js\"} 1"),
-            "expected pg-js app counter in metrics:\n{metrics}"
-        );
+        for _ in 0..3 {
+            let res = download("alice@example.com").await;
+            assert_eq!(res.into_bytes().await.unwrap(), sealed);
+        }
+        assert_eq!(remaining().await, 1, "a proven recipient counts once");
+        let res = download("dave@example.com").await;
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
+        assert_eq!(remaining().await, 0);
+        assert_eq!(download("alice@example.com").await.status(), Status::Gone);
 
         let _ = std::fs::remove_dir_all(dir);
     }
 
-    // Minimal Rocket exposing only /metrics, with the given config managed so
-    // the MetricsAuth guard can read `metrics_token`. Avoids needing a real
-    // VerifyingKey / TestSetup.
-    fn metrics_only_config(with_token: bool) -> CryptifyConfig {
-        let (figment, _dir) = test_figment();
-        let figment = if with_token {
-            figment.merge(("metrics_token", "s3cret"))
-        } else {
-            figment
+    /// A sender who asked for a confirmation gets a receipt for each
+    /// recipient's first completed download, and for nothing else.
+    #[rocket::async_test]
+    async fn first_download_per_recipient_sends_a_receipt() {
+        const SECRET: &str = "a secret of at least thirty-two bytes";
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"did they get it?").await;
+        let (figment, dir) = test_figment();
+        let figment = figment.merge(("download_link_secret", SECRET));
+        let vk = Parameters {
+            format_version: 0,
+            public_key: VerifyingKey(setup.ibs_pk.0.clone()),
         };
-        figment.extract::<CryptifyConfig>().expect("extract config")
-    }
+        let client = Client::tracked(build_rocket(figment, vk))
+            .await
+            .expect("valid rocket");
 
-    async fn metrics_only_client(config: CryptifyConfig) -> Client {
-        let rocket = rocket::build()
-            .mount("/", routes![metrics_endpoint])
-            .manage(config)
-            .manage(std::sync::Arc::new(Metrics::new()));
-        Client::tracked(rocket).await.expect("valid rocket")
-    }
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "recipient": "Alice@Example.com, dave@example.com",
+                    "mailContent": "hello",
+                    "mailLang": "NL",
+                    "confirm": true,
+                })
+                .to_string(),
+            )
+            .dispatch()
+            .await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let store = client.rocket().state::<Store>().unwrap();
+        let (tx, mut receipts) = rocket::tokio::sync::mpsc::unbounded_channel();
+        store.set_receipt_sender(tx);
+        let download = |recipient: &str, range: Option<&str>| {
+            let token = links::sign(SECRET.as_bytes(), &uuid, recipient);
+            let mut req = client.get(format!(
+                "/filedownload/{}?recipient={}&token={}",
+                uuid, recipient, token
+            ));
+            if let Some(range) = range {
+                req = req.header(Header::new("Range", range.to_owned()));
+            }
+            async move { req.dispatch().await.into_bytes().await.unwrap() }
+        };
 
-    #[rocket::async_test]
-    async fn metrics_requires_bearer_when_token_configured() {
-        let client = metrics_only_client(metrics_only_config(true)).await;
+        assert_eq!(download("alice@example.com", None).await, sealed);
+        let receipt = receipts.try_recv().expect("a receipt for alice");
+        assert_eq!(receipt.id, uuid);
+        assert_eq!(receipt.recipient, "alice@example.com");
+        assert!(matches!(
+            receipt.mailing.mail_lang,
+            crate::email::Language::Nl
+        ));
 
-        // No Authorization header → 401.
-        assert_eq!(
-            client.get("/metrics").dispatch().await.status(),
-            Status::Unauthorized
-        );
-        // Wrong token → 401.
-        assert_eq!(
-            client
-                .get("/metrics")
-                .header(Header::new("Authorization", "Bearer wrong"))
-                .dispatch()
-                .await
-                .status(),
-            Status::Unauthorized
+        download("alice@example.com", None).await;
+        download("dave@example.com", Some("bytes=0-9")).await;
+        download("mallory@example.com", None).await;
+        assert!(
+            receipts.try_recv().is_err(),
+            "repeats, partial and unknown downloads send nothing"
         );
-        // Correct token → 200 with the metrics body.
-        let ok = client
-            .get("/metrics")
-            .header(Header::new("Authorization", "Bearer s3cret"))
-            .dispatch()
-            .await;
-        assert_eq!(ok.status(), Status::Ok);
-        assert!(ok
-            .into_string()
-            .await
-            .unwrap_or_default()
-            .contains("cryptify_uploads_total"));
-    }
 
-    #[rocket::async_test]
-    async fn metrics_open_when_token_unset() {
-        let client = metrics_only_client(metrics_only_config(false)).await;
-        assert_eq!(client.get("/metrics").dispatch().await.status(), Status::Ok);
+        download("dave@example.com", Some("bytes=10-")).await;
+        let receipt = receipts.try_recv().expect("a receipt for dave");
+        assert_eq!(receipt.recipient, "dave@example.com");
+
+        let _ = std::fs::remove_dir_all(dir);
     }
 
+    /// Without a signed link, `recipient` proves nothing: its download sends
+    /// no receipt and is not recorded as theirs.
     #[rocket::async_test]
-    async fn upload_happy_path_multi_chunk() {
-        // Two chunks >1 MiB to exercise the rolling token chain across
-        // multiple PUTs. Keeps payload well under CHUNK_SIZE.
+    async fn unsigned_recipient_sends_no_receipt() {
         let mut rng = rand08::thread_rng();
         let setup = TestSetup::new(&mut rng);
-        let payload: Vec<u8> = (0..(2 * 1024 * 1024 + 17))
-            .map(|i| (i % 251) as u8)
-            .collect();
-        let sealed = seal_payload(&setup, &payload).await;
-
+        let sealed = seal_payload(&setup, b"who was it?").await;
         let (client, dir) = test_client(&setup).await;
 
-        let (uuid, mut token, _) = do_init(&client, SENDER_EMAIL).await;
-
-        let split = sealed.len() / 2;
-        let (s1, next1) = do_chunk(&client, &uuid, &token, &sealed[..split], 0).await;
-        assert_eq!(s1, Status::Ok);
-        token = next1;
-
-        let (s2, next2) = do_chunk(&client, &uuid, &token, &sealed[split..], split as u64).await;
-        assert_eq!(s2, Status::Ok);
-        token = next2;
+        let res = client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "recipient": "alice@example.com",
+                    "mailContent": "hello",
+                    "mailLang": "EN",
+                    "confirm": true,
+                })
+                .to_string(),
+            )
+            .dispatch()
+            .await;
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        let uuid = body["uuid"].as_str().unwrap().to_owned();
+        let (_, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let store = client.rocket().state::<Store>().unwrap();
+        let (tx, mut receipts) = rocket::tokio::sync::mpsc::unbounded_channel();
+        store.set_receipt_sender(tx);
+        let res = client
+            .get(format!(
+                "/filedownload/{}?recipient=alice@example.com",
+                uuid
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.into_bytes().await.unwrap(), sealed);
 
-        let final_status = do_finalize(&client, &uuid, &token, sealed.len() as u64).await;
-        assert_eq!(final_status, Status::Ok);
+        assert!(receipts.try_recv().is_err(), "no receipt is sent");
+        let upload = store.finalized(&uuid).unwrap();
+        assert!(upload.downloaded_by.is_empty());
+        assert_eq!(upload.downloads, 1, "the download still counts");
 
         let _ = std::fs::remove_dir_all(dir);
     }
@@ -3429,6 +8552,95 @@ mod integration {
         let _ = std::fs::remove_dir_all(dir);
     }
 
+    /// A finalize retried after a lost response succeeds again without
+    /// re-booking the usage, and the finalized upload takes no more chunks.
+    #[rocket::async_test]
+    async fn finalized_upload_is_immutable_and_finalize_is_idempotent() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let sealed = seal_payload(&setup, b"only once").await;
+        let (client, dir) = test_client(&setup).await;
+
+        let (uuid, token, _) = do_init(&client, "alice@example.com").await;
+        let (status, token) = do_chunk(&client, &uuid, &token, &sealed, 0).await;
+        assert_eq!(status, Status::Ok);
+        let total = sealed.len() as u64;
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+        assert_eq!(do_finalize(&client, &uuid, &token, total).await, Status::Ok);
+
+        let store = client.rocket().state::<Store>().unwrap();
+        let sender = store
+            .get(&uuid)
+            .unwrap()
+            .lock()
+            .await
+            .sender
+            .clone()
+            .unwrap();
+        let usage = store.get_usage(&sender, chrono::Utc::now().timestamp());
+        assert_eq!(usage.used_bytes, total, "usage booked once");
+        let metrics = client.rocket().state::<Arc<Metrics>>().unwrap().render();
+        let uploads: u64 = metrics
+            .lines()
+            .filter(|l| l.starts_with("cryptify_uploads_total{"))
+            .filter_map(|l| l.rsplit(' ').next()?.parse::<u64>().ok())
+            .sum();
+        assert_eq!(uploads, 1, "upload counted once");
+
+        assert_eq!(
+            do_finalize(&client, &uuid, "wrong-token", total).await,
+            Status::BadRequest,
+            "a retry still needs the token"
+        );
+
+        let (status, _) = do_chunk(&client, &uuid, &token, b"appended", total).await;
+        assert_eq!(status, Status::Conflict);
+        assert_eq!(std::fs::read(dir.join(&uuid)).unwrap(), sealed);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A body shorter than its `Content-Range` (what a dropped connection
+    /// looks like) is rejected without leaving any of its bytes behind, and
+    /// the chunk can be sent again with the same token.
+    #[rocket::async_test]
+    async fn upload_chunk_rolls_back_incomplete_body() {
+        let mut rng = rand08::thread_rng();
+        let setup = TestSetup::new(&mut rng);
+        let (client, dir) = test_client(&setup).await;
+        let sealed = seal_payload(&setup, b"rolled back").await;
+        let (uuid, token, _) = do_init(&client, SENDER_EMAIL).await;
+        let (status, token) = do_chunk(&client, &uuid, &token, &sealed[..5], 0).await;
+        assert_eq!(status, Status::Ok);
+
+        let res = client
+            .put(format!("/fileupload/{}", uuid))
+            .header(Header::new("CryptifyToken", token.clone()))
+            .header(Header::new("Content-Range", "bytes 5-15/*"))
+            .body(b"trunc")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        assert_eq!(
+            std::fs::read(dir.join(format!("{}.part", uuid))).unwrap(),
+            &sealed[..5]
+        );
+
+        let (status, token) = do_chunk(&client, &uuid, &token, &sealed[5..], 5).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(
+            std::fs::read(dir.join(format!("{}.part", uuid))).unwrap(),
+            sealed
+        );
+        assert_eq!(
+            do_finalize(&client, &uuid, &token, sealed.len() as u64).await,
+            Status::Ok,
+            "the sealed prefix was rolled back with the chunk"
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
     #[rocket::async_test]
     async fn upload_chunk_rejects_content_range_misalignment() {
         // Start must equal state.uploaded (currently 0).
@@ -3451,7 +8663,7 @@ mod integration {
 #[cfg(test)]
 mod email_template_tests {
     use super::*;
-    use rocket::http::{Header, Status};
+    use rocket::http::{ContentType, Header, Status};
     use rocket::local::asynchronous::Client;
     use std::time::Duration;
 
@@ -3639,6 +8851,226 @@ mod email_template_tests {
         assert_eq!(res.status(), Status::NotFound);
     }
 
+    /// Cryptify client mounting the init, chunk and extend routes, with a
+    /// `PkgClient` pointed at `pkg_url` so uploads can carry an API key.
+    async fn api_key_upload_client(pkg_url: String, data_dir: &std::path::Path) -> Client {
+        use rocket::figment::{providers::Serialized, Figment};
+
+        std::fs::create_dir_all(data_dir).expect("create test data_dir");
+        let figment = Figment::from(rocket::Config::default()).merge(Serialized::defaults(
+            serde_json::json!({
+                "server_url": "http://localhost",
+                "data_dir": data_dir.to_str().unwrap(),
+                "email_from": "Test <test@example.com>",
+                "smtp_url": "localhost",
+                "smtp_port": 1025u16,
+                "allowed_origins": ".*",
+                "pkg_url": "http://localhost",
+                "api_key_max_upload_lifetime_secs": 30 * 86_400,
+            }),
+        ));
+        let rocket = rocket::custom(figment)
+            .mount("/", routes![upload_init, upload_chunk, extend_upload])
+            .attach(AdHoc::config::<CryptifyConfig>())
+            .manage(Arc::new(LocalStorage::new(data_dir)) as Arc<dyn Storage>)
+            .manage(Store::new(Arc::new(Metrics::new())))
+            .manage(PkgClient::new(pkg_url))
+            .manage(Parameters {
+                format_version: 0,
+                public_key: pg_core::test::TestSetup::new(&mut rand08::thread_rng()).ibs_pk,
+            });
+        Client::tracked(rocket).await.expect("valid rocket")
+    }
+
+    async fn init_with_declared_size(
+        client: &Client,
+        declared: u64,
+    ) -> rocket::local::asynchronous::LocalResponse<'_> {
+        let body = serde_json::json!({
+            "recipient": "alice@example.com",
+            "mailContent": "hello",
+            "mailLang": "EN",
+            "confirm": false,
+            "declaredSize": declared,
+        });
+        client
+            .post("/fileupload/init")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer PG-key-no-template"))
+            .body(body.to_string())
+            .dispatch()
+            .await
+    }
+
+    /// A sender-chosen lifetime becomes the upload's expiry when it is within
+    /// the bounds of the sender's tier.
+    #[rocket::async_test]
+    async fn upload_init_applies_sender_chosen_expiry_within_tier_bounds() {
+        let pkg_url = spawn_mock_pkg().await;
+        let data_dir =
+            std::env::temp_dir().join(format!("cryptify-expiry-{}", uuid::Uuid::new_v4()));
+        let client = api_key_upload_client(pkg_url, &data_dir).await;
+        let init = |expires_in: Option<u64>, api_key: bool| {
+            let mut body = serde_json::json!({
+                "recipient": "alice@example.com",
+                "mailContent": "hello",
+                "mailLang": "EN",
+                "confirm": false,
+            });
+            if let Some(secs) = expires_in {
+                body["expiresIn"] = secs.into();
+            }
+            let mut req = client
+                .post("/fileupload/init")
+                .header(ContentType::JSON)
+                .body(body.to_string());
+            if api_key {
+                req = req.header(Header::new("Authorization", "Bearer PG-key-no-template"));
+            }
+            req
+        };
+        async fn lifetime(
+            client: &Client,
+            res: rocket::local::asynchronous::LocalResponse<'_>,
+        ) -> i64 {
+            assert_eq!(res.status(), Status::Ok);
+            let body: serde_json::Value = res.into_json().await.unwrap();
+            let store = client.rocket().state::<Store>().unwrap();
+            let state = store.get(body["uuid"].as_str().unwrap()).unwrap();
+            let expires = state.lock().await.expires;
+            expires - chrono::Utc::now().timestamp()
+        }
+        const DAY: u64 = 86_400;
+
+        let secs = lifetime(&client, init(None, false).dispatch().await).await;
+        assert!((secs - UPLOAD_LIFETIME_SECS).abs() < 60);
+        let secs = lifetime(&client, init(Some(2 * DAY), false).dispatch().await).await;
+        assert!((secs - 2 * DAY as i64).abs() < 60);
+        let secs = lifetime(&client, init(Some(20 * DAY), true).dispatch().await).await;
+        assert!((secs - 20 * DAY as i64).abs() < 60);
+
+        for (secs, api_key) in [(20 * DAY, false), (31 * DAY, true), (60, false)] {
+            let res = init(Some(secs), api_key).dispatch().await;
+            assert_eq!(res.status(), Status::BadRequest, "{} seconds", secs);
+            assert!(res
+                .into_string()
+                .await
+                .unwrap()
+                .contains("expiresIn must be between"));
+        }
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    /// The tenant that uploaded a file may extend it with its API key, up to
+    /// the tenant's maximum lifetime; another tenant may not.
+    #[rocket::async_test]
+    async fn uploading_tenant_extends_with_its_api_key() {
+        let pkg_url = spawn_mock_pkg().await;
+        let data_dir =
+            std::env::temp_dir().join(format!("cryptify-extend-{}", uuid::Uuid::new_v4()));
+        let client = api_key_upload_client(pkg_url, &data_dir).await;
+        let now = chrono::Utc::now().timestamp();
+        let store = client.rocket().state::<Store>().unwrap();
+        store.record_finalized(
+            "u1",
+            FinalizedUpload {
+                finalized_at: Some(now),
+                api_key_tenant: Some("tenant-xyz".to_owned()),
+                ..FinalizedUpload::new(now + 86_400)
+            },
+        );
+        let extend = |key: &str, days: u64| {
+            client
+                .post("/filedownload/u1/extend")
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", format!("Bearer {}", key)))
+                .body(serde_json::json!({ "expiresIn": days * 86_400 }).to_string())
+        };
+
+        let res = extend("PG-key-with-template", 20).dispatch().await;
+        assert_eq!(res.status(), Status::NotFound, "another tenant");
+        let res = extend("PG-key-no-template", 31).dispatch().await;
+        assert_eq!(res.status(), Status::BadRequest, "past the tenant maximum");
+        let res = extend("PG-key-no-template", 20).dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let expires = store.finalized("u1").unwrap().expires;
+        assert!((expires - (now + 20 * 86_400)).abs() < 60);
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    /// A declared size is reserved at init: the tenant is turned away as soon
+    /// as its booked usage plus the uploads it already has in flight would
+    /// exceed the rolling limit, before any byte is sent.
+    #[rocket::async_test]
+    async fn upload_init_reserves_declared_size_against_rolling_limit() {
+        let pkg_url = spawn_mock_pkg().await;
+        let data_dir =
+            std::env::temp_dir().join(format!("cryptify-reserve-{}", uuid::Uuid::new_v4()));
+        let client = api_key_upload_client(pkg_url, &data_dir).await;
+        let now = chrono::Utc::now().timestamp();
+        let store = client.rocket().state::<Store>().unwrap();
+        store.record_upload("api-key:tenant-xyz".into(), API_KEY_ROLLING_LIMIT / 2, now);
+
+        let res = init_with_declared_size(&client, API_KEY_ROLLING_LIMIT / 4).await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = init_with_declared_size(&client, API_KEY_ROLLING_LIMIT / 4 + 1).await;
+        assert_eq!(res.status(), Status::PayloadTooLarge);
+        let body: serde_json::Value = res.into_json().await.unwrap();
+        assert_eq!(body["limit"], "rolling_window");
+        assert_eq!(
+            body["used_bytes"].as_u64(),
+            Some(API_KEY_ROLLING_LIMIT / 2 + API_KEY_ROLLING_LIMIT / 4)
+        );
+        assert_eq!(
+            std::fs::read_dir(&data_dir).unwrap().count(),
+            1,
+            "no payload for the rejected init"
+        );
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[rocket::async_test]
+    async fn upload_chunk_rejects_bytes_past_declared_size() {
+        let pkg_url = spawn_mock_pkg().await;
+        let data_dir =
+            std::env::temp_dir().join(format!("cryptify-reserve-{}", uuid::Uuid::new_v4()));
+        let client = api_key_upload_client(pkg_url, &data_dir).await;
+
+        let res = init_with_declared_size(&client, 4).await;
+        assert_eq!(res.status(), Status::Ok);
+        let token = res.headers().get_one("cryptifytoken").unwrap().to_owned();
+        let uuid = res.into_json::<serde_json::Value>().await.unwrap()["uuid"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        // The start of a sealed stream, so the header check lets it through.
+        let prelude = pg_core::PRELUDE;
+        let status = put_chunk(&client, &uuid, &token, &[&prelude[..], b"5"].concat()).await;
+        assert_eq!(status, Status::BadRequest);
+        let status = put_chunk(&client, &uuid, &token, &prelude).await;
+        assert_eq!(status, Status::Ok);
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    async fn put_chunk(client: &Client, uuid: &str, token: &str, chunk: &[u8]) -> Status {
+        client
+            .put(format!("/fileupload/{}", uuid))
+            .header(Header::new("CryptifyToken", token.to_owned()))
+            .header(Header::new(
+                "Content-Range",
+                format!("bytes 0-{}/*", chunk.len()),
+            ))
+            .body(chunk)
+            .dispatch()
+            .await
+            .status()
+    }
+
     /// Serve `/v2/sign/parameters` from a plain thread: the first `failures`
     /// requests get a 503, subsequent ones the given JSON body. Returns the
     /// URL and a counter of requests seen.
@@ -3,20 +3,23 @@
 //! Exposes a Prometheus text-format `/metrics` endpoint covering:
 //!   - uploads completed, split by traffic source ("channel")
 //!   - bytes uploaded, split by channel
-//!   - current on-disk storage bytes and active file count (sampled
+//!   - current stored payload bytes and active file count (sampled
 //!     periodically by a background task)
+//!   - uploads purged before finalizing, and finalized uploads deleted by
+//!     the retention reaper once they expired
 //!
 //! See `docs/grafana/` for the reference dashboard JSON.
 
 use std::collections::BTreeMap;
 use std::fmt::Write as _;
-use std::path::Path;
 use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::Mutex;
 use std::time::Duration;
 
 use rocket::http::HeaderMap;
 
+use crate::storage::Storage;
+
 /// Channel label used when no other source information is present.
 pub const CHANNEL_UNKNOWN: &str = "unknown";
 
@@ -86,6 +89,8 @@ pub struct Metrics {
     storage_bytes: AtomicI64,
     active_files: AtomicI64,
     expired_files: AtomicU64,
+    cancelled_files: AtomicU64,
+    retention_deleted_files: AtomicU64,
 }
 
 // `Default` is implemented manually (not derived) so it goes through
@@ -118,6 +123,8 @@ impl Metrics {
             storage_bytes: AtomicI64::new(0),
             active_files: AtomicI64::new(0),
             expired_files: AtomicU64::new(0),
+            cancelled_files: AtomicU64::new(0),
+            retention_deleted_files: AtomicU64::new(0),
         }
     }
 
@@ -145,6 +152,17 @@ impl Metrics {
         self.expired_files.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// Record an unfinished upload its sender cancelled.
+    pub fn record_cancelled(&self) {
+        self.cancelled_files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a finalized upload whose payload the retention reaper deleted
+    /// because its expiry passed.
+    pub fn record_retention_deleted(&self) {
+        self.retention_deleted_files.fetch_add(1, Ordering::Relaxed);
+    }
+
     /// Update the current on-disk storage sample.
     pub fn set_storage(&self, bytes: i64, active_files: i64) {
         self.storage_bytes.store(bytes, Ordering::Relaxed);
@@ -257,6 +275,28 @@ impl Metrics {
             self.expired_files.load(Ordering::Relaxed)
         );
 
+        let _ = writeln!(
+            out,
+            "# HELP cryptify_cancelled_files_total Unfinished uploads cancelled by their sender."
+        );
+        let _ = writeln!(out, "# TYPE cryptify_cancelled_files_total counter");
+        let _ = writeln!(
+            out,
+            "cryptify_cancelled_files_total {}",
+            self.cancelled_files.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP cryptify_retention_deleted_files_total Finalized uploads deleted from disk after they expired."
+        );
+        let _ = writeln!(out, "# TYPE cryptify_retention_deleted_files_total counter");
+        let _ = writeln!(
+            out,
+            "cryptify_retention_deleted_files_total {}",
+            self.retention_deleted_files.load(Ordering::Relaxed)
+        );
+
         out
     }
 }
@@ -328,39 +368,27 @@ fn sanitize_label(raw: &str) -> String {
     }
 }
 
-/// Walk `data_dir` once and return `(total_bytes, file_count)`. Symlinks
-/// and subdirectories are ignored — the upload directory is a flat
-/// directory of files named by UUID.
-pub fn sample_storage(data_dir: &Path) -> std::io::Result<(i64, i64)> {
-    let mut total: i64 = 0;
-    let mut count: i64 = 0;
-    match std::fs::read_dir(data_dir) {
-        Ok(rd) => {
-            for entry in rd.flatten() {
-                if let Ok(meta) = entry.metadata() {
-                    if meta.is_file() {
-                        total = total.saturating_add(meta.len() as i64);
-                        count += 1;
-                    }
-                }
-            }
-            Ok((total, count))
-        }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok((0, 0)),
-        Err(e) => Err(e),
-    }
+/// List the payload store once and return `(total_bytes, file_count)`. For
+/// the local backend that is every regular file in `data_dir`; symlinks and
+/// subdirectories are ignored.
+pub async fn sample_storage(storage: &dyn Storage) -> std::io::Result<(i64, i64)> {
+    let objects = storage.list().await?;
+    let total = objects
+        .iter()
+        .fold(0i64, |acc, o| acc.saturating_add(o.size as i64));
+    Ok((total, objects.len() as i64))
 }
 
-/// Periodically sample `data_dir` and push the numbers onto `metrics`.
+/// Periodically sample the payload store and push the numbers onto `metrics`.
 pub async fn storage_sampler(
     metrics: std::sync::Arc<Metrics>,
-    data_dir: std::path::PathBuf,
+    storage: std::sync::Arc<dyn Storage>,
     interval: Duration,
 ) {
     loop {
-        match sample_storage(&data_dir) {
+        match sample_storage(storage.as_ref()).await {
             Ok((bytes, count)) => metrics.set_storage(bytes, count),
-            Err(e) => log::warn!("metrics: storage sampling failed for {:?}: {}", data_dir, e),
+            Err(e) => log::warn!("metrics: storage sampling failed: {}", e),
         }
         rocket::tokio::time::sleep(interval).await;
     }
@@ -369,6 +397,7 @@ pub async fn storage_sampler(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::LocalStorage;
     use rocket::http::Header;
 
     fn headers(pairs: &[(&'static str, &'static str)]) -> rocket::http::HeaderMap<'static> {
@@ -505,6 +534,8 @@ mod tests {
         assert!(text.contains("cryptify_storage_bytes 0"));
         assert!(text.contains("cryptify_active_files 0"));
         assert!(text.contains("cryptify_expired_files_total 0"));
+        assert!(text.contains("cryptify_cancelled_files_total 0"));
+        assert!(text.contains("cryptify_retention_deleted_files_total 0"));
     }
 
     #[test]
@@ -514,6 +545,9 @@ mod tests {
         m.record_upload("website", 500);
         m.record_upload("outlook", 250);
         m.record_expired();
+        m.record_cancelled();
+        m.record_retention_deleted();
+        m.record_retention_deleted();
         m.set_storage(9_999, 3);
         let text = m.render();
         assert!(text.contains("cryptify_uploads_total{channel=\"website\"} 2"));
@@ -523,22 +557,24 @@ mod tests {
         assert!(text.contains("cryptify_storage_bytes 9999"));
         assert!(text.contains("cryptify_active_files 3"));
         assert!(text.contains("cryptify_expired_files_total 1"));
+        assert!(text.contains("cryptify_cancelled_files_total 1"));
+        assert!(text.contains("cryptify_retention_deleted_files_total 2"));
     }
 
-    #[test]
-    fn sample_storage_missing_dir_is_zero() {
+    #[rocket::async_test]
+    async fn sample_storage_missing_dir_is_zero() {
         let tmp = std::env::temp_dir().join("cryptify-metrics-missing-xyz");
-        let (bytes, count) = sample_storage(&tmp).unwrap();
+        let (bytes, count) = sample_storage(&LocalStorage::new(tmp)).await.unwrap();
         assert_eq!((bytes, count), (0, 0));
     }
 
-    #[test]
-    fn sample_storage_counts_files() {
+    #[rocket::async_test]
+    async fn sample_storage_counts_files() {
         let tmp = std::env::temp_dir().join(format!("cryptify-metrics-{}", uuid::Uuid::new_v4()));
         std::fs::create_dir_all(&tmp).unwrap();
         std::fs::write(tmp.join("a"), b"hello").unwrap();
         std::fs::write(tmp.join("b"), b"world!").unwrap();
-        let (bytes, count) = sample_storage(&tmp).unwrap();
+        let (bytes, count) = sample_storage(&LocalStorage::new(&tmp)).await.unwrap();
         assert_eq!(count, 2);
         assert_eq!(bytes, 11);
         std::fs::remove_dir_all(&tmp).unwrap();
@@ -0,0 +1,323 @@
+//! The flat-directory backend: one file per upload, named by its UUID.
+//! While an upload is in progress the file is called `<uuid>.part`; finalize
+//! renames it into place and adds an empty `<uuid>.finalized` marker.
+
+use std::collections::HashSet;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use rocket::tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use super::{ChunkSource, PayloadReader, Storage, StoredObject};
+
+const PART_SUFFIX: &str = ".part";
+const MARKER_SUFFIX: &str = ".finalized";
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.root.join(id)
+    }
+
+    fn part_path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{}{}", id, PART_SUFFIX))
+    }
+
+    fn marker_path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{}{}", id, MARKER_SUFFIX))
+    }
+
+    async fn open_part(&self, id: &str) -> io::Result<File> {
+        // No `create(true)`: a chunk for a payload that was never created
+        // (or has been deleted since) must surface as `NotFound`.
+        OpenOptions::new()
+            .write(true)
+            .open(self.part_path(id))
+            .await
+    }
+}
+
+async fn write_to(file: &mut File, offset: u64, data: ChunkSource<'_>) -> io::Result<u64> {
+    file.seek(io::SeekFrom::Start(offset)).await?;
+    let written = rocket::tokio::io::copy(data, file).await?;
+    file.flush().await?;
+    Ok(written)
+}
+
+async fn remove_if_exists(path: PathBuf) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for LocalStorage {
+    async fn create(&self, id: &str) -> io::Result<()> {
+        File::create(self.part_path(id)).await.map(drop)
+    }
+
+    async fn write_at(&self, id: &str, offset: u64, data: ChunkSource<'_>) -> io::Result<u64> {
+        let mut file = self.open_part(id).await?;
+        let result = write_to(&mut file, offset, data).await;
+        if result.is_err() {
+            if let Err(e) = file.set_len(offset).await {
+                log::error!("could not roll back {} to {} bytes: {}", id, offset, e);
+            }
+        }
+        result
+    }
+
+    fn supports_parallel_writes(&self) -> bool {
+        true
+    }
+
+    /// Every call writes through its own file handle, so chunks at
+    /// different offsets do not get in each other's way.
+    async fn write_chunk(&self, id: &str, offset: u64, data: ChunkSource<'_>) -> io::Result<u64> {
+        let mut file = self.open_part(id).await?;
+        write_to(&mut file, offset, data).await
+    }
+
+    async fn complete(&self, id: &str) -> io::Result<()> {
+        match fs::rename(self.part_path(id), self.path(id)).await {
+            // Already moved by an earlier call.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.size(id).await.map(drop),
+            result => result,
+        }
+    }
+
+    async fn mark_finalized(&self, id: &str) -> io::Result<()> {
+        File::create(self.marker_path(id)).await.map(drop)
+    }
+
+    async fn is_finalized(&self, id: &str) -> io::Result<bool> {
+        match fs::metadata(self.marker_path(id)).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn read(&self, id: &str, range: Option<Range<u64>>) -> io::Result<PayloadReader> {
+        let mut file = File::open(self.path(id)).await?;
+        match range {
+            Some(range) => {
+                file.seek(io::SeekFrom::Start(range.start)).await?;
+                Ok(Box::pin(file.take(range.end - range.start)))
+            }
+            None => Ok(Box::pin(file)),
+        }
+    }
+
+    async fn size(&self, id: &str) -> io::Result<u64> {
+        let meta = fs::metadata(self.path(id)).await?;
+        if !meta.is_file() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(meta.len())
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()> {
+        // Marker first: a payload must never look finalized without its bytes.
+        remove_if_exists(self.marker_path(id)).await?;
+        remove_if_exists(self.path(id)).await?;
+        remove_if_exists(self.part_path(id)).await
+    }
+
+    /// Symlinks and subdirectories are skipped, and a missing directory is
+    /// simply empty.
+    async fn list(&self) -> io::Result<Vec<StoredObject>> {
+        let mut entries = match fs::read_dir(&self.root).await {
+            Ok(rd) => rd,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut objects = Vec::new();
+        let mut finalized = HashSet::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if let Some(id) = name.strip_suffix(MARKER_SUFFIX) {
+                finalized.insert(id.to_owned());
+                continue;
+            }
+            let (id, in_progress) = match name.strip_suffix(PART_SUFFIX) {
+                Some(id) => (id.to_owned(), true),
+                None => (name.clone(), false),
+            };
+            let Ok(meta) = entry.metadata().await else {
+                continue;
+            };
+            if !meta.is_file() {
+                continue;
+            }
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs() as i64);
+            objects.push(StoredObject {
+                id,
+                size: meta.len(),
+                modified,
+                finalized: false,
+                in_progress,
+            });
+        }
+        for object in &mut objects {
+            object.finalized = finalized.contains(&object.id);
+        }
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Broken;
+
+    fn temp_root() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cryptify-local-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn read_all(reader: PayloadReader) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut reader = reader;
+        reader.read_to_end(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[rocket::async_test]
+    async fn write_read_and_delete_roundtrip() {
+        let root = temp_root();
+        let storage = LocalStorage::new(&root);
+
+        storage.create("f").await.unwrap();
+        storage.write_at("f", 0, &mut &b"hello "[..]).await.unwrap();
+        storage.write_at("f", 6, &mut &b"world"[..]).await.unwrap();
+        assert_eq!(
+            storage.read("f", None).await.err().unwrap().kind(),
+            io::ErrorKind::NotFound,
+            "nothing is readable under the final name before completion"
+        );
+        let listed = storage.list().await.unwrap();
+        assert_eq!(listed[0].id, "f");
+        assert!(listed[0].in_progress);
+        storage.complete("f").await.unwrap();
+        storage
+            .complete("f")
+            .await
+            .expect("completing twice succeeds");
+        assert!(!storage.is_finalized("f").await.unwrap());
+        storage.mark_finalized("f").await.unwrap();
+        assert!(storage.is_finalized("f").await.unwrap());
+
+        assert_eq!(storage.size("f").await.unwrap(), 11);
+        assert_eq!(
+            read_all(storage.read("f", None).await.unwrap()).await,
+            b"hello world"
+        );
+        assert_eq!(
+            read_all(storage.read("f", Some(6..9)).await.unwrap()).await,
+            b"wor"
+        );
+
+        let listed = storage.list().await.unwrap();
+        assert_eq!(listed.len(), 1, "the marker is not a payload");
+        assert_eq!((listed[0].id.as_str(), listed[0].size), ("f", 11));
+        assert!(listed[0].finalized);
+        assert!(!listed[0].in_progress);
+
+        storage.delete("f").await.unwrap();
+        assert!(!storage.is_finalized("f").await.unwrap());
+        storage.delete("f").await.expect("deleting twice succeeds");
+        assert_eq!(
+            storage.size("f").await.unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[rocket::async_test]
+    async fn broken_write_rolls_back_to_its_offset() {
+        let root = temp_root();
+        let storage = LocalStorage::new(&root);
+
+        storage.create("f").await.unwrap();
+        storage.write_at("f", 0, &mut &b"kept"[..]).await.unwrap();
+        let err = storage
+            .write_at("f", 4, &mut Broken(b"lost"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(std::fs::read(root.join("f.part")).unwrap(), b"kept");
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[rocket::async_test]
+    async fn chunks_can_be_written_out_of_order() {
+        let root = temp_root();
+        let storage = LocalStorage::new(&root);
+        assert!(storage.supports_parallel_writes());
+
+        storage.create("f").await.unwrap();
+        let (mut hello, mut world) = (&b"hello "[..], &b"world"[..]);
+        let (second, first) = rocket::tokio::join!(
+            storage.write_chunk("f", 6, &mut world),
+            storage.write_chunk("f", 0, &mut hello),
+        );
+        assert_eq!((first.unwrap(), second.unwrap()), (6, 5));
+        let err = storage
+            .write_chunk("f", 0, &mut Broken(b""))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            std::fs::read(root.join("f.part")).unwrap(),
+            b"hello world",
+            "a broken chunk leaves the rest of the payload alone"
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[rocket::async_test]
+    async fn write_to_missing_payload_is_not_found() {
+        let root = temp_root();
+        let storage = LocalStorage::new(&root);
+
+        let err = storage
+            .write_at("never-created", 0, &mut &b"x"[..])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(storage.list().await.unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[rocket::async_test]
+    async fn missing_root_lists_empty() {
+        let storage = LocalStorage::new("/nonexistent/cryptify-test-path");
+        assert!(storage.list().await.unwrap().is_empty());
+    }
+}
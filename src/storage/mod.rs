@@ -0,0 +1,132 @@
+//! Where upload payloads live.
+//!
+//! Every handler that touches payload bytes goes through the [`Storage`]
+//! trait rather than `tokio::fs`, so the same code runs against:
+//!   - [`LocalStorage`]: the flat directory of UUID-named files under
+//!     `data_dir` (the default, and the only option for a single pod)
+//!   - [`S3Storage`]: an S3-compatible bucket (`storage_backend = "s3"` plus
+//!     an `[s3]` table). Only the payloads move there; everything else stays
+//!     with the process, so a bucket prefix serves a single instance
+//!
+//! A payload only becomes downloadable once `upload_finalize` has verified
+//! it: bytes are written under an in-progress name, moved into place by
+//! [`Storage::complete`], and finally a persistent "finalized" marker is
+//! written next to them with [`Storage::mark_finalized`]. Downloads check
+//! [`Storage::is_finalized`] first.
+//!
+//! Errors are plain `std::io::Error`s. A missing payload is always reported
+//! as `ErrorKind::NotFound`, whatever the backend, so callers can keep
+//! mapping it to the same 404s as before.
+
+mod local;
+pub mod s3;
+
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+
+use rocket::tokio::io::AsyncRead;
+
+pub use local::LocalStorage;
+pub use s3::{S3Config, S3Storage};
+
+/// Streaming reader over (a range of) a stored payload.
+pub type PayloadReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// The bytes handed to [`Storage::write_at`], typically a request body.
+pub type ChunkSource<'a> = &'a mut (dyn AsyncRead + Send + Unpin);
+
+/// One entry of [`Storage::list`].
+#[derive(Clone, Debug)]
+pub struct StoredObject {
+    /// The upload UUID the payload is stored under.
+    pub id: String,
+    pub size: u64,
+    /// Last modification, in seconds since the Unix epoch.
+    pub modified: i64,
+    /// Whether the finalized marker exists for this payload.
+    pub finalized: bool,
+    /// Whether the payload is still being written, i.e. was never passed
+    /// to [`Storage::complete`].
+    pub in_progress: bool,
+}
+
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    /// Start an empty payload for `id`.
+    async fn create(&self, id: &str) -> io::Result<()>;
+
+    /// Stream `data` to byte `offset` of the payload until it ends, and
+    /// return the number of bytes written. If reading `data` (or writing)
+    /// fails partway, the payload is rolled back to `offset` bytes before
+    /// the error is returned, so a broken request never leaves half a chunk
+    /// behind. Upload chunks arrive in order, so backends may require
+    /// `offset` to equal the number of bytes written so far and reject
+    /// anything else with `InvalidInput`.
+    async fn write_at(&self, id: &str, offset: u64, data: ChunkSource<'_>) -> io::Result<u64>;
+
+    /// Whether [`Storage::write_chunk`] is available. Parallel uploads are
+    /// only offered on backends that have it.
+    fn supports_parallel_writes(&self) -> bool {
+        false
+    }
+
+    /// Stream `data` to byte `offset` of the payload while other writes to
+    /// the same payload may be running, and return the number of bytes
+    /// written. Unlike [`Storage::write_at`] nothing is rolled back when it
+    /// fails: the caller has to treat the whole range as unwritten.
+    async fn write_chunk(
+        &self,
+        _id: &str,
+        _offset: u64,
+        _data: ChunkSource<'_>,
+    ) -> io::Result<u64> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Atomically move a fully written payload from its in-progress name into
+    /// place, after which it can be read back. Calling it again on a payload
+    /// that is already complete is a no-op.
+    async fn complete(&self, id: &str) -> io::Result<()>;
+
+    /// Record that `upload_finalize` accepted the payload.
+    async fn mark_finalized(&self, id: &str) -> io::Result<()>;
+
+    /// Whether [`Storage::mark_finalized`] ran for `id`.
+    async fn is_finalized(&self, id: &str) -> io::Result<bool>;
+
+    /// Stream the completed payload, or the half-open byte `range` of it.
+    async fn read(&self, id: &str, range: Option<Range<u64>>) -> io::Result<PayloadReader>;
+
+    /// Size in bytes of the completed payload.
+    async fn size(&self, id: &str) -> io::Result<u64>;
+
+    /// Remove the payload, including any partially written state and its
+    /// finalized marker. Deleting a payload that does not exist succeeds.
+    async fn delete(&self, id: &str) -> io::Result<()>;
+
+    /// Every stored payload, without the markers. The local backend includes
+    /// payloads still being written; S3 only sees completed objects.
+    async fn list(&self) -> io::Result<Vec<StoredObject>>;
+}
+
+/// Test input that yields its bytes, then fails like a dropped connection.
+#[cfg(test)]
+pub(crate) struct Broken<'a>(pub &'a [u8]);
+
+#[cfg(test)]
+impl AsyncRead for Broken<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &mut rocket::tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        if self.0.is_empty() {
+            return std::task::Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        let n = self.0.len().min(buf.remaining());
+        buf.put_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        std::task::Poll::Ready(Ok(()))
+    }
+}
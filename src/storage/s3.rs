@@ -0,0 +1,1280 @@
+//! S3-compatible object store backend (AWS S3, MinIO, Garage, ...).
+//!
+//! A payload is built up with a multipart upload while its chunks arrive and
+//! becomes a plain object at [`Storage::complete`]. S3 rejects non-final
+//! parts smaller than [`MIN_PART_SIZE`], while clients pick their own chunk
+//! size (often 1 MB), so the payload is cut into parts of exactly that size
+//! as it streams in, whatever the chunk boundaries: at most one part is held
+//! in memory. Bytes that do not yet fill a part are kept in a `<key>.pending`
+//! object, rewritten at the end of every write together with the number of
+//! parts the payload has so far. That object is the commit record: parts
+//! uploaded by a write that then failed are not counted and get overwritten
+//! by the next write. Completing the multipart upload is the atomic move into
+//! place; the finalized marker is an empty `<key>.finalized` object.
+//!
+//! Only payloads live in the bucket. Upload sessions, retention records,
+//! revocations, download counts and Yivi sessions are kept by the process
+//! that accepted the upload (and its SQLite database), so a bucket prefix
+//! belongs to a single cryptify instance: replicas sharing one would
+//! neither see each other's uploads nor leave them alone. Give every
+//! instance its own `prefix`.
+//!
+//! The in-memory view of each multipart upload is only a cache; after a
+//! restart it is rebuilt from `ListMultipartUploads`, `ListParts` and the
+//! pending object. Multipart uploads orphaned by a crash are invisible to
+//! [`Storage::list`], so buckets should carry an
+//! `AbortIncompleteMultipartUpload` lifecycle rule as a backstop.
+//!
+//! Requests go through the AWS SDK with path-style addressing
+//! (`<endpoint>/<bucket>/<key>`), which every S3-compatible server supports.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::ops::Range;
+use std::time::Duration;
+
+use aws_sdk_s3::config::timeout::TimeoutConfig;
+use aws_sdk_s3::config::{
+    BehaviorVersion, Credentials, Region, RequestChecksumCalculation, ResponseChecksumValidation,
+};
+use aws_sdk_s3::error::{DisplayErrorContext, SdkError};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use rocket::tokio::io::AsyncReadExt;
+use serde::Deserialize;
+
+use super::{ChunkSource, PayloadReader, Storage, StoredObject};
+
+/// S3 rejects a multipart upload whose non-final parts are smaller than this.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Suffix of the object holding the bytes not yet uploaded as a part.
+const PENDING_SUFFIX: &str = ".pending";
+
+/// Suffix of the empty object written by [`Storage::mark_finalized`].
+const MARKER_SUFFIX: &str = ".finalized";
+
+/// The `[s3]` table of the configuration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct S3Config {
+    /// Base URL of the service, e.g. `https://s3.eu-central-1.amazonaws.com`
+    /// or `http://minio:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prepended to every object key, e.g. `uploads/`. Empty by default.
+    /// Must not be shared with another cryptify instance.
+    #[serde(default)]
+    pub prefix: String,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_owned()
+}
+
+pub struct S3Storage {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+    /// Cached multipart state per upload id. Entries are taken out for the
+    /// duration of an operation; `Store` serializes operations on one
+    /// upload, and a missing entry is simply rebuilt from the bucket.
+    uploads: std::sync::Mutex<HashMap<String, Multipart>>,
+}
+
+struct Multipart {
+    upload_id: String,
+    parts: Vec<Part>,
+    /// Bytes written but not yet uploaded as a part; mirrored in the pending
+    /// object.
+    pending: Vec<u8>,
+}
+
+impl Multipart {
+    fn len(&self) -> u64 {
+        self.parts.iter().map(|p| p.size).sum::<u64>() + self.pending.len() as u64
+    }
+}
+
+struct Part {
+    number: i32,
+    etag: String,
+    size: u64,
+}
+
+/// Map an SDK error to an `io::Error`, keeping a 404 recognisable as
+/// `ErrorKind::NotFound`.
+fn s3_error<E>(operation: &str, key: &str, err: SdkError<E>) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let kind = match err.raw_response().map(|r| r.status().as_u16()) {
+        Some(404) => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(
+        kind,
+        format!(
+            "S3 {} {} failed: {}",
+            operation,
+            key,
+            DisplayErrorContext(&err)
+        ),
+    )
+}
+
+fn not_found<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl S3Storage {
+    pub fn new(config: S3Config) -> Result<Self, url::ParseError> {
+        // The SDK only complains about a bad endpoint on the first request.
+        let endpoint = url::Url::parse(&config.endpoint)?;
+        if endpoint.host_str().is_none() {
+            return Err(url::ParseError::EmptyHost);
+        }
+        let sdk_config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .endpoint_url(config.endpoint.trim_end_matches('/'))
+            .region(Region::new(config.region))
+            .credentials_provider(Credentials::new(
+                config.access_key,
+                config.secret_key,
+                None,
+                None,
+                "cryptify-config",
+            ))
+            .force_path_style(true)
+            // Not every S3-compatible server understands the SDK's default
+            // CRC checksums; they are only sent where S3 requires them.
+            .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+            .response_checksum_validation(ResponseChecksumValidation::WhenRequired)
+            .timeout_config(
+                TimeoutConfig::builder()
+                    .connect_timeout(Duration::from_secs(10))
+                    .operation_timeout(Duration::from_secs(300))
+                    .build(),
+            )
+            .build();
+        Ok(S3Storage {
+            client: aws_sdk_s3::Client::from_conf(sdk_config),
+            bucket: config.bucket,
+            prefix: config.prefix,
+            uploads: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}{}", self.prefix, id)
+    }
+
+    fn pending_key(&self, id: &str) -> String {
+        format!("{}{}{}", self.prefix, id, PENDING_SUFFIX)
+    }
+
+    fn marker_key(&self, id: &str) -> String {
+        format!("{}{}{}", self.prefix, id, MARKER_SUFFIX)
+    }
+
+    async fn get_object(&self, key: &str) -> io::Result<Vec<u8>> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| s3_error("GetObject", key, e))?;
+        let bytes = output.body.collect().await.map_err(io::Error::other)?;
+        Ok(bytes.into_bytes().to_vec())
+    }
+
+    async fn put_object(&self, key: &str, body: Vec<u8>) -> io::Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map(drop)
+            .map_err(|e| s3_error("PutObject", key, e))
+    }
+
+    async fn delete_object(&self, key: &str) -> io::Result<()> {
+        let result = self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| s3_error("DeleteObject", key, e));
+        not_found(result).map(drop)
+    }
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) -> io::Result<()> {
+        let result = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| s3_error("AbortMultipartUpload", key, e));
+        not_found(result).map(drop)
+    }
+
+    async fn multipart_upload_ids(&self, key: &str) -> io::Result<Vec<String>> {
+        let listing = self
+            .client
+            .list_multipart_uploads()
+            .bucket(&self.bucket)
+            .prefix(key)
+            .send()
+            .await
+            .map_err(|e| s3_error("ListMultipartUploads", key, e))?;
+        Ok(listing
+            .uploads()
+            .iter()
+            .filter(|u| u.key() == Some(key))
+            .filter_map(|u| u.upload_id().map(str::to_owned))
+            .collect())
+    }
+
+    /// Take the multipart state of `id` out of the cache, rebuilding it from
+    /// the bucket when it is not there. `NotFound` when no multipart upload
+    /// is in flight for `id`.
+    async fn take_upload(&self, id: &str) -> io::Result<Multipart> {
+        let cached = self.uploads.lock().unwrap().remove(id);
+        match cached {
+            Some(upload) => Ok(upload),
+            None => self.recover_upload(id).await,
+        }
+    }
+
+    fn put_back(&self, id: &str, upload: Multipart) {
+        self.uploads.lock().unwrap().insert(id.to_owned(), upload);
+    }
+
+    async fn recover_upload(&self, id: &str) -> io::Result<Multipart> {
+        let key = self.key(id);
+        let upload_id = self
+            .multipart_upload_ids(&key)
+            .await?
+            .pop()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no multipart upload in flight for {}", key),
+                )
+            })?;
+
+        let listed = self
+            .client
+            .list_parts()
+            .bucket(&self.bucket)
+            .key(&key)
+            .upload_id(&upload_id)
+            .into_paginator()
+            .items()
+            .send()
+            .try_collect()
+            .await
+            .map_err(|e| s3_error("ListParts", &key, e))?;
+        let mut parts: Vec<Part> = listed
+            .into_iter()
+            .map(|p| Part {
+                number: p.part_number().unwrap_or_default(),
+                etag: p.e_tag().unwrap_or_default().to_owned(),
+                size: p.size().unwrap_or_default() as u64,
+            })
+            .collect();
+        parts.sort_by_key(|p| p.number);
+
+        // Without a pending object no write has finished yet.
+        let (committed, pending) = match not_found(self.get_object(&self.pending_key(id)).await)? {
+            Some(body) => decode_pending(&body)?,
+            None => (0, Vec::new()),
+        };
+        // Anything past the committed parts is left over from a failed write.
+        parts.truncate(committed);
+        if parts.len() != committed || parts.iter().zip(1..).any(|(p, n)| p.number != n) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("parts of {} do not match its pending object", key),
+            ));
+        }
+
+        Ok(Multipart {
+            upload_id,
+            parts,
+            pending,
+        })
+    }
+
+    /// Upload the pending bytes as the next part.
+    async fn flush_part(&self, id: &str, upload: &mut Multipart) -> io::Result<()> {
+        let key = self.key(id);
+        let number = upload.parts.len() as i32 + 1;
+        let output = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&key)
+            .upload_id(&upload.upload_id)
+            .part_number(number)
+            .body(ByteStream::from(upload.pending.clone()))
+            .send()
+            .await
+            .map_err(|e| s3_error("UploadPart", &key, e))?;
+        let etag = output
+            .e_tag()
+            .ok_or_else(|| io::Error::other("S3 UploadPart response carries no ETag"))?
+            .to_owned();
+        upload.parts.push(Part {
+            number,
+            etag,
+            size: upload.pending.len() as u64,
+        });
+        upload.pending.clear();
+        Ok(())
+    }
+
+    /// Stream `data` onto the end of the payload, uploading a part each time
+    /// [`MIN_PART_SIZE`] bytes are pending.
+    async fn append(
+        &self,
+        id: &str,
+        upload: &mut Multipart,
+        data: ChunkSource<'_>,
+    ) -> io::Result<u64> {
+        let mut written = 0;
+        loop {
+            let room = (MIN_PART_SIZE - upload.pending.len()) as u64;
+            let read = (&mut *data)
+                .take(room)
+                .read_to_end(&mut upload.pending)
+                .await?;
+            written += read as u64;
+            if upload.pending.len() < MIN_PART_SIZE {
+                return Ok(written);
+            }
+            self.flush_part(id, upload).await?;
+        }
+    }
+
+    async fn save_pending(&self, id: &str, upload: &Multipart) -> io::Result<()> {
+        self.put_object(
+            &self.pending_key(id),
+            encode_pending(upload.parts.len(), &upload.pending),
+        )
+        .await
+    }
+
+    async fn finish_upload(&self, id: &str, upload: &mut Multipart) -> io::Result<()> {
+        let key = self.key(id);
+        if upload.parts.is_empty() {
+            // Everything fits in one part: store it with a single PUT and
+            // drop the multipart upload (which must have at least one part).
+            self.put_object(&key, upload.pending.clone()).await?;
+            if let Err(e) = self.abort_upload(&key, &upload.upload_id).await {
+                log::warn!("could not abort multipart upload of {}: {}", id, e);
+            }
+            return Ok(());
+        }
+
+        // The final part is exempt from the minimum size.
+        if !upload.pending.is_empty() {
+            self.flush_part(id, upload).await?;
+        }
+        let parts = upload
+            .parts
+            .iter()
+            .map(|part| {
+                CompletedPart::builder()
+                    .part_number(part.number)
+                    .e_tag(&part.etag)
+                    .build()
+            })
+            .collect();
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&key)
+            .upload_id(&upload.upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map(drop)
+            .map_err(|e| s3_error("CompleteMultipartUpload", &key, e))
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for S3Storage {
+    async fn create(&self, id: &str) -> io::Result<()> {
+        let key = self.key(id);
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| s3_error("CreateMultipartUpload", &key, e))?;
+        let upload_id = output
+            .upload_id()
+            .ok_or_else(|| {
+                io::Error::other("S3 CreateMultipartUpload response carries no UploadId")
+            })?
+            .to_owned();
+        self.put_back(
+            id,
+            Multipart {
+                upload_id,
+                parts: Vec::new(),
+                pending: Vec::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Rolling back restores the parts and pending bytes the write started
+    /// from; the pending object in the bucket still describes them, as it is
+    /// only rewritten once the write succeeded.
+    async fn write_at(&self, id: &str, offset: u64, data: ChunkSource<'_>) -> io::Result<u64> {
+        let mut upload = self.take_upload(id).await?;
+        if offset != upload.len() {
+            let written = upload.len();
+            self.put_back(id, upload);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "S3 payloads are append-only: write at {} after {} bytes",
+                    offset, written
+                ),
+            ));
+        }
+
+        let committed_parts = upload.parts.len();
+        let committed_pending = upload.pending.clone();
+        let result = match self.append(id, &mut upload, data).await {
+            Ok(written) => self.save_pending(id, &upload).await.map(|()| written),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            upload.parts.truncate(committed_parts);
+            upload.pending = committed_pending;
+        }
+        self.put_back(id, upload);
+        result
+    }
+
+    async fn complete(&self, id: &str) -> io::Result<()> {
+        let mut upload = match self.take_upload(id).await {
+            Ok(upload) => upload,
+            // Nothing in flight: fine if a previous call already completed it.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return self.size(id).await.map(drop),
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = self.finish_upload(id, &mut upload).await {
+            self.put_back(id, upload);
+            return Err(e);
+        }
+        if let Err(e) = self.delete_object(&self.pending_key(id)).await {
+            log::warn!("could not delete pending bytes of {}: {}", id, e);
+        }
+        Ok(())
+    }
+
+    async fn mark_finalized(&self, id: &str) -> io::Result<()> {
+        self.put_object(&self.marker_key(id), Vec::new()).await
+    }
+
+    async fn is_finalized(&self, id: &str) -> io::Result<bool> {
+        let key = self.marker_key(id);
+        let result = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| s3_error("HeadObject", &key, e));
+        not_found(result).map(|found| found.is_some())
+    }
+
+    async fn read(&self, id: &str, range: Option<Range<u64>>) -> io::Result<PayloadReader> {
+        let range = match range {
+            Some(range) if range.start >= range.end => {
+                return Ok(Box::pin(rocket::tokio::io::empty()));
+            }
+            Some(range) => Some(format!("bytes={}-{}", range.start, range.end - 1)),
+            None => None,
+        };
+        let key = self.key(id);
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .set_range(range)
+            .send()
+            .await
+            .map_err(|e| s3_error("GetObject", &key, e))?;
+        Ok(Box::pin(output.body.into_async_read()))
+    }
+
+    async fn size(&self, id: &str) -> io::Result<u64> {
+        let key = self.key(id);
+        let output = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| s3_error("HeadObject", &key, e))?;
+        output
+            .content_length()
+            .map(|len| len as u64)
+            .ok_or_else(|| io::Error::other("S3 HeadObject response carries no Content-Length"))
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()> {
+        self.uploads.lock().unwrap().remove(id);
+        // Marker first: a payload must never look finalized without its bytes.
+        self.delete_object(&self.marker_key(id)).await?;
+        let key = self.key(id);
+        for upload_id in self.multipart_upload_ids(&key).await? {
+            self.abort_upload(&key, &upload_id).await?;
+        }
+        self.delete_object(&self.pending_key(id)).await?;
+        self.delete_object(&key).await
+    }
+
+    async fn list(&self) -> io::Result<Vec<StoredObject>> {
+        let pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&self.prefix)
+            .into_paginator()
+            .send()
+            .try_collect()
+            .await
+            .map_err(|e| s3_error("ListObjectsV2", &self.prefix, e))?;
+
+        let mut objects = Vec::new();
+        let mut finalized = HashSet::new();
+        for object in pages.iter().flat_map(|page| page.contents()) {
+            let Some(id) = object.key().and_then(|k| k.strip_prefix(&self.prefix)) else {
+                continue;
+            };
+            if id.ends_with(PENDING_SUFFIX) {
+                continue;
+            }
+            if let Some(id) = id.strip_suffix(MARKER_SUFFIX) {
+                finalized.insert(id.to_owned());
+                continue;
+            }
+            objects.push(StoredObject {
+                id: id.to_owned(),
+                size: object.size().unwrap_or_default() as u64,
+                modified: object.last_modified().map_or(0, |t| t.secs()),
+                finalized: false,
+                // A payload still being written is a multipart upload, not
+                // an object, until it is completed.
+                in_progress: false,
+            });
+        }
+        for object in &mut objects {
+            object.finalized = finalized.contains(&object.id);
+        }
+        Ok(objects)
+    }
+}
+
+/// The pending object starts with the number of parts that belong to the
+/// payload, followed by the bytes that come after them.
+fn encode_pending(part_count: usize, pending: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + pending.len());
+    body.extend_from_slice(&(part_count as u32).to_be_bytes());
+    body.extend_from_slice(pending);
+    body
+}
+
+fn decode_pending(body: &[u8]) -> io::Result<(usize, Vec<u8>)> {
+    let (count, rest) = body
+        .split_first_chunk::<4>()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated pending object"))?;
+    Ok((u32::from_be_bytes(*count) as usize, rest.to_vec()))
+}
+
+/// In-process stand-in for an S3-compatible server, covering the subset of
+/// the API `S3Storage` uses — a local MinIO for tests that cannot run one.
+/// Requests must be signed for the test credentials, and multipart
+/// completion enforces the minimum part size, so the tests fail the way a
+/// real server would.
+#[cfg(test)]
+pub mod fake {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    use chrono::Utc;
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use rocket::tokio::net::{TcpListener, TcpStream};
+    use sha2::{Digest, Sha256};
+
+    use super::{S3Config, MIN_PART_SIZE};
+    use crate::bytes_to_hex;
+
+    pub const BUCKET: &str = "cryptify";
+    const ACCESS_KEY: &str = "test-access-key";
+    const SECRET_KEY: &str = "test-secret-key";
+    /// Small, so listing tests exercise pagination.
+    const LIST_PAGE_SIZE: usize = 2;
+
+    #[derive(Default)]
+    struct State {
+        /// key → (bytes, last modified)
+        objects: HashMap<String, (Vec<u8>, i64)>,
+        /// upload id → (key, parts)
+        uploads: HashMap<String, (String, BTreeMap<u32, Vec<u8>>)>,
+        next_upload: u64,
+        /// Size of the largest request body received.
+        largest_body: usize,
+    }
+
+    pub struct FakeS3 {
+        endpoint: String,
+        state: Arc<Mutex<State>>,
+    }
+
+    impl FakeS3 {
+        pub async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let endpoint = format!("http://{}", listener.local_addr().unwrap());
+            let state = Arc::new(Mutex::new(State::default()));
+            let shared = state.clone();
+            rocket::tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    rocket::tokio::spawn(serve(stream, shared.clone()));
+                }
+            });
+            FakeS3 { endpoint, state }
+        }
+
+        pub fn config(&self, prefix: &str) -> S3Config {
+            S3Config {
+                endpoint: self.endpoint.clone(),
+                bucket: BUCKET.to_owned(),
+                region: "us-east-1".to_owned(),
+                access_key: ACCESS_KEY.to_owned(),
+                secret_key: SECRET_KEY.to_owned(),
+                prefix: prefix.to_owned(),
+            }
+        }
+
+        pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+            let state = self.state.lock().unwrap();
+            state.objects.get(key).map(|(bytes, _)| bytes.clone())
+        }
+
+        pub fn put_object(&self, key: &str, bytes: Vec<u8>) {
+            let mut state = self.state.lock().unwrap();
+            state.objects.insert(key.to_owned(), (bytes, 0));
+        }
+
+        pub fn object_keys(&self) -> Vec<String> {
+            let mut keys: Vec<String> =
+                self.state.lock().unwrap().objects.keys().cloned().collect();
+            keys.sort();
+            keys
+        }
+
+        pub fn in_flight_uploads(&self) -> usize {
+            self.state.lock().unwrap().uploads.len()
+        }
+
+        pub fn largest_body(&self) -> usize {
+            self.state.lock().unwrap().largest_body
+        }
+    }
+
+    struct Request {
+        method: String,
+        path: String,
+        query: Vec<(String, String)>,
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    impl Request {
+        fn param(&self, name: &str) -> Option<&str> {
+            self.query
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    struct Response {
+        status: u16,
+        headers: Vec<(&'static str, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Response {
+        fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+            Response {
+                status,
+                headers: Vec::new(),
+                body: body.into(),
+            }
+        }
+
+        fn error(status: u16, code: &str) -> Self {
+            Response::new(
+                status,
+                format!("<Error><Code>{}</Code></Error>", code).into_bytes(),
+            )
+        }
+    }
+
+    async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+        let Some(request) = read_request(&mut stream).await else {
+            return;
+        };
+        let response = if is_authorized(&request) {
+            handle(&request, &mut state.lock().unwrap())
+        } else {
+            Response::error(403, "SignatureDoesNotMatch")
+        };
+
+        let mut head = format!(
+            "HTTP/1.1 {} Fake\r\nContent-Length: {}\r\nConnection: close\r\n",
+            response.status,
+            response.body.len()
+        );
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        let _ = stream.write_all(head.as_bytes()).await;
+        if request.method != "HEAD" {
+            let _ = stream.write_all(&response.body).await;
+        }
+        let _ = stream.shutdown().await;
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 64 * 1024];
+        let header_end = loop {
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos;
+            }
+            let n = stream.read(&mut chunk).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        };
+
+        let head = String::from_utf8(buf[..header_end].to_vec()).ok()?;
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next()?.split(' ');
+        let method = request_line.next()?.to_owned();
+        let target = request_line.next()?;
+        let (path, raw_query) = target.split_once('?').unwrap_or((target, ""));
+        let query = raw_query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (k, v) = p.split_once('=').unwrap_or((p, ""));
+                (percent_decode(k), percent_decode(v))
+            })
+            .collect();
+        let headers: HashMap<String, String> = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_owned()))
+            .collect();
+
+        let length: usize = headers
+            .get("content-length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let mut body = buf[header_end + 4..].to_vec();
+        while body.len() < length {
+            let n = stream.read(&mut chunk).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+            body.extend_from_slice(&chunk[..n]);
+        }
+
+        Some(Request {
+            method,
+            path: path.to_owned(),
+            query,
+            headers,
+            body,
+        })
+    }
+
+    fn percent_decode(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' && i + 2 < bytes.len() {
+                if let Ok(b) = u8::from_str_radix(&s[i + 1..i + 3], 16) {
+                    out.push(b);
+                    i += 3;
+                    continue;
+                }
+            }
+            out.push(bytes[i]);
+            i += 1;
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Signed by the SDK for the configured access key and region.
+    fn is_authorized(request: &Request) -> bool {
+        let credential = format!("AWS4-HMAC-SHA256 Credential={}/", ACCESS_KEY);
+        request
+            .headers
+            .get("authorization")
+            .is_some_and(|a| a.starts_with(&credential) && a.contains("/us-east-1/s3/aws4_request"))
+    }
+
+    fn xml_values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+        let open = format!("<{}>", tag);
+        let close = format!("</{}>", tag);
+        xml.split(open.as_str())
+            .skip(1)
+            .filter_map(|rest| rest.split(close.as_str()).next())
+            .collect()
+    }
+
+    fn etag(bytes: &[u8]) -> String {
+        format!("\"{}\"", &bytes_to_hex(&Sha256::digest(bytes))[..32])
+    }
+
+    fn handle(request: &Request, state: &mut State) -> Response {
+        state.largest_body = state.largest_body.max(request.body.len());
+        let Some(rest) = request.path.strip_prefix(&format!("/{}", BUCKET)) else {
+            return Response::error(404, "NoSuchBucket");
+        };
+        let key = rest
+            .strip_prefix('/')
+            .filter(|k| !k.is_empty())
+            .map(percent_decode);
+        let upload_id = request.param("uploadId");
+
+        match (request.method.as_str(), key) {
+            ("GET", None) if request.param("uploads").is_some() => {
+                let prefix = request.param("prefix").unwrap_or("");
+                let mut xml = String::from("<ListMultipartUploadsResult>");
+                for (id, (key, _)) in &state.uploads {
+                    if key.starts_with(prefix) {
+                        xml.push_str(&format!(
+                            "<Upload><Key>{}</Key><UploadId>{}</UploadId></Upload>",
+                            key, id
+                        ));
+                    }
+                }
+                xml.push_str("</ListMultipartUploadsResult>");
+                Response::new(200, xml)
+            }
+            ("GET", None) if request.param("list-type") == Some("2") => {
+                let prefix = request.param("prefix").unwrap_or("");
+                let after = request.param("continuation-token").unwrap_or("");
+                let mut keys: Vec<&String> = state
+                    .objects
+                    .keys()
+                    .filter(|k| k.starts_with(prefix) && k.as_str() > after)
+                    .collect();
+                keys.sort();
+                let truncated = keys.len() > LIST_PAGE_SIZE;
+                keys.truncate(LIST_PAGE_SIZE);
+                let mut xml = format!("<ListBucketResult><IsTruncated>{}</IsTruncated>", truncated);
+                if truncated {
+                    xml.push_str(&format!(
+                        "<NextContinuationToken>{}</NextContinuationToken>",
+                        keys.last().unwrap()
+                    ));
+                }
+                for key in keys {
+                    let (bytes, modified) = &state.objects[key];
+                    let modified = chrono::DateTime::from_timestamp(*modified, 0).unwrap();
+                    xml.push_str(&format!(
+                        "<Contents><Key>{}</Key><LastModified>{}</LastModified><Size>{}</Size></Contents>",
+                        key,
+                        modified.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+                        bytes.len()
+                    ));
+                }
+                xml.push_str("</ListBucketResult>");
+                Response::new(200, xml)
+            }
+            ("POST", Some(key)) if request.param("uploads").is_some() => {
+                state.next_upload += 1;
+                let id = format!("upload-{}", state.next_upload);
+                state.uploads.insert(id.clone(), (key, BTreeMap::new()));
+                Response::new(
+                    200,
+                    format!(
+                        "<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                        id
+                    ),
+                )
+            }
+            ("PUT", Some(_)) if upload_id.is_some() => {
+                let Some((_, parts)) = state.uploads.get_mut(upload_id.unwrap()) else {
+                    return Response::error(404, "NoSuchUpload");
+                };
+                let Some(number) = request.param("partNumber").and_then(|n| n.parse().ok()) else {
+                    return Response::error(400, "InvalidArgument");
+                };
+                parts.insert(number, request.body.clone());
+                let mut response = Response::new(200, Vec::new());
+                response.headers.push(("ETag", etag(&request.body)));
+                response
+            }
+            ("GET", Some(_)) if upload_id.is_some() => {
+                let Some((_, parts)) = state.uploads.get(upload_id.unwrap()) else {
+                    return Response::error(404, "NoSuchUpload");
+                };
+                let mut xml = String::from("<ListPartsResult><IsTruncated>false</IsTruncated>");
+                for (number, bytes) in parts {
+                    xml.push_str(&format!(
+                        "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag><Size>{}</Size></Part>",
+                        number,
+                        etag(bytes).replace('"', "&quot;"),
+                        bytes.len()
+                    ));
+                }
+                xml.push_str("</ListPartsResult>");
+                Response::new(200, xml)
+            }
+            ("POST", Some(key)) if upload_id.is_some() => {
+                let Some((_, parts)) = state.uploads.get(upload_id.unwrap()) else {
+                    return Response::error(404, "NoSuchUpload");
+                };
+                let body = String::from_utf8_lossy(&request.body);
+                let numbers = xml_values(&body, "PartNumber");
+                let etags = xml_values(&body, "ETag");
+                if numbers.is_empty() || numbers.len() != etags.len() {
+                    return Response::error(400, "MalformedXML");
+                }
+                let mut object = Vec::new();
+                for (i, (number, tag)) in numbers.iter().zip(etags).enumerate() {
+                    let Some(bytes) = number.parse().ok().and_then(|n: u32| parts.get(&n)) else {
+                        return Response::error(400, "InvalidPart");
+                    };
+                    if etag(bytes) != tag.replace("&quot;", "\"") {
+                        return Response::error(400, "InvalidPart");
+                    }
+                    if i + 1 < numbers.len() && bytes.len() < MIN_PART_SIZE {
+                        return Response::error(400, "EntityTooSmall");
+                    }
+                    object.extend_from_slice(bytes);
+                }
+                state.uploads.remove(upload_id.unwrap());
+                state.objects.insert(key, (object, Utc::now().timestamp()));
+                Response::new(200, "<CompleteMultipartUploadResult/>")
+            }
+            ("DELETE", Some(_)) if upload_id.is_some() => {
+                match state.uploads.remove(upload_id.unwrap()) {
+                    Some(_) => Response::new(204, Vec::new()),
+                    None => Response::error(404, "NoSuchUpload"),
+                }
+            }
+            ("PUT", Some(key)) => {
+                state
+                    .objects
+                    .insert(key, (request.body.clone(), Utc::now().timestamp()));
+                Response::new(200, Vec::new())
+            }
+            ("GET" | "HEAD", Some(key)) => {
+                let Some((bytes, _)) = state.objects.get(&key) else {
+                    return Response::error(404, "NoSuchKey");
+                };
+                let range = request
+                    .headers
+                    .get("range")
+                    .and_then(|r| r.strip_prefix("bytes="))
+                    .and_then(|r| r.split_once('-'))
+                    .and_then(|(s, e)| Some((s.parse::<usize>().ok()?, e.parse::<usize>().ok()?)));
+                match range {
+                    Some((start, end)) => {
+                        let end = end.min(bytes.len() - 1);
+                        let mut response = Response::new(206, bytes[start..=end].to_vec());
+                        response.headers.push((
+                            "Content-Range",
+                            format!("bytes {}-{}/{}", start, end, bytes.len()),
+                        ));
+                        response
+                    }
+                    None => Response::new(200, bytes.clone()),
+                }
+            }
+            ("DELETE", Some(key)) => {
+                state.objects.remove(&key);
+                Response::new(204, Vec::new())
+            }
+            _ => Response::error(400, "NotImplemented"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::FakeS3;
+    use super::*;
+    use crate::storage::Broken;
+    use rocket::tokio::io::AsyncReadExt;
+
+    const MIB: usize = 1024 * 1024;
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn read_all(reader: PayloadReader) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut reader = reader;
+        reader.read_to_end(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[rocket::async_test]
+    async fn small_chunks_are_coalesced_into_valid_parts() {
+        let s3 = FakeS3::start().await;
+        let storage = S3Storage::new(s3.config("uploads/")).unwrap();
+        let data = payload(7 * MIB);
+
+        storage.create("f").await.unwrap();
+        for (i, chunk) in data.chunks(MIB).enumerate() {
+            storage
+                .write_at("f", (i * MIB) as u64, &mut &chunk[..])
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            storage.size("f").await.unwrap_err().kind(),
+            io::ErrorKind::NotFound,
+            "nothing is readable before completion"
+        );
+        storage.complete("f").await.unwrap();
+        storage
+            .complete("f")
+            .await
+            .expect("completing twice succeeds");
+
+        assert_eq!(s3.object("uploads/f").as_deref(), Some(&data[..]));
+        assert_eq!(s3.object_keys(), vec!["uploads/f".to_owned()]);
+        assert_eq!(s3.in_flight_uploads(), 0);
+        assert_eq!(storage.size("f").await.unwrap(), data.len() as u64);
+        assert_eq!(
+            read_all(storage.read("f", Some(10..20)).await.unwrap()).await,
+            &data[10..20]
+        );
+        assert_eq!(read_all(storage.read("f", None).await.unwrap()).await, data);
+    }
+
+    #[rocket::async_test]
+    async fn small_payload_is_stored_with_a_single_put() {
+        let s3 = FakeS3::start().await;
+        let storage = S3Storage::new(s3.config("")).unwrap();
+
+        storage.create("f").await.unwrap();
+        storage.write_at("f", 0, &mut &b"tiny"[..]).await.unwrap();
+        storage.complete("f").await.unwrap();
+
+        assert_eq!(s3.object("f").as_deref(), Some(&b"tiny"[..]));
+        assert_eq!(s3.in_flight_uploads(), 0);
+    }
+
+    /// A chunk is cut into parts as it streams in, so neither the process
+    /// nor a single request ever holds much more than one part.
+    #[rocket::async_test]
+    async fn large_chunks_are_streamed_in_parts() {
+        let s3 = FakeS3::start().await;
+        let storage = S3Storage::new(s3.config("")).unwrap();
+        let data = payload(17 * MIB);
+
+        storage.create("f").await.unwrap();
+        storage.write_at("f", 0, &mut &data[..]).await.unwrap();
+        assert!(s3.largest_body() <= MIN_PART_SIZE + 4);
+        storage.complete("f").await.unwrap();
+        assert_eq!(s3.object("f").as_deref(), Some(&data[..]));
+    }
+
+    /// Parts a broken write already uploaded are dropped, both by the
+    /// process that saw it fail and after a restart.
+    #[rocket::async_test]
+    async fn broken_write_rolls_back_uploaded_parts() {
+        let s3 = FakeS3::start().await;
+        let data = payload(12 * MIB);
+
+        let storage = S3Storage::new(s3.config("")).unwrap();
+        storage.create("f").await.unwrap();
+        storage
+            .write_at("f", 0, &mut &data[..3 * MIB])
+            .await
+            .unwrap();
+        let err = storage
+            .write_at("f", 3 * MIB as u64, &mut Broken(&data[3 * MIB..9 * MIB]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        let err = storage.write_at("f", 9 * MIB as u64, &mut &b"x"[..]).await;
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        storage
+            .write_at("f", 3 * MIB as u64, &mut &data[3 * MIB..6 * MIB])
+            .await
+            .unwrap();
+        let err = storage
+            .write_at("f", 6 * MIB as u64, &mut Broken(&data[6 * MIB..]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        drop(storage);
+
+        let restarted = S3Storage::new(s3.config("")).unwrap();
+        restarted
+            .write_at("f", 6 * MIB as u64, &mut &data[6 * MIB..])
+            .await
+            .unwrap();
+        restarted.complete("f").await.unwrap();
+
+        assert_eq!(s3.object("f").as_deref(), Some(&data[..]));
+        assert_eq!(s3.object_keys(), vec!["f".to_owned()]);
+    }
+
+    #[rocket::async_test]
+    async fn finalized_marker_is_a_separate_object() {
+        let s3 = FakeS3::start().await;
+        let storage = S3Storage::new(s3.config("uploads/")).unwrap();
+
+        storage.create("f").await.unwrap();
+        storage
+            .write_at("f", 0, &mut &b"payload"[..])
+            .await
+            .unwrap();
+        storage.complete("f").await.unwrap();
+        assert!(!storage.is_finalized("f").await.unwrap());
+
+        storage.mark_finalized("f").await.unwrap();
+        assert!(storage.is_finalized("f").await.unwrap());
+        assert_eq!(s3.object("uploads/f.finalized").as_deref(), Some(&b""[..]));
+        assert_eq!(s3.object("uploads/f").as_deref(), Some(&b"payload"[..]));
+    }
+
+    #[rocket::async_test]
+    async fn write_must_append() {
+        let s3 = FakeS3::start().await;
+        let storage = S3Storage::new(s3.config("")).unwrap();
+
+        storage.create("f").await.unwrap();
+        storage.write_at("f", 0, &mut &b"abc"[..]).await.unwrap();
+        let err = storage.write_at("f", 1, &mut &b"x"[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        storage.write_at("f", 3, &mut &b"def"[..]).await.unwrap();
+        storage.complete("f").await.unwrap();
+        assert_eq!(s3.object("f").as_deref(), Some(&b"abcdef"[..]));
+
+        let err = storage
+            .write_at("never-created", 0, &mut &b"x"[..])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn delete_removes_in_flight_and_completed_payloads() {
+        let s3 = FakeS3::start().await;
+        let storage = S3Storage::new(s3.config("")).unwrap();
+
+        storage.create("done").await.unwrap();
+        storage
+            .write_at("done", 0, &mut &b"payload"[..])
+            .await
+            .unwrap();
+        storage.complete("done").await.unwrap();
+        storage.mark_finalized("done").await.unwrap();
+        storage.create("partial").await.unwrap();
+        storage
+            .write_at("partial", 0, &mut &b"half"[..])
+            .await
+            .unwrap();
+
+        storage.delete("done").await.unwrap();
+        storage.delete("partial").await.unwrap();
+        storage
+            .delete("partial")
+            .await
+            .expect("deleting twice succeeds");
+
+        assert!(s3.object_keys().is_empty());
+        assert_eq!(s3.in_flight_uploads(), 0);
+        assert!(!storage.is_finalized("done").await.unwrap());
+        assert_eq!(
+            storage.read("done", None).await.err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[rocket::async_test]
+    async fn list_pages_through_completed_payloads_only() {
+        let s3 = FakeS3::start().await;
+        let storage = S3Storage::new(s3.config("p/")).unwrap();
+        for id in ["a", "b", "c"] {
+            storage.create(id).await.unwrap();
+            storage.write_at(id, 0, &mut id.as_bytes()).await.unwrap();
+            storage.complete(id).await.unwrap();
+        }
+        storage.mark_finalized("b").await.unwrap();
+        storage.create("in-flight").await.unwrap();
+        storage
+            .write_at("in-flight", 0, &mut &b"half"[..])
+            .await
+            .unwrap();
+        s3.put_object("elsewhere", b"not ours".to_vec());
+
+        let mut ids: Vec<(String, u64, bool)> = storage
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| (o.id, o.size, o.finalized))
+            .collect();
+        ids.sort();
+        assert_eq!(
+            ids,
+            vec![
+                ("a".into(), 1, false),
+                ("b".into(), 1, true),
+                ("c".into(), 1, false)
+            ]
+        );
+    }
+}
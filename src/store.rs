@@ -1,13 +1,19 @@
 use crate::email;
 use crate::metrics::Metrics;
+use crate::storage::Storage;
 
 use std::{
-    collections::{BTreeMap, HashMap, VecDeque},
-    sync::Arc,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    sync::{Arc, Weak},
     time::Duration,
 };
 
-use rocket::tokio::{sync::Notify, time::Instant};
+use pg_core::identity::HiddenPolicy;
+use rocket::tokio::{
+    sync::{mpsc::UnboundedSender, Notify},
+    time::Instant,
+};
+use serde::{Deserialize, Serialize};
 
 pub const PER_UPLOAD_LIMIT: u64 = 5_000_000_000;
 pub const ROLLING_LIMIT: u64 = 5_000_000_000;
@@ -15,17 +21,28 @@ pub const API_KEY_PER_UPLOAD_LIMIT: u64 = 100_000_000_000;
 pub const API_KEY_ROLLING_LIMIT: u64 = 100_000_000_000;
 pub const ROLLING_WINDOW_SECS: i64 = 14 * 24 * 60 * 60;
 
+/// How long a finalized upload stays downloadable unless the sender chose
+/// otherwise. `upload_init` stamps `FileState.expires` with the lifetime, the
+/// notification email quotes that date, and the retention reaper deletes the
+/// payload from storage once it has passed.
+pub const UPLOAD_LIFETIME_SECS: i64 = 14 * 24 * 60 * 60;
+
 /// Default idle window for an in-memory upload session when no value is
 /// provided in config. Each successful chunk PUT resets it; if no activity
-/// is seen for this long the session is evicted (the on-disk file is left
-/// alone — `FileState.expires` covers that).
+/// is seen for this long the session is evicted; a session that never
+/// finalized takes its partial payload with it.
 #[cfg(test)]
 pub const DEFAULT_UPLOAD_SESSION_IDLE_TIMEOUT_SECS: u64 = 60 * 60;
 
+/// In-flight upload session. Serialized into the `upload_sessions` table on
+/// every change so a restart can pick up where the client left off; fields
+/// added later must carry `#[serde(default)]` so older rows still load.
+#[derive(Serialize, Deserialize)]
 pub struct FileState {
     pub uploaded: u64,
     pub cryptify_token: String,
     pub expires: i64,
+    #[serde(with = "mailboxes_as_string")]
     pub recipients: lettre::message::Mailboxes,
     pub mail_content: String,
     pub mail_lang: email::Language,
@@ -77,6 +94,189 @@ pub struct FileState {
     /// `X-Recovery-Token` header. Compared in constant time to defeat
     /// timing oracles. Hex-encoded 32-byte random.
     pub recovery_token: String,
+    /// Set once `upload_finalize` has verified the payload and sent the
+    /// notification emails. From then on the upload is immutable: chunk
+    /// PUTs are refused, and a retried finalize returns the original result
+    /// without mailing or accounting again.
+    #[serde(default)]
+    pub finalized: bool,
+    /// Rolling-quota bytes held for this upload while it is in flight; see
+    /// [`Store::reserve`]. Mirrored into the store's reservation index, and
+    /// persisted with the session so a restart does not drop it.
+    #[serde(default)]
+    pub reservation: Option<Reservation>,
+    /// Set once the sealed header at the start of the payload has been
+    /// parsed and its signature verified, see `upload_chunk`.
+    #[serde(default)]
+    pub header_verified: bool,
+    /// The first bytes of the payload, kept in memory until they hold the
+    /// whole sealed header. Not persisted: a session reloaded before its
+    /// header was complete is only checked at finalize.
+    #[serde(skip)]
+    pub sealed_prefix: Vec<u8>,
+    /// Set when the upload was started in parallel mode: chunks are then
+    /// addressed by index and may arrive concurrently and in any order.
+    /// `None` for the default sequential mode with its rolling token.
+    #[serde(default)]
+    pub chunked: Option<ChunkedUpload>,
+    /// The `Upload-Length` of a session created through the tus endpoints,
+    /// which finalize it as soon as that many bytes are stored. `None` for
+    /// sessions started with `POST /fileupload/init`.
+    #[serde(default)]
+    pub upload_length: Option<u64>,
+    /// The size the client declared at init, past which no chunk may go.
+    /// Reserved against the sender's rolling quota at init for API-key
+    /// uploads, and once the sealed header names the sender otherwise.
+    #[serde(default)]
+    pub declared_size: Option<u64>,
+    /// How many completed downloads the sender allows; see
+    /// [`FinalizedUpload::max_downloads`]. `None` means unlimited.
+    #[serde(default)]
+    pub max_downloads: Option<u32>,
+    /// Notified addresses the sealed header has no recipient for, found at
+    /// finalize under `recipient_check = "flag"`. Kept so a retried
+    /// finalize reports them again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unsealable_recipients: Vec<String>,
+}
+
+/// Which chunks of a parallel upload are stored. Chunk `i` covers the bytes
+/// from `i * chunk_size`; every chunk is `chunk_size` long except the last,
+/// which may be shorter.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChunkedUpload {
+    pub chunk_size: u64,
+    /// Indexes of the stored chunks as sorted, disjoint, half-open runs, so
+    /// a multi-GB upload stays a handful of entries in the session row.
+    pub received: Vec<(u64, u64)>,
+    /// Index and length of a stored chunk shorter than `chunk_size`.
+    pub short_chunk: Option<(u64, u64)>,
+    /// Chunks being written right now. Not persisted: those requests do not
+    /// survive a restart either.
+    #[serde(skip)]
+    pub in_flight: HashSet<u64>,
+}
+
+impl ChunkedUpload {
+    pub fn new(chunk_size: u64) -> Self {
+        ChunkedUpload {
+            chunk_size,
+            ..Default::default()
+        }
+    }
+
+    /// Whether chunk `index` may be `len` bytes long, given the chunks
+    /// stored so far (not counting an earlier copy of `index` itself).
+    pub fn check(&self, index: u64, len: u64) -> Result<(), &'static str> {
+        if len == 0 || len > self.chunk_size {
+            return Err("Chunk must be between 1 byte and the chunk size long");
+        }
+        let last_index = self.received.last().map(|&(_, end)| end - 1);
+        match self.short_chunk {
+            Some((short, _)) if short < index => {
+                Err("Chunk lies past the shorter last chunk of the upload")
+            }
+            Some((short, _)) if short != index && len < self.chunk_size => {
+                Err("Only the last chunk may be shorter than the chunk size")
+            }
+            _ if len < self.chunk_size && last_index.is_some_and(|last| last > index) => {
+                Err("Only the last chunk may be shorter than the chunk size")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record chunk `index` of `len` bytes as stored.
+    pub fn insert(&mut self, index: u64, len: u64) {
+        self.remove(index);
+        if len < self.chunk_size {
+            self.short_chunk = Some((index, len));
+        }
+        let at = self.received.partition_point(|&(_, end)| end < index);
+        let joins_prev = self.received.get(at).is_some_and(|&(_, end)| end == index);
+        let next = if joins_prev { at + 1 } else { at };
+        let joins_next = self
+            .received
+            .get(next)
+            .is_some_and(|&(start, _)| start == index + 1);
+        match (joins_prev, joins_next) {
+            (true, true) => {
+                self.received[at].1 = self.received[next].1;
+                self.received.remove(next);
+            }
+            (true, false) => self.received[at].1 = index + 1,
+            (false, true) => self.received[next].0 = index,
+            (false, false) => self.received.insert(at, (index, index + 1)),
+        }
+    }
+
+    /// Forget chunk `index`, e.g. because it is being written again.
+    pub fn remove(&mut self, index: u64) {
+        if self.short_chunk.is_some_and(|(short, _)| short == index) {
+            self.short_chunk = None;
+        }
+        let Some(at) = self
+            .received
+            .iter()
+            .position(|&(start, end)| (start..end).contains(&index))
+        else {
+            return;
+        };
+        let (start, end) = self.received[at];
+        match (start == index, end == index + 1) {
+            (true, true) => {
+                self.received.remove(at);
+            }
+            (true, false) => self.received[at].0 = index + 1,
+            (false, true) => self.received[at].1 = index,
+            (false, false) => {
+                self.received[at].1 = index;
+                self.received.insert(at + 1, (index + 1, end));
+            }
+        }
+    }
+
+    pub fn received_bytes(&self) -> u64 {
+        let chunks: u64 = self.received.iter().map(|&(start, end)| end - start).sum();
+        let short = self.short_chunk.map_or(0, |(_, len)| self.chunk_size - len);
+        chunks * self.chunk_size - short
+    }
+
+    /// The first chunk index missing from an upload of `total` bytes, or
+    /// where the stored chunks stop matching it (chunks past the end, a
+    /// last chunk of the wrong length). `None` if exactly `total` bytes are
+    /// stored, without gaps.
+    pub fn first_missing(&self, total: u64) -> Option<u64> {
+        let count = total.div_ceil(self.chunk_size);
+        let stored = match self.received.first() {
+            Some(&(0, end)) => end,
+            _ => 0,
+        };
+        if stored < count {
+            return Some(stored);
+        }
+        let tail = total % self.chunk_size;
+        let expected_short = (tail != 0).then(|| (count - 1, tail));
+        let whole = if count == 0 {
+            self.received.is_empty()
+        } else {
+            self.received == [(0, count)]
+        };
+        if !whole || self.short_chunk != expected_short {
+            // Chunks past the end, or a last chunk of the wrong length:
+            // `total` does not describe what was uploaded.
+            return Some(count);
+        }
+        None
+    }
+}
+
+/// Quota held against `key` (an accounting key as used by
+/// [`Store::record_upload`]) for an upload that has not been booked yet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub key: String,
+    pub bytes: u64,
 }
 
 /// Replay record of the most recently committed chunk. See
@@ -87,7 +287,7 @@ pub struct FileState {
 /// the same construction the rolling-token chain itself relies on, so no
 /// separate digest needs to be cached. Length differences also surface as
 /// a hash mismatch.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LastChunkRecord {
     /// The `CryptifyToken` the client sent in the chunk PUT — i.e., the
     /// rolling token *before* this chunk advanced it. A retry that lost the
@@ -102,12 +302,77 @@ pub struct LastChunkRecord {
     pub response_token: String,
 }
 
+/// `Mailboxes` has no serde support of its own; persist it in the same
+/// comma-separated form `upload_init` parses it from.
+mod mailboxes_as_string {
+    use lettre::message::Mailboxes;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mailboxes: &Mailboxes, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&mailboxes.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Mailboxes, D::Error> {
+        let raw = String::deserialize(d)?;
+        if raw.is_empty() {
+            return Ok(Mailboxes::new());
+        }
+        raw.parse().map_err(D::Error::custom)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 struct UploadRecord {
     timestamp: i64,
     bytes: u64,
 }
 
+type DbJob = Box<dyn FnOnce(&rusqlite::Connection) + Send>;
+
+/// A SQLite connection owned by a thread of its own. Jobs run there one at
+/// a time, in the order they were queued, so the write-through on the
+/// upload and download paths costs a channel send instead of blocking the
+/// async executor on disk I/O. The thread ends once the owner is dropped
+/// and the queue is drained.
+struct DbThread {
+    jobs: std::sync::mpsc::Sender<DbJob>,
+}
+
+impl DbThread {
+    fn spawn(conn: rusqlite::Connection) -> Self {
+        let (jobs, queue) = std::sync::mpsc::channel::<DbJob>();
+        std::thread::Builder::new()
+            .name("cryptify-db".to_owned())
+            .spawn(move || {
+                for job in queue {
+                    job(&conn);
+                }
+            })
+            .expect("spawn database thread");
+        DbThread { jobs }
+    }
+
+    /// Queue `job` without waiting for it.
+    fn submit(&self, job: impl FnOnce(&rusqlite::Connection) + Send + 'static) {
+        if self.jobs.send(Box::new(job)).is_err() {
+            log::error!("database thread is gone, dropping a write");
+        }
+    }
+
+    /// Run `job` after everything queued before it and wait for its result.
+    /// Blocks, so only for startup loads and [`Store`]'s drop.
+    fn call<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&rusqlite::Connection) -> T + Send + 'static,
+    ) -> T {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.submit(move |conn| {
+            let _ = tx.send(job(conn));
+        });
+        rx.recv().expect("database thread is gone")
+    }
+}
+
 /// SQLite-backed persistence for the rolling-quota usage state.
 ///
 /// The in-memory `StoreState.usage` map is only a cache: this database is
@@ -115,12 +380,8 @@ struct UploadRecord {
 /// redeploys. On startup the full table is loaded back into the cache
 /// ([`UsageDb::load_all`]); every accounted upload is written through here
 /// ([`UsageDb::record`]) before the cache is updated.
-///
-/// The connection is wrapped in a `Mutex` because `rusqlite::Connection`
-/// is `Send` but not `Sync`, and `SharedState` is shared across the purge
-/// task via an `Arc`.
 struct UsageDb {
-    conn: std::sync::Mutex<rusqlite::Connection>,
+    db: DbThread,
 }
 
 impl UsageDb {
@@ -145,7 +406,7 @@ impl UsageDb {
             [],
         )?;
         Ok(UsageDb {
-            conn: std::sync::Mutex::new(conn),
+            db: DbThread::spawn(conn),
         })
     }
 
@@ -155,25 +416,26 @@ impl UsageDb {
     /// pruned here: pruning is relative to the caller-supplied `now`, which
     /// only the request path knows.
     fn load_all(&self) -> rusqlite::Result<HashMap<String, VecDeque<UploadRecord>>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt =
-            conn.prepare("SELECT email, timestamp, bytes FROM usage ORDER BY timestamp ASC")?;
-        let rows = stmt.query_map([], |row| {
-            let email: String = row.get(0)?;
-            let timestamp: i64 = row.get(1)?;
-            let bytes: i64 = row.get(2)?;
-            Ok((email, timestamp, bytes))
-        })?;
-
-        let mut map: HashMap<String, VecDeque<UploadRecord>> = HashMap::new();
-        for row in rows {
-            let (email, timestamp, bytes) = row?;
-            map.entry(email).or_default().push_back(UploadRecord {
-                timestamp,
-                bytes: bytes as u64,
-            });
-        }
-        Ok(map)
+        self.db.call(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT email, timestamp, bytes FROM usage ORDER BY timestamp ASC")?;
+            let rows = stmt.query_map([], |row| {
+                let email: String = row.get(0)?;
+                let timestamp: i64 = row.get(1)?;
+                let bytes: i64 = row.get(2)?;
+                Ok((email, timestamp, bytes))
+            })?;
+
+            let mut map: HashMap<String, VecDeque<UploadRecord>> = HashMap::new();
+            for row in rows {
+                let (email, timestamp, bytes) = row?;
+                map.entry(email).or_default().push_back(UploadRecord {
+                    timestamp,
+                    bytes: bytes as u64,
+                });
+            }
+            Ok(map)
+        })
     }
 
     /// Persist one accounted upload and drop any rows for the same email that
@@ -182,24 +444,430 @@ impl UsageDb {
     /// hiccup must not fail an otherwise-successful upload, and the in-memory
     /// cache still reflects the record for the lifetime of the process.
     fn record(&self, email: &str, bytes: u64, now: i64) {
-        let conn = self.conn.lock().unwrap();
-        if let Err(e) = conn.execute(
-            "INSERT INTO usage (email, timestamp, bytes) VALUES (?1, ?2, ?3)",
-            rusqlite::params![email, now, bytes as i64],
-        ) {
-            log::error!("Failed to persist usage record for {}: {}", email, e);
+        let email = email.to_owned();
+        self.db.submit(move |conn| {
+            if let Err(e) = conn.execute(
+                "INSERT INTO usage (email, timestamp, bytes) VALUES (?1, ?2, ?3)",
+                rusqlite::params![email, now, bytes as i64],
+            ) {
+                log::error!("Failed to persist usage record for {}: {}", email, e);
+                return;
+            }
+            let cutoff = now - ROLLING_WINDOW_SECS;
+            if let Err(e) = conn.execute(
+                "DELETE FROM usage WHERE email = ?1 AND timestamp < ?2",
+                rusqlite::params![email, cutoff],
+            ) {
+                log::error!("Failed to prune usage records for {}: {}", email, e);
+            }
+        });
+    }
+}
+
+/// Retention record for a finalized upload. Written once `upload_finalize`
+/// succeeds and consulted by the retention reaper, which deletes the payload
+/// from storage once `expires` has passed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FinalizedUpload {
+    /// Unix timestamp after which the payload is deleted. Copied from
+    /// `FileState.expires`, i.e. the date quoted in the notification email.
+    pub expires: i64,
+    /// The session's recovery token, which after finalize authorizes the
+    /// sender to revoke the upload. `None` for uploads recorded before
+    /// revocation existed, which cannot be revoked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_token: Option<String>,
+    /// Set once the sender revoked the upload: the payload is deleted and
+    /// downloads answer 410 Gone until the record expires.
+    #[serde(default)]
+    pub revoked: bool,
+    /// Who was told about the upload, so later notices reach the same
+    /// people. `None` for uploads recorded before it was kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mailing: Option<Mailing>,
+    /// Completed downloads after which the payload is deleted and downloads
+    /// answer 410 Gone. `None` means unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u32>,
+    /// Completed downloads, counted against `max_downloads` if there is one.
+    #[serde(default)]
+    pub downloads: u32,
+    /// Proven recipients (lowercased addresses) whose download was counted;
+    /// each one counts only once, however often they download.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub downloaded_by: Vec<String>,
+    /// Unix timestamp of finalize; the sender may extend `expires` up to the
+    /// maximum lifetime counted from here. `None` for older records.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finalized_at: Option<i64>,
+    /// The API-key tenant that uploaded it, which may also extend it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_tenant: Option<String>,
+    /// Payload size in bytes, for the download page. `None` for older
+    /// records.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The hidden policy of each recipient in the sealed header, keyed by
+    /// recipient identifier, so the download page can start the right
+    /// disclosure before fetching the payload. `None` for older records.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient_policies: Option<BTreeMap<String, HiddenPolicy>>,
+    /// Recipients (lowercased addresses) whose download link the sender
+    /// revoked; their downloads answer 410 Gone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revoked_links: Vec<String>,
+    /// The sender asked for a confirmation at upload, and so gets a
+    /// [`Receipt`] when a recipient first downloads the file. Only a
+    /// recipient proven by a signed link or a Yivi disclosure gets one.
+    #[serde(default)]
+    pub receipts: bool,
+}
+
+impl FinalizedUpload {
+    pub fn new(expires: i64) -> Self {
+        FinalizedUpload {
+            expires,
+            recovery_token: None,
+            revoked: false,
+            mailing: None,
+            max_downloads: None,
+            downloads: 0,
+            downloaded_by: Vec::new(),
+            finalized_at: None,
+            api_key_tenant: None,
+            size: None,
+            recipient_policies: None,
+            revoked_links: Vec::new(),
+            receipts: false,
+        }
+    }
+
+    /// When the lifetime of the upload started; older records without a
+    /// finalize time are assumed to have had the default lifetime.
+    pub fn started(&self) -> i64 {
+        self.finalized_at
+            .unwrap_or(self.expires - UPLOAD_LIFETIME_SECS)
+    }
+
+    /// Downloads left before the limit is reached; `None` when unlimited.
+    pub fn downloads_remaining(&self) -> Option<u32> {
+        self.max_downloads
+            .map(|max| max.saturating_sub(self.downloads))
+    }
+
+    /// False once the payload is withdrawn: revoked by the sender, or
+    /// downloaded as often as allowed.
+    pub fn is_available(&self) -> bool {
+        !self.revoked && self.downloads_remaining() != Some(0)
+    }
+
+    /// The sender's email address, lowercased, as proven by the signature
+    /// at finalize. `None` for older records.
+    pub fn sender(&self) -> Option<String> {
+        self.mailing
+            .as_ref()
+            .and_then(|m| m.sender.as_deref())
+            .map(str::to_lowercase)
+    }
+
+    /// Whether the upload was sent to `address`, compared case-insensitively.
+    /// False for records from before the recipients were kept.
+    pub fn is_recipient(&self, address: &str) -> bool {
+        let address = address.to_lowercase();
+        self.mailing.as_ref().is_some_and(|m| {
+            m.recipients
+                .iter()
+                .any(|mb| mb.email.to_string().to_lowercase() == address)
+        })
+    }
+
+    /// Whether the sender revoked `recipient`'s link, compared
+    /// case-insensitively.
+    pub fn is_link_revoked(&self, recipient: &str) -> bool {
+        self.revoked_links.contains(&recipient.to_lowercase())
+    }
+
+    /// Count one completed download by `recipient`, if the download proved
+    /// who it was for. Returns whether it was counted: a recipient of the
+    /// upload counts only once, anonymous downloads every time.
+    fn count_download(&mut self, recipient: Option<&str>) -> bool {
+        let recipient = recipient
+            .filter(|r| self.is_recipient(r))
+            .map(str::to_lowercase);
+        if let Some(recipient) = recipient {
+            if self.downloaded_by.contains(&recipient) {
+                return false;
+            }
+            self.downloaded_by.push(recipient);
+        }
+        self.downloads = self.downloads.saturating_add(1);
+        true
+    }
+}
+
+/// Counts a completed download of a finalized upload, deletes its payload
+/// once a download limit is reached and passes on the receipt of a
+/// recipient's first download. Handed out by
+/// [`Store::download_counter`]; holds the store weakly, like the reaper, so
+/// a download still streaming at shutdown does not keep it alive.
+pub struct DownloadCounter {
+    shared: Weak<SharedState>,
+    id: String,
+    recipient: Option<String>,
+}
+
+impl DownloadCounter {
+    /// Count the download. Called from the body stream, so the payload is
+    /// deleted on a spawned task.
+    pub fn count(self) {
+        let Some(shared) = self.shared.upgrade() else {
+            return;
+        };
+        let exhausted = {
+            let mut state = shared.state.lock().unwrap();
+            let Some(upload) = state.uploads.get_mut(&self.id) else {
+                return;
+            };
+            let known = upload.downloaded_by.len();
+            if !upload.count_download(self.recipient.as_deref()) {
+                return;
+            }
+            if let Some(db) = &shared.upload_db {
+                db.upsert(&self.id, upload);
+            }
+            let exhausted = upload.downloads_remaining() == Some(0);
+            let receipt = upload
+                .downloaded_by
+                .get(known)
+                .filter(|_| upload.receipts)
+                .zip(upload.mailing.as_ref())
+                .map(|(recipient, mailing)| Receipt {
+                    id: self.id.clone(),
+                    recipient: recipient.clone(),
+                    mailing: mailing.clone(),
+                    at: chrono::offset::Utc::now().timestamp(),
+                });
+            if let Some((receipt, receipts)) = receipt.zip(state.receipts.as_ref()) {
+                // The mailer only goes away at shutdown.
+                let _ = receipts.send(receipt);
+            }
+            exhausted
+        };
+        if !exhausted {
             return;
         }
-        let cutoff = now - ROLLING_WINDOW_SECS;
-        if let Err(e) = conn.execute(
-            "DELETE FROM usage WHERE email = ?1 AND timestamp < ?2",
-            rusqlite::params![email, cutoff],
-        ) {
-            log::error!("Failed to prune usage records for {}: {}", email, e);
+        log::info!("upload {} reached its download limit", self.id);
+        if let Some(storage) = shared.storage.clone() {
+            rocket::tokio::spawn(async move {
+                // A failed delete is retried by the startup sweep and the
+                // reaper; downloads are refused either way.
+                remove_payload(storage.as_ref(), &self.id).await;
+            });
         }
     }
 }
 
+/// A recipient's first completed download of an upload whose sender wants
+/// receipts, on its way to the receipt mailer.
+#[derive(Clone, Debug)]
+pub struct Receipt {
+    /// The upload.
+    pub id: String,
+    /// Who downloaded it, lowercased.
+    pub recipient: String,
+    pub mailing: Mailing,
+    /// Unix timestamp of the download.
+    pub at: i64,
+}
+
+/// The addressing of an upload's notification emails, kept past the end of
+/// its session.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mailing {
+    #[serde(with = "mailboxes_as_string")]
+    pub recipients: lettre::message::Mailboxes,
+    pub mail_lang: email::Language,
+    pub sender: Option<String>,
+    pub sender_attributes: Vec<(String, String)>,
+}
+
+impl Mailing {
+    pub fn of(state: &FileState) -> Self {
+        Mailing {
+            recipients: state.recipients.clone(),
+            mail_lang: state.mail_lang.clone(),
+            sender: state.sender.clone(),
+            sender_attributes: state.sender_attributes.clone(),
+        }
+    }
+}
+
+/// SQLite-backed persistence for per-upload state: the retention records of
+/// finalized uploads and the in-flight upload sessions. Lives in the same
+/// database file as [`UsageDb`] (on its own connection) and follows the same
+/// cache/source-of-truth split: every change is written through here and
+/// both tables are reloaded on startup, so a restart neither turns expired
+/// payloads into files nobody will ever delete nor throws away uploads in
+/// progress.
+///
+/// Rows are stored as JSON so fields added later do not need a schema
+/// migration.
+struct UploadDb {
+    db: DbThread,
+    /// True when this open created the table, i.e. the database predates
+    /// retention records (or is brand new). See
+    /// `SharedState::sweep_partial_uploads`.
+    created: bool,
+}
+
+impl UploadDb {
+    fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        let existed: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master
+                            WHERE type = 'table' AND name = 'finalized_uploads')",
+            [],
+            |row| row.get(0),
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS finalized_uploads (
+                 uuid   TEXT PRIMARY KEY NOT NULL,
+                 record TEXT NOT NULL
+             )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS upload_sessions (
+                 uuid  TEXT PRIMARY KEY NOT NULL,
+                 state TEXT NOT NULL
+             )",
+            [],
+        )?;
+        Ok(UploadDb {
+            db: DbThread::spawn(conn),
+            created: !existed,
+        })
+    }
+
+    /// Load every retention record. Rows that no longer deserialize are
+    /// logged and skipped rather than failing startup.
+    fn load_all(&self) -> rusqlite::Result<HashMap<String, FinalizedUpload>> {
+        self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT uuid, record FROM finalized_uploads")?;
+            let rows = stmt.query_map([], |row| {
+                let uuid: String = row.get(0)?;
+                let record: String = row.get(1)?;
+                Ok((uuid, record))
+            })?;
+
+            let mut map = HashMap::new();
+            for row in rows {
+                let (uuid, record) = row?;
+                match serde_json::from_str::<FinalizedUpload>(&record) {
+                    Ok(upload) => {
+                        map.insert(uuid, upload);
+                    }
+                    Err(e) => {
+                        log::error!("Skipping unreadable retention record for {}: {}", uuid, e)
+                    }
+                }
+            }
+            Ok(map)
+        })
+    }
+
+    /// Insert or replace the record for `uuid`. Like [`UsageDb::record`],
+    /// errors are logged rather than propagated: the in-memory cache still
+    /// drives the reaper for the lifetime of the process.
+    fn upsert(&self, uuid: &str, upload: &FinalizedUpload) {
+        let record = match serde_json::to_string(upload) {
+            Ok(r) => r,
+            Err(e) => {
+                log::error!("Failed to serialize retention record for {}: {}", uuid, e);
+                return;
+            }
+        };
+        let uuid = uuid.to_owned();
+        self.db.submit(move |conn| {
+            if let Err(e) = conn.execute(
+                "INSERT OR REPLACE INTO finalized_uploads (uuid, record) VALUES (?1, ?2)",
+                rusqlite::params![uuid, record],
+            ) {
+                log::error!("Failed to persist retention record for {}: {}", uuid, e);
+            }
+        });
+    }
+
+    fn delete(&self, uuid: &str) {
+        let uuid = uuid.to_owned();
+        self.db.submit(move |conn| {
+            if let Err(e) = conn.execute(
+                "DELETE FROM finalized_uploads WHERE uuid = ?1",
+                rusqlite::params![uuid],
+            ) {
+                log::error!("Failed to delete retention record for {}: {}", uuid, e);
+            }
+        });
+    }
+
+    /// Load every persisted upload session. Unreadable rows are logged and
+    /// skipped; the sweep that follows then treats their payload as orphaned.
+    fn load_sessions(&self) -> rusqlite::Result<HashMap<String, FileState>> {
+        self.db.call(|conn| {
+            let mut stmt = conn.prepare("SELECT uuid, state FROM upload_sessions")?;
+            let rows = stmt.query_map([], |row| {
+                let uuid: String = row.get(0)?;
+                let state: String = row.get(1)?;
+                Ok((uuid, state))
+            })?;
+
+            let mut map = HashMap::new();
+            for row in rows {
+                let (uuid, state) = row?;
+                match serde_json::from_str::<FileState>(&state) {
+                    Ok(session) => {
+                        map.insert(uuid, session);
+                    }
+                    Err(e) => log::error!("Skipping unreadable upload session {}: {}", uuid, e),
+                }
+            }
+            Ok(map)
+        })
+    }
+
+    fn save_session(&self, uuid: &str, session: &FileState) {
+        let state = match serde_json::to_string(session) {
+            Ok(s) => s,
+            Err(e) => {
+                log::error!("Failed to serialize upload session {}: {}", uuid, e);
+                return;
+            }
+        };
+        let uuid = uuid.to_owned();
+        self.db.submit(move |conn| {
+            if let Err(e) = conn.execute(
+                "INSERT OR REPLACE INTO upload_sessions (uuid, state) VALUES (?1, ?2)",
+                rusqlite::params![uuid, state],
+            ) {
+                log::error!("Failed to persist upload session {}: {}", uuid, e);
+            }
+        });
+    }
+
+    fn delete_session(&self, uuid: &str) {
+        let uuid = uuid.to_owned();
+        self.db.submit(move |conn| {
+            if let Err(e) = conn.execute(
+                "DELETE FROM upload_sessions WHERE uuid = ?1",
+                rusqlite::params![uuid],
+            ) {
+                log::error!("Failed to delete upload session {}: {}", uuid, e);
+            }
+        });
+    }
+}
+
 struct StoreState {
     files: HashMap<String, Arc<rocket::tokio::sync::Mutex<FileState>>>,
     expirations: BTreeMap<(Instant, u64), String>,
@@ -207,8 +875,52 @@ struct StoreState {
     /// `expirations`. Lets `touch` extend the deadline without scanning.
     expiration_keys: HashMap<String, (Instant, u64)>,
     usage: HashMap<String, VecDeque<UploadRecord>>,
+    /// Retention records for finalized uploads, keyed by file id.
+    uploads: HashMap<String, FinalizedUpload>,
+    /// Index of `uploads` by [`FinalizedUpload::sender`], for the sender
+    /// dashboard. Derived from the records, so it is rebuilt on startup
+    /// rather than persisted.
+    uploads_by_sender: HashMap<String, BTreeSet<String>>,
+    /// Quota reservations of in-flight sessions, keyed by file id.
+    reservations: HashMap<String, Reservation>,
     next_id: u64,
     shutdown: bool,
+    /// The longest lifetime any upload can have. A payload without a
+    /// retention record is only known to have expired once it is this old.
+    max_lifetime: i64,
+    /// Where counted downloads send their [`Receipt`]s; `None` drops them.
+    receipts: Option<UnboundedSender<Receipt>>,
+}
+
+impl StoreState {
+    fn insert_upload(&mut self, id: &str, upload: FinalizedUpload) {
+        if let Some(sender) = upload.sender() {
+            self.uploads_by_sender
+                .entry(sender)
+                .or_default()
+                .insert(id.to_owned());
+        }
+        self.uploads.insert(id.to_owned(), upload);
+    }
+
+    /// Whether `id` is a finalized upload past its expiry at `now`.
+    fn is_expired(&self, id: &str, now: i64) -> bool {
+        self.uploads.get(id).is_some_and(|u| u.expires <= now)
+    }
+
+    fn remove_upload(&mut self, id: &str) {
+        let Some(upload) = self.uploads.remove(id) else {
+            return;
+        };
+        if let Some(sender) = upload.sender() {
+            if let Some(ids) = self.uploads_by_sender.get_mut(&sender) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.uploads_by_sender.remove(&sender);
+                }
+            }
+        }
+    }
 }
 
 struct SharedState {
@@ -220,6 +932,12 @@ struct SharedState {
     /// memory only (the pre-persistence behaviour, used by unit tests and
     /// when `usage_db` is unset in config).
     usage_db: Option<UsageDb>,
+    /// SQLite source of truth for finalized-upload retention records; opened
+    /// on the same file as `usage_db` and `None` whenever that is.
+    upload_db: Option<UploadDb>,
+    /// Where the upload payloads live. `None` (unit tests) disables every
+    /// payload side effect of the store.
+    storage: Option<Arc<dyn Storage>>,
 }
 
 pub struct Store {
@@ -233,6 +951,7 @@ impl Store {
             Duration::from_secs(DEFAULT_UPLOAD_SESSION_IDLE_TIMEOUT_SECS),
             metrics,
             None,
+            None,
         )
     }
 
@@ -243,12 +962,35 @@ impl Store {
     /// survives process restarts. A configured-but-unopenable database is a
     /// deployment error and panics here, the same way a malformed config
     /// does — better a loud startup failure than silently losing quota
-    /// persistence.
+    /// persistence. The same database also holds the retention records of
+    /// finalized uploads, whose payloads live in `storage`.
     pub fn with_idle_ttl(
         idle_ttl: Duration,
         metrics: Arc<Metrics>,
         usage_db: Option<&str>,
+        storage: Option<Arc<dyn Storage>>,
     ) -> Self {
+        let (upload_db, uploads, sessions) = match usage_db {
+            Some(path) => {
+                let db = UploadDb::open(path)
+                    .unwrap_or_else(|e| panic!("Failed to open upload records at {}: {}", path, e));
+                let uploads = db.load_all().unwrap_or_else(|e| {
+                    panic!("Failed to load retention records from {}: {}", path, e)
+                });
+                let sessions = db.load_sessions().unwrap_or_else(|e| {
+                    panic!("Failed to load upload sessions from {}: {}", path, e)
+                });
+                log::info!(
+                    "Loaded {} retention record(s) and {} upload session(s) from {}",
+                    uploads.len(),
+                    sessions.len(),
+                    path
+                );
+                (Some(db), uploads, sessions)
+            }
+            None => (None, HashMap::new(), HashMap::new()),
+        };
+
         let (usage_db, usage) = match usage_db {
             Some(path) => {
                 let db = UsageDb::open(path)
@@ -275,22 +1017,62 @@ impl Store {
                     expirations: BTreeMap::new(),
                     expiration_keys: HashMap::new(),
                     usage,
+                    uploads_by_sender: HashMap::new(),
+                    uploads: HashMap::new(),
+                    reservations: HashMap::new(),
                     next_id: 0,
                     shutdown: false,
+                    max_lifetime: UPLOAD_LIFETIME_SECS,
+                    receipts: None,
                 }),
                 notify: Notify::new(),
                 idle_ttl,
                 metrics,
                 usage_db,
+                upload_db,
+                storage,
             }),
         };
 
+        {
+            let mut state = result.shared.state.lock().unwrap();
+            for (id, upload) in uploads {
+                state.insert_upload(&id, upload);
+            }
+        }
+        // Reloaded sessions get a fresh idle window: the client had no way
+        // to keep them alive while the process was down.
+        for (id, session) in sessions {
+            result.insert_session(id, session);
+        }
         rocket::tokio::spawn(purge_task(result.shared.clone()));
         result
     }
 
+    /// Raise the assumed lifetime of payloads without a retention record to
+    /// `secs` when senders may choose lifetimes longer than the default.
+    pub fn set_max_upload_lifetime(&self, secs: i64) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.max_lifetime = secs.max(UPLOAD_LIFETIME_SECS);
+    }
+
+    /// Send a [`Receipt`] here for every first download by a recipient of
+    /// an upload that wants them.
+    pub fn set_receipt_sender(&self, receipts: UnboundedSender<Receipt>) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receipts = Some(receipts);
+    }
+
     pub fn create(&self, id: String, filestate: FileState) {
+        self.save(&id, &filestate);
+        self.insert_session(id, filestate);
+    }
+
+    fn insert_session(&self, id: String, filestate: FileState) {
         let mut state = self.shared.state.lock().unwrap(); // this will only panic if we already panicked elsewhere while holding the mutex, which is fine.
+        if let Some(reservation) = &filestate.reservation {
+            state.reservations.insert(id.clone(), reservation.clone());
+        }
         state.files.insert(
             id.clone(),
             Arc::new(rocket::tokio::sync::Mutex::new(filestate)),
@@ -307,6 +1089,11 @@ impl Store {
         self.shared.notify.notify_one()
     }
 
+    /// How long a session may sit idle before it is evicted.
+    pub fn idle_ttl(&self) -> Duration {
+        self.shared.idle_ttl
+    }
+
     pub fn get(&self, id: &str) -> Option<Arc<rocket::tokio::sync::Mutex<FileState>>> {
         let state = self.shared.state.lock().unwrap(); // this will only panic if we already panicked elsewhere while holding the mutex, which is fine.
         state.files.get(id).cloned()
@@ -331,9 +1118,22 @@ impl Store {
         self.shared.notify.notify_one();
     }
 
+    /// Write the current state of session `id` through to the database.
+    /// Callers invoke this after every mutation of a `FileState` they hold
+    /// the lock for; a no-op when no database is configured.
+    pub fn save(&self, id: &str, filestate: &FileState) {
+        if let Some(db) = &self.shared.upload_db {
+            db.save_session(id, filestate);
+        }
+    }
+
     pub fn remove(&self, id: &str) {
+        if let Some(db) = &self.shared.upload_db {
+            db.delete_session(id);
+        }
         let mut state = self.shared.state.lock().unwrap();
         state.files.remove(id);
+        state.reservations.remove(id);
         if let Some((when, removal_id)) = state.expiration_keys.remove(id) {
             state.expirations.remove(&(when, removal_id));
         }
@@ -368,21 +1168,157 @@ impl Store {
 
     pub fn get_usage(&self, email: &str, now: i64) -> UsageSnapshot {
         let mut state = self.shared.state.lock().unwrap();
-        match state.usage.get_mut(email) {
-            Some(entry) => {
-                prune_records(entry, now);
-                let used_bytes = entry.iter().map(|r| r.bytes).sum();
-                let oldest_expires_at = entry.front().map(|r| r.timestamp + ROLLING_WINDOW_SECS);
-                UsageSnapshot {
-                    used_bytes,
-                    oldest_expires_at,
-                }
+        usage_of(&mut state, email, now)
+    }
+
+    /// Hold `reservation.bytes` of `reservation.key`'s rolling quota for
+    /// upload `id`, replacing any reservation `id` already had. Fails when
+    /// the booked usage plus every other upload's reservation for the same
+    /// key would exceed `limit`; the returned snapshot then counts those
+    /// reservations as used. Check and reservation happen under one lock,
+    /// so parallel uploads of one sender cannot all squeeze in under the
+    /// limit.
+    pub fn reserve(
+        &self,
+        id: &str,
+        reservation: Reservation,
+        limit: u64,
+        now: i64,
+    ) -> Result<(), UsageSnapshot> {
+        let mut state = self.shared.state.lock().unwrap();
+        let mut usage = usage_of(&mut state, &reservation.key, now);
+        usage.used_bytes += state
+            .reservations
+            .iter()
+            .filter(|(other, r)| other.as_str() != id && r.key == reservation.key)
+            .map(|(_, r)| r.bytes)
+            .sum::<u64>();
+        if usage.used_bytes.saturating_add(reservation.bytes) > limit {
+            return Err(usage);
+        }
+        state.reservations.insert(id.to_owned(), reservation);
+        Ok(())
+    }
+
+    /// Drop the reservation of `id` without booking it.
+    pub fn release(&self, id: &str) {
+        self.shared.state.lock().unwrap().reservations.remove(id);
+    }
+
+    /// Turn the reservation of `id` into booked usage, see
+    /// [`Store::record_upload`]. A no-op when `id` holds none.
+    pub fn commit_reservation(&self, id: &str, now: i64) {
+        let reservation = self.shared.state.lock().unwrap().reservations.remove(id);
+        if let Some(reservation) = reservation {
+            self.record_upload(reservation.key, reservation.bytes, now);
+        }
+    }
+
+    /// Register a finalized upload with the retention reaper. Written through
+    /// to the database first, mirroring [`Store::record_upload`].
+    pub fn record_finalized(&self, id: &str, upload: FinalizedUpload) {
+        if let Some(db) = &self.shared.upload_db {
+            db.upsert(id, &upload);
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        state.insert_upload(id, upload);
+    }
+
+    /// The finalized uploads of `sender`, matched case-insensitively, with
+    /// their ids.
+    pub fn uploads_of(&self, sender: &str) -> Vec<(String, FinalizedUpload)> {
+        let state = self.shared.state.lock().unwrap();
+        let Some(ids) = state.uploads_by_sender.get(&sender.to_lowercase()) else {
+            return Vec::new();
+        };
+        ids.iter()
+            .filter_map(|id| Some((id.clone(), state.uploads.get(id)?.clone())))
+            .collect()
+    }
+
+    /// Retention record for `id`, if it was finalized and not yet reaped.
+    pub fn finalized(&self, id: &str) -> Option<FinalizedUpload> {
+        let state = self.shared.state.lock().unwrap();
+        state.uploads.get(id).cloned()
+    }
+
+    /// A [`DownloadCounter`] for `id`, if it is a finalized upload. Every
+    /// upload counts its downloads, so the sender can see them; only one
+    /// with a download limit is ever withdrawn by them. `recipient` must
+    /// have been proven by a signed link or a Yivi disclosure.
+    pub fn download_counter(&self, id: &str, recipient: Option<&str>) -> Option<DownloadCounter> {
+        let state = self.shared.state.lock().unwrap();
+        state.uploads.contains_key(id).then(|| DownloadCounter {
+            shared: Arc::downgrade(&self.shared),
+            id: id.to_owned(),
+            recipient: recipient.map(str::to_owned),
+        })
+    }
+
+    /// Move the expiry of the finalized upload `id` to `expires`, returning
+    /// the updated record. `None` when there is no such upload, or it has
+    /// already expired: the reaper may be deleting it. The clock is read
+    /// under the lock, so once the reaper has seen an upload expired, no
+    /// extension gets in.
+    pub fn extend(&self, id: &str, expires: i64) -> Option<FinalizedUpload> {
+        let mut state = self.shared.state.lock().unwrap();
+        let now = chrono::offset::Utc::now().timestamp();
+        let upload = state.uploads.get_mut(id).filter(|u| u.expires > now)?;
+        upload.expires = expires;
+        if let Some(db) = &self.shared.upload_db {
+            db.upsert(id, upload);
+        }
+        Some(upload.clone())
+    }
+
+    /// Mark the finalized upload `id` as revoked, returning its record as it
+    /// was before. `None` when there is no such upload.
+    pub fn revoke(&self, id: &str) -> Option<FinalizedUpload> {
+        let mut state = self.shared.state.lock().unwrap();
+        let upload = state.uploads.get_mut(id)?;
+        let before = upload.clone();
+        upload.revoked = true;
+        if let Some(db) = &self.shared.upload_db {
+            db.upsert(id, upload);
+        }
+        Some(before)
+    }
+
+    /// Revoke `recipient`'s link to the finalized upload `id`, leaving the
+    /// other recipients' links working. `None` when there is no such upload.
+    pub fn revoke_link(&self, id: &str, recipient: &str) -> Option<FinalizedUpload> {
+        let mut state = self.shared.state.lock().unwrap();
+        let upload = state.uploads.get_mut(id)?;
+        if !upload.is_link_revoked(recipient) {
+            upload.revoked_links.push(recipient.to_lowercase());
+            if let Some(db) = &self.shared.upload_db {
+                db.upsert(id, upload);
             }
-            None => UsageSnapshot {
-                used_bytes: 0,
-                oldest_expires_at: None,
-            },
         }
+        Some(upload.clone())
+    }
+
+    /// Delete every finalized payload whose expiry is at or before `now`,
+    /// plus any untracked file old enough that it must have expired. Returns
+    /// the number of payloads removed. `now` is a parameter (rather than read
+    /// from the clock) so tests can drive the reaper with a fake clock.
+    #[cfg(test)]
+    pub async fn reap_expired_uploads(&self, now: i64) -> usize {
+        self.shared.reap_expired_uploads(now).await
+    }
+
+    /// Delete the partial payloads a crash left behind; see
+    /// [`SharedState::sweep_partial_uploads`]. Must finish before the first
+    /// upload is accepted, so `build_rocket` runs it from an ignite fairing.
+    pub async fn sweep_partial_uploads(&self) {
+        self.shared.sweep_partial_uploads().await
+    }
+
+    /// Spawn the background task that runs [`Store::reap_expired_uploads`]
+    /// every `interval`. The task only holds a weak reference, so it winds
+    /// down on its own once the store is dropped.
+    pub fn spawn_retention_reaper(&self, interval: Duration) {
+        rocket::tokio::spawn(retention_task(Arc::downgrade(&self.shared), interval));
     }
 }
 
@@ -392,6 +1328,24 @@ pub struct UsageSnapshot {
     pub oldest_expires_at: Option<i64>,
 }
 
+fn usage_of(state: &mut StoreState, email: &str, now: i64) -> UsageSnapshot {
+    match state.usage.get_mut(email) {
+        Some(entry) => {
+            prune_records(entry, now);
+            let used_bytes = entry.iter().map(|r| r.bytes).sum();
+            let oldest_expires_at = entry.front().map(|r| r.timestamp + ROLLING_WINDOW_SECS);
+            UsageSnapshot {
+                used_bytes,
+                oldest_expires_at,
+            }
+        }
+        None => UsageSnapshot {
+            used_bytes: 0,
+            oldest_expires_at: None,
+        },
+    }
+}
+
 fn prune_records(records: &mut VecDeque<UploadRecord>, now: i64) {
     let cutoff = now - ROLLING_WINDOW_SECS;
     while let Some(front) = records.front() {
@@ -405,6 +1359,13 @@ fn prune_records(records: &mut VecDeque<UploadRecord>, now: i64) {
 
 impl Drop for Store {
     fn drop(&mut self) {
+        // Let queued writes land before a successor opens the database.
+        if let Some(db) = &self.shared.usage_db {
+            db.db.call(|_| ());
+        }
+        if let Some(db) = &self.shared.upload_db {
+            db.db.call(|_| ());
+        }
         if Arc::strong_count(&self.shared) == 2 {
             self.shared.state.lock().unwrap().shutdown = true; // this will only panic if we already panicked elsewhere while holding the mutex, which is fine.
             self.shared.notify.notify_one()
@@ -413,7 +1374,11 @@ impl Drop for Store {
 }
 
 impl SharedState {
-    fn purge_expired(&self) -> Option<Instant> {
+    /// Evict every session whose idle deadline has passed and return the
+    /// next deadline, if any. Ids of evicted sessions that never finalized
+    /// are pushed onto `orphans` so the (async) caller can delete their
+    /// partial payloads once the lock is released.
+    fn purge_expired(&self, orphans: &mut Vec<String>) -> Option<Instant> {
         let mut state = self.state.lock().unwrap(); // this will only panic if we already panicked elsewhere while holding the mutex, which is fine.
 
         if state.shutdown {
@@ -429,18 +1394,20 @@ impl SharedState {
             }
 
             let id = id.clone();
+            state.reservations.remove(&id);
             if let Some(entry) = state.files.remove(&id) {
-                // An entry that still had no `sender` set was never finalized.
-                // (`sender` is populated by `upload_finalize` once the file has
-                // been unsealed.)
-                let was_unfinalized = entry
-                    .try_lock()
-                    .map(|g| g.sender.is_none())
-                    .unwrap_or(false);
+                // A session evicted before it was finalized leaves a
+                // half-written payload with no future reader, so the caller
+                // deletes it.
+                let was_unfinalized = entry.try_lock().map(|g| !g.finalized).unwrap_or(false);
                 if was_unfinalized {
                     self.metrics.record_expired();
+                    orphans.push(id.clone());
                 }
             }
+            if let Some(db) = &self.upload_db {
+                db.delete_session(&id);
+            }
             state.expiration_keys.remove(&id);
             state.expirations.remove(&(when, removal_id));
         }
@@ -448,6 +1415,207 @@ impl SharedState {
         None
     }
 
+    /// Delete the partial payloads of sessions evicted before they were
+    /// finalized. Without this they would be served by `download` and
+    /// counted by `sample_storage` until the retention fallback caught them.
+    async fn remove_orphans(&self, orphans: Vec<String>) {
+        let Some(storage) = self.storage.as_deref() else {
+            return;
+        };
+        for id in orphans {
+            if remove_payload(storage, &id).await {
+                log::info!("removed partial payload of evicted upload session {}", id);
+            }
+        }
+    }
+
+    /// Startup sweep for partial payloads left behind by a crash or an
+    /// unclean shutdown: every UUID-named file that is neither a finalized
+    /// upload nor a (reloaded) upload session is deleted. A payload carrying
+    /// the finalized marker is never deleted here, tracked or not: its
+    /// record may only be missing because the database was lost or replaced.
+    ///
+    /// Only runs when retention records are persisted — without a database
+    /// every finalized upload looks untracked after a restart. The first
+    /// start against a database that predates the retention table cannot
+    /// tell partial from finalized files either, so it adopts them as
+    /// finalized uploads expiring the longest lifetime after their last
+    /// write (the latest their real expiry could be) and leaves them to the
+    /// reaper. Only files stored under their final name are adopted: one
+    /// still being written was never verified, and is deleted as partial.
+    ///
+    /// Finalized uploads recorded before downloads required the finalized
+    /// marker get one here, so their links keep working. Without a database
+    /// there is no way to tell them from crash leftovers, and they stay
+    /// unavailable until the reaper removes them.
+    async fn sweep_partial_uploads(&self) {
+        let (Some(storage), Some(db)) = (self.storage.as_deref(), self.upload_db.as_ref()) else {
+            return;
+        };
+        let objects = match storage.list().await {
+            Ok(objects) => objects,
+            Err(e) => {
+                log::warn!("startup sweep: could not list stored payloads: {}", e);
+                return;
+            }
+        };
+
+        let mut partial = Vec::new();
+        let mut unmarked = Vec::new();
+        let mut withdrawn = Vec::new();
+        let mut adopted = 0usize;
+        {
+            let mut state = self.state.lock().unwrap();
+            for object in objects {
+                if uuid::Uuid::parse_str(&object.id).is_err()
+                    || state.files.contains_key(&object.id)
+                {
+                    continue;
+                }
+                if let Some(upload) = state.uploads.get(&object.id) {
+                    if !upload.is_available() {
+                        // Its deletion when it was withdrawn failed part-way.
+                        withdrawn.push(object.id);
+                    } else if !object.finalized && !object.in_progress {
+                        unmarked.push(object.id);
+                    }
+                    continue;
+                }
+                if object.finalized && !db.created {
+                    log::warn!(
+                        "startup sweep: finalized payload {} has no record, leaving it in place",
+                        object.id
+                    );
+                    continue;
+                }
+                if db.created && !object.in_progress {
+                    let upload = FinalizedUpload::new(object.modified + state.max_lifetime);
+                    db.upsert(&object.id, &upload);
+                    state.insert_upload(&object.id, upload);
+                    if !object.finalized {
+                        unmarked.push(object.id);
+                    }
+                    adopted += 1;
+                } else {
+                    partial.push(object.id);
+                }
+            }
+        }
+
+        for id in unmarked {
+            // Payloads of older releases were written under their final name.
+            if let Err(e) = storage.complete(&id).await {
+                log::warn!("startup sweep: could not complete {}: {}", id, e);
+                continue;
+            }
+            if let Err(e) = storage.mark_finalized(&id).await {
+                log::warn!("startup sweep: could not mark {} as finalized: {}", id, e);
+            }
+        }
+
+        for id in withdrawn {
+            if remove_payload(storage, &id).await {
+                log::info!("startup sweep: removed payload of withdrawn upload {}", id);
+            }
+        }
+
+        let mut removed = 0usize;
+        for id in partial {
+            if remove_payload(storage, &id).await {
+                self.metrics.record_expired();
+                removed += 1;
+            }
+        }
+
+        if removed > 0 || adopted > 0 {
+            log::info!(
+                "startup sweep: removed {} partial upload(s), adopted {} pre-existing upload(s)",
+                removed,
+                adopted
+            );
+        }
+    }
+
+    async fn reap_expired_uploads(&self, now: i64) -> usize {
+        let Some(storage) = self.storage.as_deref() else {
+            return 0;
+        };
+
+        let expired: Vec<String> = {
+            let state = self.state.lock().unwrap();
+            state
+                .uploads
+                .iter()
+                .filter(|(_, u)| u.expires <= now)
+                .map(|(id, _)| id.clone())
+                .collect()
+        };
+
+        // An upload extended since it was listed is no longer expired; once
+        // it is seen expired under the lock, `Store::extend` refuses it.
+        let mut reaped = 0;
+        for id in expired {
+            if !self.state.lock().unwrap().is_expired(&id, now) {
+                continue;
+            }
+            if !remove_payload(storage, &id).await {
+                // Keep the record so the next pass retries the delete.
+                continue;
+            }
+            {
+                let mut state = self.state.lock().unwrap();
+                if !state.is_expired(&id, now) {
+                    continue;
+                }
+                if let Some(db) = &self.upload_db {
+                    db.delete(&id);
+                }
+                state.remove_upload(&id);
+            }
+            self.metrics.record_retention_deleted();
+            log::info!("retention: deleted expired upload {}", id);
+            reaped += 1;
+        }
+
+        reaped + self.reap_untracked_files(storage, now).await
+    }
+
+    /// Fallback for payloads without a retention record: uploads finalized
+    /// before the reaper existed, or before a restart when no database is
+    /// configured. `FileState.expires` is at most `init + max_lifetime` and
+    /// every write happens after init, so a file whose last modification is
+    /// more than that ago has certainly expired.
+    async fn reap_untracked_files(&self, storage: &dyn Storage, now: i64) -> usize {
+        let max_lifetime = self.state.lock().unwrap().max_lifetime;
+        let candidates: Vec<String> = match storage.list().await {
+            Ok(objects) => objects
+                .into_iter()
+                .filter(|o| uuid::Uuid::parse_str(&o.id).is_ok())
+                .filter(|o| o.modified + max_lifetime <= now)
+                .map(|o| o.id)
+                .collect(),
+            Err(e) => {
+                log::warn!("retention: could not list stored payloads: {}", e);
+                return 0;
+            }
+        };
+
+        let mut reaped = 0;
+        for id in candidates {
+            let tracked = {
+                let state = self.state.lock().unwrap();
+                state.uploads.contains_key(&id) || state.files.contains_key(&id)
+            };
+            if tracked || !remove_payload(storage, &id).await {
+                continue;
+            }
+            self.metrics.record_retention_deleted();
+            log::info!("retention: deleted untracked expired file {}", id);
+            reaped += 1;
+        }
+        reaped
+    }
+
     fn is_shutdown(&self) -> bool {
         self.state.lock().unwrap().shutdown // this will only panic if we already panicked elsewhere while holding the mutex, which is fine.
     }
@@ -455,7 +1623,10 @@ impl SharedState {
 
 async fn purge_task(shared: Arc<SharedState>) {
     while !shared.is_shutdown() {
-        if let Some(when) = shared.purge_expired() {
+        let mut orphans = Vec::new();
+        let next = shared.purge_expired(&mut orphans);
+        shared.remove_orphans(orphans).await;
+        if let Some(when) = next {
             rocket::tokio::select! {
                 _ = rocket::tokio::time::sleep_until(when) => {}
                 _ = shared.notify.notified() => {}
@@ -466,9 +1637,38 @@ async fn purge_task(shared: Arc<SharedState>) {
     }
 }
 
+/// Remove the payload for `id`. A payload that is already gone counts as
+/// removed; any other failure is logged and reported as `false`.
+async fn remove_payload(storage: &dyn Storage, id: &str) -> bool {
+    match storage.delete(id).await {
+        Ok(()) => true,
+        Err(e) => {
+            log::error!("could not delete upload payload {}: {}", id, e);
+            false
+        }
+    }
+}
+
+async fn retention_task(shared: Weak<SharedState>, interval: Duration) {
+    loop {
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        if shared.is_shutdown() {
+            return;
+        }
+        shared
+            .reap_expired_uploads(chrono::offset::Utc::now().timestamp())
+            .await;
+        drop(shared);
+        rocket::tokio::time::sleep(interval).await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::LocalStorage;
 
     #[rocket::async_test]
     async fn usage_is_zero_for_unknown_email() {
@@ -519,6 +1719,140 @@ mod tests {
         assert_eq!(store.get_usage("b@example.com", now).used_bytes, 2_000);
     }
 
+    fn reservation(key: &str, bytes: u64) -> Reservation {
+        Reservation {
+            key: key.to_owned(),
+            bytes,
+        }
+    }
+
+    #[rocket::async_test]
+    async fn reservations_count_against_their_key() {
+        let store = Store::new(Arc::new(Metrics::new()));
+        let now: i64 = 2_000_000;
+        store.record_upload("k".into(), 400, now);
+
+        assert!(store
+            .reserve("u1", reservation("k", 300), 1_000, now)
+            .is_ok());
+        let err = store
+            .reserve("u2", reservation("k", 301), 1_000, now)
+            .expect_err("400 booked + 300 reserved + 301 > 1000");
+        assert_eq!(err.used_bytes, 700);
+        assert!(store
+            .reserve("u2", reservation("k", 300), 1_000, now)
+            .is_ok());
+        assert!(
+            store
+                .reserve("u3", reservation("other", 1_000), 1_000, now)
+                .is_ok(),
+            "other keys are unaffected"
+        );
+        assert!(
+            store
+                .reserve("u1", reservation("k", 300), 1_000, now)
+                .is_ok(),
+            "re-reserving replaces rather than adds"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn reservation_is_booked_on_commit_and_dropped_on_release() {
+        let store = Store::new(Arc::new(Metrics::new()));
+        let now: i64 = 2_000_000;
+        store
+            .reserve("u1", reservation("k", 300), 1_000, now)
+            .unwrap();
+        store
+            .reserve("u2", reservation("k", 700), 1_000, now)
+            .unwrap();
+
+        store.commit_reservation("u1", now);
+        assert_eq!(store.get_usage("k", now).used_bytes, 300);
+        store.commit_reservation("u1", now);
+        assert_eq!(store.get_usage("k", now).used_bytes, 300, "booked once");
+
+        store.release("u2");
+        assert_eq!(store.get_usage("k", now).used_bytes, 300);
+        assert!(store
+            .reserve("u3", reservation("k", 700), 1_000, now)
+            .is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn evicted_session_releases_its_reservation() {
+        let store = Store::with_idle_ttl(
+            Duration::from_millis(50),
+            Arc::new(Metrics::new()),
+            None,
+            None,
+        );
+        let now: i64 = 2_000_000;
+        let mut session = dummy_filestate();
+        session.reservation = Some(reservation("k", 1_000));
+        store.create("u1".into(), session);
+        assert!(store
+            .reserve("u2", reservation("k", 1), 1_000, now)
+            .is_err());
+
+        rocket::tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(store.get("u1").is_none());
+        assert!(store
+            .reserve("u2", reservation("k", 1_000), 1_000, now)
+            .is_ok());
+    }
+
+    #[test]
+    fn chunked_upload_merges_and_splits_runs() {
+        let mut chunks = ChunkedUpload::new(10);
+        for index in [3, 0, 1, 5, 4] {
+            chunks.insert(index, 10);
+        }
+        assert_eq!(chunks.received, [(0, 2), (3, 6)]);
+        chunks.insert(2, 10);
+        assert_eq!(chunks.received, [(0, 6)]);
+        assert_eq!(chunks.received_bytes(), 60);
+
+        chunks.remove(3);
+        assert_eq!(chunks.received, [(0, 3), (4, 6)]);
+        chunks.remove(0);
+        chunks.remove(5);
+        assert_eq!(chunks.received, [(1, 3), (4, 5)]);
+        chunks.insert(3, 10);
+        chunks.insert(0, 10);
+        assert_eq!(chunks.received, [(0, 5)]);
+    }
+
+    #[test]
+    fn chunked_upload_is_complete_only_without_gaps() {
+        let mut chunks = ChunkedUpload::new(10);
+        chunks.insert(2, 5);
+        chunks.insert(0, 10);
+        assert_eq!(chunks.received_bytes(), 15);
+        assert_eq!(chunks.first_missing(25), Some(1));
+        chunks.insert(1, 10);
+        assert_eq!(chunks.first_missing(25), None);
+        assert_eq!(chunks.first_missing(30), Some(3), "last chunk too short");
+        assert_eq!(chunks.first_missing(20), Some(2), "a chunk past the end");
+        assert_eq!(ChunkedUpload::new(10).first_missing(0), None);
+    }
+
+    #[test]
+    fn only_the_last_chunk_may_be_short() {
+        let mut chunks = ChunkedUpload::new(10);
+        assert!(chunks.check(0, 0).is_err());
+        assert!(chunks.check(0, 11).is_err());
+        chunks.insert(3, 10);
+        assert!(chunks.check(1, 4).is_err(), "chunk 3 follows it");
+        assert!(chunks.check(4, 4).is_ok());
+        chunks.insert(4, 4);
+        assert!(chunks.check(5, 10).is_err(), "past the short chunk");
+        assert!(chunks.check(2, 9).is_err(), "a second short chunk");
+        assert!(chunks.check(4, 3).is_ok(), "the short chunk again");
+        assert!(chunks.check(4, 10).is_ok());
+        assert!(chunks.check(2, 10).is_ok());
+    }
+
     fn dummy_filestate() -> FileState {
         FileState {
             uploaded: 0,
@@ -538,6 +1872,15 @@ mod tests {
             api_key_validation_failed: false,
             last_chunk: None,
             recovery_token: String::new(),
+            finalized: false,
+            reservation: None,
+            header_verified: false,
+            sealed_prefix: Vec::new(),
+            chunked: None,
+            upload_length: None,
+            declared_size: None,
+            max_downloads: None,
+            unsealable_recipients: Vec::new(),
         }
     }
 
@@ -626,6 +1969,7 @@ mod tests {
             Duration::from_secs(DEFAULT_UPLOAD_SESSION_IDLE_TIMEOUT_SECS),
             Arc::new(Metrics::new()),
             Some(path),
+            None,
         )
     }
 
@@ -739,4 +2083,453 @@ mod tests {
         store.record_upload("c@example.com".into(), 3_000, now);
         assert_eq!(store.get_usage("c@example.com", now).used_bytes, 5_000);
     }
+
+    // ----- retention reaper -----
+
+    /// Fresh per-test `data_dir` so reaper tests never touch each other's
+    /// files.
+    fn temp_data_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("cryptify-retention-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn retention_store(
+        metrics: Arc<Metrics>,
+        usage_db: Option<&str>,
+        data_dir: &std::path::Path,
+    ) -> Store {
+        Store::with_idle_ttl(
+            Duration::from_secs(DEFAULT_UPLOAD_SESSION_IDLE_TIMEOUT_SECS),
+            metrics,
+            usage_db,
+            Some(Arc::new(LocalStorage::new(data_dir))),
+        )
+    }
+
+    fn now_secs() -> i64 {
+        chrono::offset::Utc::now().timestamp()
+    }
+
+    #[rocket::async_test]
+    async fn reaper_deletes_only_expired_finalized_uploads() {
+        let dir = temp_data_dir();
+        let metrics = Arc::new(Metrics::new());
+        let store = retention_store(metrics.clone(), None, &dir);
+        let now = now_secs();
+
+        let expired = uuid::Uuid::new_v4().to_string();
+        let live = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(&expired), b"old").unwrap();
+        std::fs::write(dir.join(&live), b"new").unwrap();
+        store.record_finalized(&expired, FinalizedUpload::new(now + 10));
+        store.record_finalized(&live, FinalizedUpload::new(now + 100));
+
+        // Fake clock: before either expiry nothing is touched.
+        assert_eq!(store.reap_expired_uploads(now).await, 0);
+        assert!(dir.join(&expired).exists());
+
+        // Jump past the first expiry only.
+        assert_eq!(store.reap_expired_uploads(now + 50).await, 1);
+        assert!(!dir.join(&expired).exists());
+        assert!(dir.join(&live).exists());
+        assert!(store.finalized(&expired).is_none());
+        assert!(store.finalized(&live).is_some());
+        assert!(metrics
+            .render()
+            .contains("cryptify_retention_deleted_files_total 1"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// An upload the reaper may already be deleting cannot be extended, so
+    /// an extension never outlives the payload it extends.
+    #[rocket::async_test]
+    async fn expired_uploads_cannot_be_extended() {
+        let dir = temp_data_dir();
+        let store = retention_store(Arc::new(Metrics::new()), None, &dir);
+        let now = now_secs();
+        let id = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(&id), b"old").unwrap();
+        store.record_finalized(&id, FinalizedUpload::new(now - 1));
+
+        assert!(store.extend(&id, now + 3600).is_none());
+        assert_eq!(store.reap_expired_uploads(now).await, 1);
+        assert!(!dir.join(&id).exists());
+        assert!(store.finalized(&id).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[rocket::async_test]
+    async fn reaper_forgets_record_when_payload_already_gone() {
+        let dir = temp_data_dir();
+        let store = retention_store(Arc::new(Metrics::new()), None, &dir);
+        let id = uuid::Uuid::new_v4().to_string();
+        store.record_finalized(&id, FinalizedUpload::new(10));
+
+        assert_eq!(store.reap_expired_uploads(20).await, 1);
+        assert!(store.finalized(&id).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[rocket::async_test]
+    async fn retention_records_survive_restart() {
+        let db = TempDbPath::new();
+        let dir = temp_data_dir();
+        let now = now_secs();
+        let id = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(&id), b"payload").unwrap();
+
+        {
+            let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+            store.record_finalized(&id, FinalizedUpload::new(now + 60));
+        }
+
+        // The reloaded record drives the reaper after a restart.
+        let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+        assert_eq!(store.finalized(&id).map(|u| u.expires), Some(now + 60));
+        assert_eq!(store.reap_expired_uploads(now).await, 0);
+        assert_eq!(store.reap_expired_uploads(now + 61).await, 1);
+        assert!(!dir.join(&id).exists());
+
+        // And the deletion is persisted too: nothing resurrects on reload.
+        drop(store);
+        let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+        assert!(store.finalized(&id).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[rocket::async_test]
+    async fn reaper_falls_back_to_mtime_for_untracked_files() {
+        let dir = temp_data_dir();
+        let store = retention_store(Arc::new(Metrics::new()), None, &dir);
+        let now = now_secs();
+
+        let untracked = uuid::Uuid::new_v4().to_string();
+        let in_flight = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(&untracked), b"legacy").unwrap();
+        std::fs::write(dir.join(&in_flight), b"partial").unwrap();
+        std::fs::write(dir.join("not-an-upload"), b"keep").unwrap();
+        store.create(in_flight.clone(), dummy_filestate());
+
+        // Freshly written: well within a lifetime of its mtime.
+        assert_eq!(store.reap_expired_uploads(now).await, 0);
+
+        let later = now + UPLOAD_LIFETIME_SECS + 60;
+        assert_eq!(store.reap_expired_uploads(later).await, 1);
+        assert!(!dir.join(&untracked).exists());
+        assert!(
+            dir.join(&in_flight).exists(),
+            "a live session's file must never be reaped"
+        );
+        assert!(
+            dir.join("not-an-upload").exists(),
+            "only UUID-named payloads are candidates"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[rocket::async_test]
+    async fn reaper_is_noop_without_data_dir() {
+        let store = Store::new(Arc::new(Metrics::new()));
+        store.record_finalized("u1", FinalizedUpload::new(0));
+        assert_eq!(store.reap_expired_uploads(i64::MAX).await, 0);
+        assert!(store.finalized("u1").is_some());
+    }
+
+    // ----- orphaned partial uploads -----
+
+    #[rocket::async_test]
+    async fn eviction_removes_partial_payload_of_unfinalized_session() {
+        let dir = temp_data_dir();
+        let metrics = Arc::new(Metrics::new());
+        let store = Store::with_idle_ttl(
+            Duration::from_millis(20),
+            metrics.clone(),
+            None,
+            Some(Arc::new(LocalStorage::new(&dir))),
+        );
+
+        let partial = uuid::Uuid::new_v4().to_string();
+        let finalized = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(&partial), b"half").unwrap();
+        std::fs::write(dir.join(&finalized), b"done").unwrap();
+        store.create(partial.clone(), dummy_filestate());
+        let mut done = dummy_filestate();
+        done.finalized = true;
+        store.create(finalized.clone(), done);
+
+        rocket::tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert!(store.get(&partial).is_none(), "idle session evicted");
+        assert!(
+            !dir.join(&partial).exists(),
+            "partial payload must be deleted with its session"
+        );
+        assert!(
+            dir.join(&finalized).exists(),
+            "a finalized upload outlives its session"
+        );
+        assert!(metrics.render().contains("cryptify_expired_files_total 1"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[rocket::async_test]
+    async fn startup_sweep_removes_untracked_partial_files() {
+        let db = TempDbPath::new();
+        let dir = temp_data_dir();
+        let finalized = uuid::Uuid::new_v4().to_string();
+        let partial = uuid::Uuid::new_v4().to_string();
+
+        {
+            let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+            std::fs::write(dir.join(&finalized), b"done").unwrap();
+            store.record_finalized(&finalized, FinalizedUpload::new(now_secs() + 60));
+            // Crash mid-upload: the session dies with the process.
+            std::fs::write(dir.join(&partial), b"half").unwrap();
+        }
+
+        let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+        store.sweep_partial_uploads().await;
+        assert!(!dir.join(&partial).exists(), "crash leftover swept");
+        assert!(dir.join(&finalized).exists(), "finalized upload kept");
+        assert!(
+            dir.join(format!("{}.finalized", finalized)).exists(),
+            "an upload finalized before markers existed gets one"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// A revocation outlives a restart, and a payload its delete missed is
+    /// removed by the next startup sweep instead of being re-marked.
+    #[rocket::async_test]
+    async fn revoked_uploads_stay_revoked_across_restart() {
+        let db = TempDbPath::new();
+        let dir = temp_data_dir();
+        let id = uuid::Uuid::new_v4().to_string();
+
+        {
+            let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+            std::fs::write(dir.join(&id), b"done").unwrap();
+            store.record_finalized(&id, FinalizedUpload::new(now_secs() + 60));
+            assert!(!store.revoke(&id).unwrap().revoked);
+            assert!(store.revoke("unknown").is_none());
+        }
+
+        let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+        assert!(store.finalized(&id).unwrap().revoked);
+        store.sweep_partial_uploads().await;
+        assert!(!dir.join(&id).exists(), "revoked payload swept");
+        assert!(!dir.join(format!("{}.finalized", id)).exists());
+        assert!(
+            store.finalized(&id).is_some(),
+            "the record stays until expiry so downloads keep answering 410"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// The sender index follows the records: rebuilt on restart and
+    /// pruned by the reaper.
+    #[rocket::async_test]
+    async fn finalized_uploads_are_indexed_by_sender() {
+        let db = TempDbPath::new();
+        let dir = temp_data_dir();
+        let upload = |sender: &str, expires: i64| {
+            let mut state = dummy_filestate();
+            state.sender = Some(sender.to_owned());
+            FinalizedUpload {
+                mailing: Some(Mailing::of(&state)),
+                ..FinalizedUpload::new(expires)
+            }
+        };
+        let (soon, later, other) = ("a".repeat(32), "b".repeat(32), "c".repeat(32));
+
+        {
+            let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+            store.record_finalized(&soon, upload("Bob@Example.com", now_secs() + 60));
+            store.record_finalized(&later, upload("bob@example.com", now_secs() + 600));
+            store.record_finalized(&other, upload("alice@example.com", now_secs() + 60));
+            store.record_finalized("untracked", FinalizedUpload::new(now_secs() + 60));
+        }
+
+        let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+        let ids = |sender: &str| -> Vec<String> {
+            store
+                .uploads_of(sender)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect()
+        };
+        assert_eq!(ids("BOB@example.com"), vec![soon.clone(), later.clone()]);
+        assert_eq!(ids("alice@example.com"), vec![other]);
+        assert!(ids("mallory@example.com").is_empty());
+
+        store.reap_expired_uploads(now_secs() + 120).await;
+        assert_eq!(ids("bob@example.com"), vec![later]);
+        assert!(ids("alice@example.com").is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[rocket::async_test]
+    async fn startup_sweep_never_deletes_finalized_payloads() {
+        let db = TempDbPath::new();
+        let dir = temp_data_dir();
+        let id = uuid::Uuid::new_v4().to_string();
+
+        // Creates the retention table, so the next start sweeps.
+        drop(retention_store(
+            Arc::new(Metrics::new()),
+            Some(db.as_str()),
+            &dir,
+        ));
+        std::fs::write(dir.join(&id), b"done").unwrap();
+        std::fs::write(dir.join(format!("{}.finalized", id)), b"").unwrap();
+
+        let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+        store.sweep_partial_uploads().await;
+        assert!(
+            dir.join(&id).exists(),
+            "finalized payload without a record kept"
+        );
+        assert!(dir.join(format!("{}.finalized", id)).exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[rocket::async_test]
+    async fn startup_sweep_adopts_files_when_retention_table_is_new() {
+        let db = TempDbPath::new();
+        let dir = temp_data_dir();
+        let existing = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(&existing), b"from an older release").unwrap();
+
+        let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+        store.sweep_partial_uploads().await;
+        assert!(dir.join(&existing).exists(), "pre-existing file not swept");
+        assert!(dir.join(format!("{}.finalized", existing)).exists());
+        let expires = store.finalized(&existing).expect("adopted").expires;
+        assert!((expires - (now_secs() + UPLOAD_LIFETIME_SECS)).abs() < 60);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// A fresh database cannot vouch for a payload still being written:
+    /// it is deleted, not adopted and made downloadable.
+    #[rocket::async_test]
+    async fn startup_sweep_never_adopts_files_still_being_written() {
+        let db = TempDbPath::new();
+        let dir = temp_data_dir();
+        let partial = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(format!("{}.part", partial)), b"trunc").unwrap();
+
+        let store = retention_store(Arc::new(Metrics::new()), Some(db.as_str()), &dir);
+        store.sweep_partial_uploads().await;
+        assert!(store.finalized(&partial).is_none(), "not adopted");
+        assert!(!dir.join(format!("{}.part", partial)).exists(), "swept");
+        assert!(!dir.join(&partial).exists(), "not completed");
+        assert!(!dir.join(format!("{}.finalized", partial)).exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[rocket::async_test]
+    async fn startup_sweep_skipped_without_database() {
+        let dir = temp_data_dir();
+        let id = uuid::Uuid::new_v4().to_string();
+        std::fs::write(dir.join(&id), b"unknown").unwrap();
+
+        let store = retention_store(Arc::new(Metrics::new()), None, &dir);
+        store.sweep_partial_uploads().await;
+        assert!(dir.join(&id).exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // ----- persisted upload sessions -----
+
+    #[rocket::async_test]
+    async fn upload_sessions_survive_restart() {
+        let db = TempDbPath::new();
+
+        {
+            let store = store_with_db(db.as_str());
+            let mut session = dummy_filestate();
+            session.recipients = "alice@example.com, bob@example.com".parse().unwrap();
+            session.recovery_token = "recover-me".to_owned();
+            session.api_key_tenant = Some("tenant-1".to_owned());
+            session.reservation = Some(Reservation {
+                key: "api-key:tenant-1".to_owned(),
+                bytes: 900,
+            });
+            session.expires = 1_700_000_000;
+            store.create("u1".into(), session);
+
+            // A chunk lands after init: the mutation is written through.
+            let entry = store.get("u1").unwrap();
+            let mut session = entry.lock().await;
+            session.uploaded = 42;
+            session.cryptify_token = "tok-after".to_owned();
+            session.last_chunk = Some(LastChunkRecord {
+                prev_token: "tok-before".to_owned(),
+                prev_uploaded: 0,
+                response_token: "tok-after".to_owned(),
+            });
+            store.save("u1", &session);
+        }
+
+        let store = store_with_db(db.as_str());
+        let entry = store.get("u1").expect("session reloaded after restart");
+        let session = entry.lock().await;
+        assert_eq!(session.uploaded, 42);
+        assert_eq!(session.cryptify_token, "tok-after");
+        assert_eq!(session.recovery_token, "recover-me");
+        assert_eq!(session.api_key_tenant.as_deref(), Some("tenant-1"));
+        assert_eq!(session.expires, 1_700_000_000);
+        assert_eq!(session.recipients.iter().count(), 2);
+        let last = session.last_chunk.as_ref().expect("last_chunk reloaded");
+        assert_eq!(last.prev_token, "tok-before");
+        assert_eq!(last.response_token, "tok-after");
+        drop(session);
+        assert!(
+            store
+                .reserve("u2", reservation("api-key:tenant-1", 101), 1_000, 0)
+                .is_err(),
+            "the reloaded session still holds its reservation"
+        );
+        assert!(
+            store.deadline_for("u1").is_some(),
+            "reloaded session is scheduled for idle eviction"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn removed_and_evicted_sessions_are_not_reloaded() {
+        let db = TempDbPath::new();
+
+        {
+            let store = Store::with_idle_ttl(
+                Duration::from_millis(20),
+                Arc::new(Metrics::new()),
+                Some(db.as_str()),
+                None,
+            );
+            store.create("removed".into(), dummy_filestate());
+            store.create("evicted".into(), dummy_filestate());
+            store.remove("removed");
+            rocket::tokio::time::sleep(Duration::from_millis(200)).await;
+            assert!(store.get("evicted").is_none());
+        }
+
+        let store = store_with_db(db.as_str());
+        assert!(store.get("removed").is_none());
+        assert!(store.get("evicted").is_none());
+    }
 }
@@ -0,0 +1,180 @@
+//! Yivi disclosure sessions, run against an IRMA server with the `irma`
+//! crate. A session asks for the email attribute; the browser only gets the
+//! session pointer for its QR code and an id of our own, while the
+//! requestor token that reads the disclosed result stays on the server.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use irma::{
+    AttributeRequest, DisclosureRequestBuilder, IrmaClient, IrmaClientBuilder, ProofStatus, Qr,
+    SessionStatus, SessionToken,
+};
+use rocket::tokio::time::Instant;
+use serde::Deserialize;
+
+/// The `[irma]` table of the configuration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IrmaConfig {
+    /// Base URL of the IRMA server's requestor API, e.g.
+    /// `https://irma.example.com/`.
+    pub url: String,
+    /// Requestor token, for a server that requires token authentication.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// How long a session may take, counted from its start, and how long a
+/// disclosure is honoured once it is done.
+pub const SESSION_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// What a session was started for; its disclosure unlocks only that.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Purpose {
+    /// Downloading the upload with this UUID.
+    Download(String),
+    /// Signing in to the sender dashboard as the disclosed address.
+    Dashboard,
+}
+
+struct Session {
+    irma: SessionToken,
+    purpose: Purpose,
+    started: Instant,
+    /// The disclosed address, lowercased, once the session is done.
+    email: Option<String>,
+}
+
+impl Session {
+    fn is_live(&self) -> bool {
+        self.started.elapsed() < SESSION_LIFETIME
+    }
+}
+
+/// Where a session stands after [`Yivi::poll`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Still waiting for the user; the status as the IRMA server reports it.
+    Pending(SessionStatus),
+    /// Ended without a valid disclosure of the email attribute. The session
+    /// is gone; the user has to start a new one.
+    Failed(SessionStatus),
+    /// The user disclosed this address, lowercased.
+    Disclosed(String),
+}
+
+pub struct Yivi {
+    client: IrmaClient,
+    /// The attribute type requested, as `CryptifyConfig::email_attribute`.
+    email_attribute: String,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Yivi {
+    pub fn new(config: &IrmaConfig, email_attribute: &str) -> Result<Self, irma::Error> {
+        // The client joins `session/...` onto the URL, which keeps its last
+        // path segment only with a trailing slash.
+        let mut url = config.url.clone();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let mut builder = IrmaClientBuilder::new(&url)?;
+        if let Some(token) = &config.token {
+            builder = builder.token_authentication(token.clone());
+        }
+        Ok(Yivi {
+            client: builder.build(),
+            email_attribute: email_attribute.to_owned(),
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Start a session disclosing the email attribute for `purpose`.
+    /// Returns our id for it and the pointer the browser shows as a QR code.
+    pub async fn start(&self, purpose: Purpose) -> Result<(String, Qr), irma::Error> {
+        let request = DisclosureRequestBuilder::new()
+            .add_discon(vec![vec![AttributeRequest::non_null(
+                self.email_attribute.clone(),
+            )]])
+            .build();
+        let session = self.client.request(&request).await?;
+
+        let id = crate::bytes_to_hex(&rand::random::<[u8; 32]>());
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.is_live());
+        sessions.insert(
+            id.clone(),
+            Session {
+                irma: session.token,
+                purpose,
+                started: Instant::now(),
+                email: None,
+            },
+        );
+        Ok((id, session.session_ptr))
+    }
+
+    /// Ask the IRMA server how session `id` is doing. `None` when there is
+    /// no such live session for `purpose`.
+    pub async fn poll(&self, id: &str, purpose: &Purpose) -> Result<Option<Outcome>, irma::Error> {
+        let token = {
+            let sessions = self.sessions.lock().unwrap();
+            match sessions.get(id) {
+                Some(session) if session.is_live() && &session.purpose == purpose => {
+                    if let Some(email) = &session.email {
+                        return Ok(Some(Outcome::Disclosed(email.clone())));
+                    }
+                    session.irma.clone()
+                }
+                _ => return Ok(None),
+            }
+        };
+
+        let status = self.client.status(&token).await?;
+        let outcome = match status {
+            SessionStatus::Done => {
+                let result = self.client.result(&token).await?;
+                let email = result
+                    .disclosed
+                    .iter()
+                    .flatten()
+                    .find(|attr| attr.identifier == self.email_attribute)
+                    .and_then(|attr| attr.raw_value.as_deref())
+                    .map(str::to_lowercase);
+                match email {
+                    Some(email) if result.proof_status == Some(ProofStatus::Valid) => {
+                        Outcome::Disclosed(email)
+                    }
+                    _ => Outcome::Failed(SessionStatus::Done),
+                }
+            }
+            SessionStatus::Cancelled | SessionStatus::Timeout => Outcome::Failed(status),
+            status => Outcome::Pending(status),
+        };
+
+        let mut sessions = self.sessions.lock().unwrap();
+        match &outcome {
+            Outcome::Disclosed(email) => {
+                if let Some(session) = sessions.get_mut(id) {
+                    session.email = Some(email.clone());
+                }
+            }
+            Outcome::Failed(_) => {
+                sessions.remove(id);
+            }
+            Outcome::Pending(_) => {}
+        }
+        Ok(Some(outcome))
+    }
+
+    /// The address disclosed in session `id` for `purpose`, if it is done
+    /// and still live. Does not contact the IRMA server.
+    pub fn disclosed(&self, id: &str, purpose: &Purpose) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(id)
+            .filter(|session| session.is_live() && &session.purpose == purpose)
+            .and_then(|session| session.email.clone())
+    }
+}